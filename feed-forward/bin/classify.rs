@@ -0,0 +1,110 @@
+use feed_forward::datasets::Dataset;
+use feed_forward::image_data::grayscale;
+use feed_forward::image_file::load_image;
+use feed_forward::network::Network;
+use feed_forward::preprocess::{mnist_style, Invert, MNIST_SIZE};
+use feed_forward::render::{render_image, RenderMode};
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+const USAGE: &str = "Classify image files with a saved network.
+
+Usage: classify --model PATH [options] IMAGE...
+
+The images can be PNG, PGM or PPM files. Each one is converted to grayscale, inverted
+if it is dark on light, and scaled and centered the same way as the MNIST digits.
+
+Options:
+  --model PATH           The saved network, from train --save
+  --top K                How many of the most likely labels to show (default 3)
+  --dataset NAME         The dataset that the network was trained on, which names the
+                         labels (default mnist)
+  --invert MODE          auto, always or never (default auto)
+  --show                 Draw each image after it has been prepared";
+
+/// Classify your own handwriting. Draw a digit, save it as a PNG, and run:
+///
+/// cargo run --bin classify -- --model ./mnist.network --show ./digit.png
+fn main() {
+    let mut model_path: Option<PathBuf> = None;
+    let mut top = 3;
+    let mut dataset = Dataset::Mnist;
+    let mut invert = Invert::Auto;
+    let mut show = false;
+    let mut image_paths: Vec<PathBuf> = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| fail(&format!("Missing a value for {}", arg)))
+        };
+        match arg.as_str() {
+            "--model" => model_path = Some(PathBuf::from(value())),
+            "--top" => {
+                top = value()
+                    .parse()
+                    .unwrap_or_else(|_| fail("--top must be a number"))
+            }
+            "--dataset" => {
+                let name = value();
+                dataset = Dataset::from_name(&name)
+                    .unwrap_or_else(|| fail(&format!("Unknown dataset {:?}", name)))
+            }
+            "--invert" => {
+                let name = value();
+                invert = Invert::from_name(&name)
+                    .unwrap_or_else(|| fail(&format!("Unknown invert mode {:?}", name)))
+            }
+            "--show" => show = true,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if arg.starts_with("--") => fail(&format!("Unknown argument {}", arg)),
+            _ => image_paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let model_path = model_path.unwrap_or_else(|| fail("--model is required"));
+    if image_paths.is_empty() {
+        fail("There are no images to classify.");
+    }
+    let network = Network::load(&model_path)
+        .unwrap_or_else(|err| fail(&format!("Failed to load the network: {}", err)));
+    if network.images.dimensions != (MNIST_SIZE, MNIST_SIZE) || network.images.channels != 1 {
+        fail("The network must be trained on 28x28 grayscale images, like MNIST.");
+    }
+    let label_names = dataset.label_names();
+
+    for path in image_paths {
+        let (dimensions, channels, pixels) = match load_image(&path) {
+            Ok(image) => image,
+            Err(err) => {
+                eprintln!("Failed to load {:?}: {}", path, err);
+                continue;
+            }
+        };
+        let image = mnist_style(&grayscale(&pixels, channels), dimensions, invert);
+        println!("{}", path.display());
+        if show {
+            print!(
+                "{}",
+                render_image(&image, (MNIST_SIZE, MNIST_SIZE), 1, RenderMode::Shaded)
+            );
+        }
+        for prediction in network.predict_top_k(&image, top) {
+            let name = label_names
+                .get(prediction.label)
+                .cloned()
+                .unwrap_or_else(|| prediction.label.to_string());
+            println!("  {:>10}  {:5.1}%", name, prediction.probability * 100.0);
+        }
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(1);
+}
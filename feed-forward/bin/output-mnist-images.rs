@@ -1,4 +1,34 @@
+use feed_forward::datasets::Dataset;
 use feed_forward::image_data::*;
+use feed_forward::network::{Network, Prediction};
+use feed_forward::render::RenderMode;
+use std::env;
+use std::fs;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::process;
+
+const USAGE: &str = "Show a sample of the MNIST images, or export them as image files.
+
+Usage: output-mnist-images [options]
+
+Options:
+  --dataset NAME         mnist, fashion-mnist, kmnist, emnist-SPLIT or cifar-10
+                         (default mnist)
+  --data DIR             The directory of the dataset files (default $MNIST_DATA_DIR
+                         or ./data/mnist for MNIST, otherwise ./data/DATASET)
+  --test                 Use the test images instead of the training images
+  --range START..END     Which images to output (default 0..100)
+  --render MODE          How to draw the images in the terminal: threshold, ramp,
+                         half-blocks or shaded (default threshold)
+  --grid                 Draw the images side by side in the terminal
+  --model PATH           Caption the grid with the predictions of a saved network,
+                         with misclassified images in red
+  --out DIR              Write each image to a file in the directory
+  --format FORMAT        The file format for --out: png, pgm, or ppm (default png)
+  --contact-sheet PATH   Write the images as a single labeled grid, e.g. sheet.png
+  --columns N            Images per row in the contact sheet (default 10) or in the
+                         grid (default 4)";
 
 /// This example shows off a sample of the training data from the terminal.
 /// For example:
@@ -33,10 +63,149 @@ use feed_forward::image_data::*;
 /// ............................
 ///
 /// ^ This image is labeled "3"
-
+///
+/// The images can also be written out to files to see the real grayscale data:
+///
+/// cargo run --bin output-mnist-images -- --range 0..5 --render shaded
+/// cargo run --bin output-mnist-images -- --test --range 0..8 --model ./mnist.network
+/// cargo run --bin output-mnist-images -- --range 0..20 --out ./data/images
+/// cargo run --bin output-mnist-images -- --contact-sheet ./data/sheet.png
 fn main() {
-    let images = load_in_training_images().unwrap();
-    for i in 0..100 {
-        output_image(&images, i);
+    let mut use_test_images = false;
+    let mut range: Range<usize> = 0..100;
+    let mut out_directory: Option<PathBuf> = None;
+    let mut format = "png".to_string();
+    let mut contact_sheet_path: Option<PathBuf> = None;
+    let mut columns: Option<usize> = None;
+    let mut dataset = Dataset::Mnist;
+    let mut data_directory: Option<PathBuf> = None;
+    let mut render_mode = RenderMode::Threshold(50);
+    let mut grid = false;
+    let mut model_path: Option<PathBuf> = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| fail(&format!("Missing a value for {}", arg)))
+        };
+        match arg.as_str() {
+            "--dataset" => {
+                let name = value();
+                dataset = Dataset::from_name(&name)
+                    .unwrap_or_else(|| fail(&format!("Unknown dataset {:?}", name)))
+            }
+            "--data" => data_directory = Some(PathBuf::from(value())),
+            "--test" => use_test_images = true,
+            "--grid" => grid = true,
+            "--model" => model_path = Some(PathBuf::from(value())),
+            "--range" => range = parse_range(&value()),
+            "--render" => render_mode = value().parse().unwrap_or_else(|err: String| fail(&err)),
+            "--out" => out_directory = Some(PathBuf::from(value())),
+            "--format" => {
+                format = value();
+                if !["png", "pgm", "ppm"].contains(&format.as_str()) {
+                    fail(&format!("Unknown format {:?}", format));
+                }
+            }
+            "--contact-sheet" => contact_sheet_path = Some(PathBuf::from(value())),
+            "--columns" => {
+                columns = Some(
+                    value()
+                        .parse()
+                        .unwrap_or_else(|_| fail("--columns must be a number")),
+                )
+            }
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => fail(&format!("Unknown argument {}", arg)),
+        }
+    }
+
+    let data_directory = data_directory.unwrap_or_else(|| dataset.default_root());
+    let split = if use_test_images {
+        Split::Test
+    } else {
+        Split::Training
+    };
+    let images = dataset
+        .load(&data_directory, split)
+        .unwrap_or_else(|err| fail(&format!("Failed to load the images: {}", err)));
+    let range = range.start.min(images.len())..range.end.min(images.len());
+
+    if grid || model_path.is_some() {
+        let indexes: Vec<usize> = range.collect();
+        let predictions = model_path.map(|path| predict(&path, &images, &indexes));
+        print!(
+            "{}",
+            output_image_grid(
+                &images,
+                &indexes,
+                predictions.as_deref(),
+                columns.unwrap_or(4),
+                render_mode
+            )
+        );
+        return;
+    }
+
+    if out_directory.is_none() && contact_sheet_path.is_none() {
+        for i in range {
+            println!("{}", output_image(&images, i, render_mode));
+        }
+        return;
     }
+
+    if let Some(directory) = out_directory {
+        fs::create_dir_all(&directory)
+            .unwrap_or_else(|err| fail(&format!("Failed to create {:?}: {}", directory, err)));
+        for i in range.clone() {
+            let path = directory.join(format!("{:05}-label-{}.{}", i, images.labels[i], format));
+            save_image(&path, images.dimensions, images.image(i))
+                .unwrap_or_else(|err| fail(&format!("Failed to write {:?}: {}", path, err)));
+        }
+        println!("Wrote {} images to {:?}", range.len(), directory);
+    }
+
+    if let Some(path) = contact_sheet_path {
+        let indexes: Vec<usize> = range.collect();
+        let (dimensions, pixels) = contact_sheet(&images, &indexes, columns.unwrap_or(10));
+        save_image(&path, dimensions, &pixels)
+            .unwrap_or_else(|err| fail(&format!("Failed to write the contact sheet: {}", err)));
+        println!(
+            "Wrote a contact sheet of {} images to {:?}",
+            indexes.len(),
+            path
+        );
+    }
+}
+
+/// Run a saved network on the images, returning its prediction for each one.
+fn predict(path: &PathBuf, images: &Images, indexes: &[usize]) -> Vec<Prediction> {
+    let network = Network::load(path)
+        .unwrap_or_else(|err| fail(&format!("Failed to load the network: {}", err)));
+    if network.images.dimensions != images.dimensions || network.images.channels != images.channels
+    {
+        fail("The network was trained on images of a different size.");
+    }
+    network.predict_images(&images.subset(indexes))
+}
+
+/// Parse a range like "10..20".
+fn parse_range(value: &str) -> Range<usize> {
+    let parts: Vec<&str> = value.split("..").collect();
+    match parts.as_slice() {
+        [start, end] => match (start.parse(), end.parse()) {
+            (Ok(start), Ok(end)) if start <= end => start..end,
+            _ => fail("--range must look like START..END"),
+        },
+        _ => fail("--range must look like START..END"),
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(1);
 }
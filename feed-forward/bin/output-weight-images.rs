@@ -0,0 +1,60 @@
+use feed_forward::network::Network;
+use feed_forward::visualize::*;
+use std::env;
+use std::process;
+
+const USAGE: &str = "Render the first layer's weights of a saved network as images.
+
+Usage: output-weight-images <network> [options]
+
+Options:
+  --columns N    Images per row in the terminal (default 4)
+  --out DIR      Also write a PGM file for each node into the directory";
+
+/// Shows what features the first hidden layer has learned. For example:
+///
+/// cargo run --bin output-weight-images -- ./mnist.network --out ./data/weights
+fn main() {
+    let mut network_path = None;
+    let mut columns = 4;
+    let mut out_directory = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| fail(&format!("Missing a value for {}", arg)))
+        };
+        match arg.as_str() {
+            "--columns" => {
+                columns = value()
+                    .parse()
+                    .unwrap_or_else(|_| fail("--columns must be a number"))
+            }
+            "--out" => out_directory = Some(value()),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ if network_path.is_none() && !arg.starts_with("--") => network_path = Some(arg),
+            _ => fail(&format!("Unknown argument {}", arg)),
+        }
+    }
+
+    let network_path = network_path.unwrap_or_else(|| fail("The network is required."));
+    let network = Network::load(&network_path)
+        .unwrap_or_else(|err| fail(&format!("Failed to load the network: {}", err)));
+
+    print!("{}", render_weight_grid(&network, columns));
+
+    if let Some(directory) = out_directory {
+        let paths = write_weight_images(&network, &directory)
+            .unwrap_or_else(|err| fail(&format!("Failed to write the images: {}", err)));
+        println!("Wrote {} images to {}", paths.len(), directory);
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(1);
+}
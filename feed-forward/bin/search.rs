@@ -0,0 +1,230 @@
+use feed_forward::augment::Augmentation;
+use feed_forward::datasets::Dataset;
+use feed_forward::image_data::*;
+use feed_forward::search::{format_table, sort_trials, Search, SearchSpace, SortBy};
+use feed_forward::tabular::{Column, CsvOptions, MissingValues};
+use feed_forward::train::Optimizer;
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+
+const USAGE: &str = "Search for the hyperparameters that train the best network.
+
+Usage: search [options]
+
+Each trial trains a fresh network on the training images, minus a held out validation
+split that every trial is scored on. Lists are separated by commas.
+
+Options:
+  --dataset NAME            mnist, fashion-mnist, kmnist, cifar-10 or emnist-SPLIT
+                            (default mnist)
+  --data DIR                The directory of the dataset files
+  --csv PATH                Search on the rows of a CSV file instead of a dataset
+  --target COLUMN           The name or index of the CSV column to predict (default 0)
+  --missing POLICY          What to do with missing CSV values (default error)
+  --limit N                 Only use the first N images
+  --learning-rates LIST     (default 0.1,0.5,1)
+  --layers LIST             Hidden layers as COUNTxNODES (default 1x16,2x16)
+  --batch-sizes LIST        (default 16,32)
+  --optimizers LIST         sgd or momentum:F (default sgd,momentum:0.9)
+  --weight-decays LIST      The strengths of the L2 regularization (default 0,0.0001)
+  --random N                Try N random points instead of every combination. The
+                            learning rate is drawn from between the smallest and
+                            largest of the learning rates on a log scale.
+  --epochs N                Epochs to train each trial (default 3)
+  --validation-fraction F   The fraction of images to score the trials on (default 0.2)
+  --augment                 Randomly distort the training images as they are used
+  --normalize NAME          none, min-max, per-pixel or global (default none)
+  --seed N                  Seed for the split, the shuffling and --random (default 0)
+  --threads N               The most trials to train at once (default: one per core)
+  --sort COLUMN             accuracy, loss, trial, learning-rate, batch-size or time
+                            (default accuracy)
+  --out PATH                Write every trial to a JSON Lines file as it finishes";
+
+/// Search for good hyperparameters. For example:
+///
+/// cargo run --release --bin search -- --limit 10000 --random 20 --out ./search.jsonl
+fn main() {
+    let mut space = SearchSpace::default();
+    let mut search = Search::default();
+    search.training.epochs = 3;
+    search.threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    let mut random_count: Option<usize> = None;
+    let mut sort_by = SortBy::Accuracy;
+    let mut out_path: Option<PathBuf> = None;
+    let mut limit: Option<usize> = None;
+    let mut dataset = Dataset::Mnist;
+    let mut data_directory: Option<PathBuf> = None;
+    let mut csv_path: Option<PathBuf> = None;
+    let mut csv_options = CsvOptions::default();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| fail(&format!("Missing a value for {}", arg)))
+        };
+        match arg.as_str() {
+            "--dataset" => {
+                let name = value();
+                dataset = Dataset::from_name(&name)
+                    .unwrap_or_else(|| fail(&format!("Unknown dataset {:?}", name)))
+            }
+            "--data" => data_directory = Some(PathBuf::from(value())),
+            "--csv" => csv_path = Some(PathBuf::from(value())),
+            "--target" => csv_options.target = Column::parse(&value()),
+            "--missing" => {
+                let name = value();
+                csv_options.missing = MissingValues::from_name(&name)
+                    .unwrap_or_else(|| fail(&format!("Unknown missing value policy {:?}", name)))
+            }
+            "--limit" => limit = Some(parse(&value())),
+            "--learning-rates" => space.learning_rates = parse_list(&value(), parse),
+            "--layers" => space.layer_sizes = parse_list(&value(), parse_layers),
+            "--batch-sizes" => {
+                space.batch_sizes = parse_list(&value(), parse);
+                if space.batch_sizes.contains(&0) {
+                    fail("--batch-sizes must all be at least 1");
+                }
+            }
+            "--optimizers" => {
+                space.optimizers = parse_list(&value(), |name| {
+                    Optimizer::from_name(name)
+                        .unwrap_or_else(|| fail(&format!("Unknown optimizer {:?}", name)))
+                })
+            }
+            "--weight-decays" => space.weight_decays = parse_list(&value(), parse),
+            "--random" => random_count = Some(parse(&value())),
+            "--epochs" => search.training.epochs = parse(&value()),
+            "--validation-fraction" => search.validation_fraction = parse(&value()),
+            "--augment" => search.training.augmentation = Some(Augmentation::mnist()),
+            "--normalize" => search.normalize = value(),
+            "--seed" => {
+                search.seed = parse(&value());
+                search.training.seed = search.seed;
+            }
+            "--threads" => search.threads = parse(&value()),
+            "--sort" => {
+                let name = value();
+                sort_by = SortBy::from_name(&name)
+                    .unwrap_or_else(|| fail(&format!("Unknown column {:?}", name)))
+            }
+            "--out" => out_path = Some(PathBuf::from(value())),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => fail(&format!("Unknown argument {}", arg)),
+        }
+    }
+    if !(search.validation_fraction > 0.0 && search.validation_fraction < 1.0) {
+        fail("--validation-fraction must be between 0 and 1");
+    }
+
+    let (images, class_count) = match csv_path {
+        Some(ref path) => {
+            let table = csv_options
+                .read(path)
+                .unwrap_or_else(|err| load_failed("the CSV file", err));
+            (table.images, table.encoding.class_names.len())
+        }
+        None => {
+            let data_directory = data_directory.unwrap_or_else(|| dataset.default_root());
+            let images = dataset
+                .load(&data_directory, Split::Training)
+                .unwrap_or_else(|err| load_failed("the training images", err));
+            (images, dataset.class_count())
+        }
+    };
+    let images = match limit {
+        Some(limit) => images.subset(&(0..limit.min(images.len())).collect::<Vec<_>>()),
+        None => images,
+    };
+    search.output_node_count = class_count;
+
+    let trials = match random_count {
+        Some(count) => space.random(count, search.seed),
+        None => space.grid(),
+    };
+    println!(
+        "Running {} trials on {} threads",
+        trials.len(),
+        search.threads.min(trials.len())
+    );
+
+    let mut out = out_path.as_ref().map(|path| {
+        BufWriter::new(
+            File::create(path)
+                .unwrap_or_else(|err| fail(&format!("Failed to create {:?}: {}", path, err))),
+        )
+    });
+    let mut finished = 0;
+    let mut results = search
+        .run(&trials, &images, |trial| {
+            finished += 1;
+            println!(
+                "Finished trial {} ({}/{}): accuracy {:.2}%",
+                trial.index,
+                finished,
+                trials.len(),
+                trial.evaluation.accuracy * 100.0
+            );
+            match out {
+                Some(ref mut out) => {
+                    writeln!(out, "{}", trial.to_json())?;
+                    out.flush()
+                }
+                None => Ok(()),
+            }
+        })
+        .unwrap_or_else(|err| fail(&format!("The search failed: {}", err)));
+
+    sort_trials(&mut results, sort_by);
+    println!("\n{}", format_table(&results));
+    if let Some(path) = out_path {
+        println!("Wrote the trials to {:?}", path);
+    }
+}
+
+/// Parse a comma separated list.
+fn parse_list<T>(value: &str, parse_item: impl Fn(&str) -> T) -> Vec<T> {
+    let list: Vec<T> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(parse_item)
+        .collect();
+    if list.is_empty() {
+        fail(&format!("The list {:?} is empty", value));
+    }
+    list
+}
+
+/// Parse the hidden layers as COUNTxNODES, e.g. "2x16".
+fn parse_layers(value: &str) -> (usize, usize) {
+    match value.split_once('x') {
+        Some((count, nodes)) => (parse(count), parse(nodes)),
+        None => fail(&format!(
+            "The layers {:?} must look like COUNTxNODES",
+            value
+        )),
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| fail(&format!("Could not parse the value {:?}", value)))
+}
+
+fn load_failed(what: &str, err: Error) -> ! {
+    fail(&format!("Failed to load {}: {}", what, err))
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(1);
+}
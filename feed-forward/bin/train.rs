@@ -0,0 +1,274 @@
+use feed_forward::augment::Augmentation;
+use feed_forward::cross_validation::CrossValidation;
+use feed_forward::datasets::Dataset;
+use feed_forward::image_data::*;
+use feed_forward::metrics::MetricsFormat;
+use feed_forward::network::Network;
+use feed_forward::normalize::Normalization;
+use feed_forward::tabular::{Column, CsvOptions, MissingValues};
+use feed_forward::train::{Optimizer, Trainer, TrainingConfig};
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+const USAGE: &str = "Train a network on the MNIST training images, or a drop-in replacement.
+
+Usage: train [options]
+
+Options:
+  --dataset NAME          mnist, fashion-mnist, kmnist, cifar-10, or emnist-SPLIT where
+                          SPLIT is byclass, bymerge, balanced, letters, digits or mnist
+                          (default mnist)
+  --data DIR              The directory of the dataset files (default $MNIST_DATA_DIR
+                          or ./data/mnist for MNIST, otherwise ./data/DATASET)
+  --mmap                  Memory map the training images instead of reading them in
+  --csv PATH              Train on the rows of a CSV file instead of a dataset
+  --target COLUMN         The name or index of the CSV column to predict (default 0)
+  --missing POLICY        What to do with missing CSV values: error, drop, mean, or
+                          fill:VALUE (default error)
+  --epochs N              Number of epochs to train (default 10)
+  --batch-size N          Images per mini-batch (default 32)
+  --learning-rate F       The learning rate (default 0.5)
+  --momentum F            Use gradient descent with momentum
+  --weight-decay F        The strength of the L2 regularization (default 0)
+  --hidden-layers N       Number of hidden layers (default 2)
+  --hidden-nodes N        Nodes per hidden layer (default 16)
+  --seed N                Seed for shuffling the images (default 0)
+  --checkpoint PATH       Write a checkpoint at the end of every epoch
+  --checkpoint-every N    Also write a checkpoint every N batches
+  --resume                Continue training from the checkpoint
+  --metrics PATH          Append metrics to a .csv or .jsonl file every epoch
+  --metrics-every N       Also append metrics every N batches
+  --validate              Compute the loss and accuracy on the test images
+  --folds K               Instead of training one network, report the mean and standard
+                          deviation of k-fold cross-validation on the training images
+  --dashboard             Show a live view of the training progress
+  --augment               Randomly distort the training images as they are used
+  --normalize NAME        How to scale the pixels: none, min-max, per-pixel or global
+                          (default none, which divides by 255)
+  --save PATH             Save the trained network";
+
+/// Train a network from the command line. For example:
+///
+/// cargo run --release --bin train -- --epochs 5 --checkpoint ./train.checkpoint
+fn main() {
+    let mut config = TrainingConfig::default();
+    let mut hidden_layer_count = 2;
+    let mut hidden_node_count = 16;
+    let mut resume = false;
+    let mut validate = false;
+    let mut save_path: Option<PathBuf> = None;
+    let mut normalize = String::from("none");
+    let mut dataset = Dataset::Mnist;
+    let mut data_directory: Option<PathBuf> = None;
+    let mut memory_map = false;
+    let mut folds: Option<usize> = None;
+    let mut csv_path: Option<PathBuf> = None;
+    let mut csv_options = CsvOptions::default();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next().unwrap_or_else(|| {
+                eprintln!("Missing a value for {}\n\n{}", arg, USAGE);
+                process::exit(1);
+            })
+        };
+        match arg.as_str() {
+            "--dataset" => {
+                let name = value();
+                dataset = Dataset::from_name(&name).unwrap_or_else(|| {
+                    eprintln!("Unknown dataset {:?}\n\n{}", name, USAGE);
+                    process::exit(1);
+                })
+            }
+            "--data" => data_directory = Some(PathBuf::from(value())),
+            "--mmap" => memory_map = true,
+            "--csv" => csv_path = Some(PathBuf::from(value())),
+            "--target" => csv_options.target = Column::parse(&value()),
+            "--missing" => {
+                let name = value();
+                csv_options.missing = MissingValues::from_name(&name).unwrap_or_else(|| {
+                    eprintln!("Unknown missing value policy {:?}\n\n{}", name, USAGE);
+                    process::exit(1);
+                })
+            }
+            "--epochs" => config.epochs = parse(&value()),
+            "--batch-size" => {
+                config.batch_size = parse(&value());
+                if config.batch_size == 0 {
+                    eprintln!("--batch-size must be at least 1\n\n{}", USAGE);
+                    process::exit(1);
+                }
+            }
+            "--learning-rate" => config.learning_rate = parse(&value()),
+            "--momentum" => config.optimizer = Optimizer::Momentum(parse(&value())),
+            "--weight-decay" => config.weight_decay = parse(&value()),
+            "--hidden-layers" => hidden_layer_count = parse(&value()),
+            "--hidden-nodes" => hidden_node_count = parse(&value()),
+            "--seed" => config.seed = parse(&value()),
+            "--checkpoint" => config.checkpoint_path = Some(PathBuf::from(value())),
+            "--checkpoint-every" => config.checkpoint_every = Some(parse(&value())),
+            "--resume" => resume = true,
+            "--metrics" => {
+                let path = PathBuf::from(value());
+                config.metrics_format = MetricsFormat::from_path(&path);
+                config.metrics_path = Some(path);
+            }
+            "--metrics-every" => config.metrics_every = Some(parse(&value())),
+            "--validate" => validate = true,
+            "--folds" => {
+                let count = parse(&value());
+                if count < 2 {
+                    eprintln!("--folds must be at least 2\n\n{}", USAGE);
+                    process::exit(1);
+                }
+                folds = Some(count);
+            }
+            "--dashboard" => config.dashboard = true,
+            "--augment" => config.augmentation = Some(Augmentation::mnist()),
+            "--normalize" => normalize = value(),
+            "--save" => save_path = Some(PathBuf::from(value())),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => {
+                eprintln!("Unknown argument {}\n\n{}", arg, USAGE);
+                process::exit(1);
+            }
+        }
+    }
+
+    let data_directory = data_directory.unwrap_or_else(|| dataset.default_root());
+    let (images, class_count) = match csv_path {
+        Some(ref path) => {
+            if validate {
+                eprintln!("--validate needs a dataset with test images, not a CSV file.");
+                process::exit(1);
+            }
+            let table = csv_options
+                .read(path)
+                .unwrap_or_else(|err| load_failed("the CSV file", err));
+            (table.images, table.encoding.class_names.len())
+        }
+        None => {
+            let images = if memory_map {
+                dataset.load_mapped(&data_directory, Split::Training)
+            } else {
+                dataset.load(&data_directory, Split::Training)
+            }
+            .unwrap_or_else(|err| load_failed("the training images", err));
+            (images, dataset.class_count())
+        }
+    };
+    let normalization = Normalization::fit(&normalize, &images).unwrap_or_else(|| {
+        eprintln!("Unknown normalization {:?}\n\n{}", normalize, USAGE);
+        process::exit(1);
+    });
+
+    if let Some(folds) = folds {
+        let cross_validation = CrossValidation {
+            folds,
+            hidden_layer_count,
+            hidden_node_count,
+            output_node_count: class_count,
+            seed: config.seed,
+            training: config,
+            normalize,
+        };
+        let report = cross_validation.run(&images).unwrap_or_else(|err| {
+            eprintln!("Failed to cross-validate: {}", err);
+            process::exit(1);
+        });
+        for (index, fold) in report.folds.iter().enumerate() {
+            println!(
+                "Fold {}: loss {:.5}, accuracy {:.2}%",
+                index,
+                fold.loss,
+                fold.accuracy * 100.0
+            );
+        }
+        println!(
+            "Mean loss {:.5} ± {:.5}, accuracy {:.2}% ± {:.2}%",
+            report.mean_loss(),
+            report.std_dev_loss(),
+            report.mean_accuracy() * 100.0,
+            report.std_dev_accuracy() * 100.0
+        );
+        return;
+    }
+
+    let mut network = Network::new(images, hidden_layer_count, hidden_node_count, class_count);
+    network.normalization = normalization;
+
+    let mut trainer = if resume {
+        let path = config.checkpoint_path.clone().unwrap_or_else(|| {
+            eprintln!("--resume requires a --checkpoint path.");
+            process::exit(1);
+        });
+        let trainer = Trainer::resume(config, &path, &mut network).unwrap_or_else(|err| {
+            eprintln!("Failed to resume from the checkpoint: {}", err);
+            process::exit(1);
+        });
+        println!(
+            "Resuming from epoch {}, batch {}",
+            trainer.epoch(),
+            trainer.batch()
+        );
+        trainer
+    } else {
+        Trainer::new(config)
+    };
+    if validate {
+        let validation = dataset
+            .load(&data_directory, Split::Test)
+            .and_then(|images| {
+                check_dimensions(&images, network.images.dimensions)?;
+                Ok(images)
+            })
+            .unwrap_or_else(|err| load_failed("the test images", err));
+        trainer.validation = Some(validation);
+    }
+
+    let show_dashboard = trainer.config.dashboard;
+    while let Some(metrics) = trainer.train_epoch(&mut network).unwrap_or_else(|err| {
+        eprintln!("Training failed: {}", err);
+        process::exit(1);
+    }) {
+        if show_dashboard {
+            // The dashboard already displays the results of each epoch.
+            continue;
+        }
+        print!("Epoch {}: loss {:.5}", metrics.epoch, metrics.train_loss);
+        if let (Some(loss), Some(accuracy)) = (metrics.validation_loss, metrics.validation_accuracy)
+        {
+            print!(
+                ", validation loss {:.5}, accuracy {:.2}%",
+                loss,
+                accuracy * 100.0
+            );
+        }
+        println!();
+    }
+
+    if let Some(path) = save_path {
+        if let Err(err) = network.save(&path) {
+            eprintln!("Failed to save the network to {:?}: {}", path, err);
+            process::exit(1);
+        }
+        println!("Saved the network to {:?}", path);
+    }
+}
+
+fn load_failed(what: &str, err: Error) -> ! {
+    eprintln!("Failed to load {}: {}", what, err);
+    process::exit(1);
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> T {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("Could not parse the value {:?}\n\n{}", value, USAGE);
+        process::exit(1);
+    })
+}
@@ -0,0 +1,449 @@
+//! Data augmentation creates new training examples by randomly distorting the
+//! existing ones. A digit that is shifted, rotated or slightly warped is still the
+//! same digit, so training on these variations helps the network generalize to
+//! handwriting it hasn't seen.
+//!
+//! Transforms are composed into an `Augmentation`, which is applied on the fly during
+//! training. All of the randomness comes from the trainer's `Random`, so augmented
+//! runs are still reproducible from a seed and a checkpoint.
+use crate::image_data::ImageData;
+use crate::random::Random;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read, Write};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transform {
+    /// Shift the image by up to `max` pixels horizontally and vertically.
+    Translate { max: f64 },
+    /// Rotate the image around its center by up to `max_degrees` in either direction.
+    Rotate { max_degrees: f64 },
+    /// Scale the image around its center by a factor between `min` and `max`.
+    Scale { min: f64, max: f64 },
+    /// Warp the image with a smoothed random displacement field. `alpha` controls the
+    /// strength of the displacement, and `sigma` how smooth it is.
+    ///
+    /// Simard, Steinkraus and Platt, "Best Practices for Convolutional Neural Networks
+    /// Applied to Visual Document Analysis", 2003.
+    Elastic { alpha: f64, sigma: f64 },
+    /// Add noise to every pixel, where the standard deviation is in pixel values
+    /// ranged 0-255.
+    GaussianNoise { std_dev: f64 },
+    /// With the given probability, blank out a random rectangle that covers up to
+    /// `max_fraction` of the image's area.
+    RandomErase { probability: f64, max_fraction: f64 },
+}
+
+/// A list of transforms that are applied in order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Augmentation {
+    pub transforms: Vec<Transform>,
+}
+
+impl Augmentation {
+    pub fn new() -> Augmentation {
+        Augmentation::default()
+    }
+
+    /// Add a transform to the end of the pipeline.
+    pub fn then(mut self, transform: Transform) -> Augmentation {
+        self.transforms.push(transform);
+        self
+    }
+
+    /// A set of gentle distortions that work well for MNIST digits.
+    pub fn mnist() -> Augmentation {
+        Augmentation::new()
+            .then(Transform::Rotate { max_degrees: 10.0 })
+            .then(Transform::Scale { min: 0.9, max: 1.1 })
+            .then(Transform::Elastic {
+                alpha: 20.0,
+                sigma: 4.0,
+            })
+            .then(Transform::Translate { max: 2.0 })
+    }
+
+    /// Apply every transform to a copy of the image. The dimensions are
+    /// (rows, columns).
+    pub fn apply(
+        &self,
+        image: &[u8],
+        dimensions: (usize, usize),
+        random: &mut Random,
+    ) -> ImageData {
+        let mut image = image.to_vec();
+        for transform in &self.transforms {
+            image = transform.apply(&image, dimensions, random);
+        }
+        image
+    }
+
+    /// Apply the transforms to an image with interleaved color channels. Every channel
+    /// is distorted in the same way, so the colors stay lined up.
+    pub fn apply_channels(
+        &self,
+        image: &[u8],
+        dimensions: (usize, usize),
+        channels: usize,
+        random: &mut Random,
+    ) -> ImageData {
+        if channels == 1 {
+            return self.apply(image, dimensions, random);
+        }
+        let start = random.clone();
+        let mut output = vec![0; image.len()];
+        for channel in 0..channels {
+            *random = start.clone();
+            let plane: Vec<u8> = image
+                .iter()
+                .skip(channel)
+                .step_by(channels)
+                .copied()
+                .collect();
+            let plane = self.apply(&plane, dimensions, random);
+            for (index, pixel) in plane.into_iter().enumerate() {
+                output[index * channels + channel] = pixel;
+            }
+        }
+        output
+    }
+
+    /// Write the transforms, so that a training checkpoint can record them. Each one
+    /// is a tag followed by two parameters, where unused parameters are 0.
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_u64::<BigEndian>(self.transforms.len() as u64)?;
+        for transform in &self.transforms {
+            let (tag, a, b) = match *transform {
+                Transform::Translate { max } => (0, max, 0.0),
+                Transform::Rotate { max_degrees } => (1, max_degrees, 0.0),
+                Transform::Scale { min, max } => (2, min, max),
+                Transform::Elastic { alpha, sigma } => (3, alpha, sigma),
+                Transform::GaussianNoise { std_dev } => (4, std_dev, 0.0),
+                Transform::RandomErase {
+                    probability,
+                    max_fraction,
+                } => (5, probability, max_fraction),
+            };
+            writer.write_u8(tag)?;
+            writer.write_f64::<BigEndian>(a)?;
+            writer.write_f64::<BigEndian>(b)?;
+        }
+        Ok(())
+    }
+
+    /// Read in the transforms that were written by `write_to`.
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Augmentation> {
+        let count = reader.read_u64::<BigEndian>()?;
+        let mut augmentation = Augmentation::new();
+        for _ in 0..count {
+            let tag = reader.read_u8()?;
+            let a = reader.read_f64::<BigEndian>()?;
+            let b = reader.read_f64::<BigEndian>()?;
+            augmentation = augmentation.then(match tag {
+                0 => Transform::Translate { max: a },
+                1 => Transform::Rotate { max_degrees: a },
+                2 => Transform::Scale { min: a, max: b },
+                3 => Transform::Elastic { alpha: a, sigma: b },
+                4 => Transform::GaussianNoise { std_dev: a },
+                5 => Transform::RandomErase {
+                    probability: a,
+                    max_fraction: b,
+                },
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "The saved augmentation has an unknown transform.",
+                    ))
+                }
+            });
+        }
+        Ok(augmentation)
+    }
+}
+
+impl Transform {
+    pub fn apply(
+        &self,
+        image: &[u8],
+        dimensions: (usize, usize),
+        random: &mut Random,
+    ) -> ImageData {
+        let (rows, cols) = dimensions;
+        assert_eq!(
+            image.len(),
+            rows * cols,
+            "The image does not match the dimensions."
+        );
+        match *self {
+            Transform::Translate { max } => {
+                let dx = random.range(-max, max);
+                let dy = random.range(-max, max);
+                resample(image, dimensions, |x, y| (x - dx, y - dy))
+            }
+            Transform::Rotate { max_degrees } => {
+                let angle = random.range(-max_degrees, max_degrees).to_radians();
+                let (sin, cos) = angle.sin_cos();
+                let (cx, cy) = center(dimensions);
+                // Map each output pixel back to where it came from in the input.
+                resample(image, dimensions, |x, y| {
+                    let (x, y) = (x - cx, y - cy);
+                    (cos * x + sin * y + cx, -sin * x + cos * y + cy)
+                })
+            }
+            Transform::Scale { min, max } => {
+                let factor = random.range(min, max);
+                let (cx, cy) = center(dimensions);
+                resample(image, dimensions, |x, y| {
+                    ((x - cx) / factor + cx, (y - cy) / factor + cy)
+                })
+            }
+            Transform::Elastic { alpha, sigma } => {
+                let mut dx: Vec<f64> = (0..image.len()).map(|_| random.range(-1.0, 1.0)).collect();
+                let mut dy: Vec<f64> = (0..image.len()).map(|_| random.range(-1.0, 1.0)).collect();
+                gaussian_blur(&mut dx, dimensions, sigma);
+                gaussian_blur(&mut dy, dimensions, sigma);
+                resample(image, dimensions, |x, y| {
+                    let index = y as usize * cols + x as usize;
+                    (x + alpha * dx[index], y + alpha * dy[index])
+                })
+            }
+            Transform::GaussianNoise { std_dev } => image
+                .iter()
+                .map(|pixel| {
+                    (*pixel as f64 + random.normal() * std_dev)
+                        .round()
+                        .clamp(0.0, 255.0) as u8
+                })
+                .collect(),
+            Transform::RandomErase {
+                probability,
+                max_fraction,
+            } => {
+                let mut image = image.to_vec();
+                if random.next_f64() >= probability || rows == 0 || cols == 0 {
+                    return image;
+                }
+                // Pick an area and an aspect ratio, then fit the rectangle in the image.
+                let area = random.range(0.0, max_fraction) * (rows * cols) as f64;
+                let aspect = random.range(0.5, 2.0);
+                let height = ((area * aspect).sqrt().round() as usize).clamp(1, rows);
+                let width = ((area / aspect).sqrt().round() as usize).clamp(1, cols);
+                let top = random.below(rows - height + 1);
+                let left = random.below(cols - width + 1);
+                for row in top..top + height {
+                    image[row * cols + left..row * cols + left + width].fill(0);
+                }
+                image
+            }
+        }
+    }
+}
+
+/// The center of the image in pixel coordinates.
+fn center(dimensions: (usize, usize)) -> (f64, f64) {
+    let (rows, cols) = dimensions;
+    ((cols as f64 - 1.0) / 2.0, (rows as f64 - 1.0) / 2.0)
+}
+
+/// Build a new image where each pixel (x, y) is sampled from the source position
+/// returned by `source`. Positions outside of the image are treated as black.
+fn resample(
+    image: &[u8],
+    dimensions: (usize, usize),
+    source: impl Fn(f64, f64) -> (f64, f64),
+) -> ImageData {
+    let (rows, cols) = dimensions;
+    let mut output = Vec::with_capacity(image.len());
+    for y in 0..rows {
+        for x in 0..cols {
+            let (sx, sy) = source(x as f64, y as f64);
+            output.push(
+                bilinear(image, dimensions, sx, sy)
+                    .round()
+                    .clamp(0.0, 255.0) as u8,
+            );
+        }
+    }
+    output
+}
+
+/// Interpolate between the four pixels that surround the position.
+pub(crate) fn bilinear(image: &[u8], dimensions: (usize, usize), x: f64, y: f64) -> f64 {
+    let (rows, cols) = dimensions;
+    let pixel = |x: f64, y: f64| -> f64 {
+        if x < 0.0 || y < 0.0 || x >= cols as f64 || y >= rows as f64 {
+            0.0
+        } else {
+            image[y as usize * cols + x as usize] as f64
+        }
+    };
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let top = pixel(x0, y0) * (1.0 - fx) + pixel(x0 + 1.0, y0) * fx;
+    let bottom = pixel(x0, y0 + 1.0) * (1.0 - fx) + pixel(x0 + 1.0, y0 + 1.0) * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+/// Blur the values in place with a separable Gaussian kernel. The edges are clamped.
+fn gaussian_blur(values: &mut [f64], dimensions: (usize, usize), sigma: f64) {
+    let (rows, cols) = dimensions;
+    if sigma <= 0.0 || rows == 0 || cols == 0 {
+        return;
+    }
+    let radius = (sigma * 3.0).ceil() as isize;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-(i * i) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f64 = kernel.iter().sum();
+
+    let blur = |values: &[f64], horizontal: bool| -> Vec<f64> {
+        let mut output = vec![0.0; values.len()];
+        for y in 0..rows {
+            for x in 0..cols {
+                let mut sum = 0.0;
+                for (k, weight) in kernel.iter().enumerate() {
+                    let offset = k as isize - radius;
+                    let (sx, sy) = if horizontal {
+                        (
+                            (x as isize + offset).clamp(0, cols as isize - 1) as usize,
+                            y,
+                        )
+                    } else {
+                        (
+                            x,
+                            (y as isize + offset).clamp(0, rows as isize - 1) as usize,
+                        )
+                    };
+                    sum += weight * values[sy * cols + sx];
+                }
+                output[y * cols + x] = sum / total;
+            }
+        }
+        output
+    };
+
+    let horizontal = blur(values, true);
+    let vertical = blur(&horizontal, false);
+    values.copy_from_slice(&vertical);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A 7x7 image with a single bright pixel in the middle.
+    fn dot() -> ImageData {
+        let mut image = vec![0; 49];
+        image[3 * 7 + 3] = 255;
+        image
+    }
+
+    #[test]
+    fn write_and_read() {
+        let augmentation = Augmentation::mnist()
+            .then(Transform::GaussianNoise { std_dev: 8.0 })
+            .then(Transform::RandomErase {
+                probability: 0.5,
+                max_fraction: 0.2,
+            });
+        let mut bytes = Vec::new();
+        augmentation.write_to(&mut bytes).unwrap();
+        assert_eq!(
+            Augmentation::read_from(&mut &bytes[..]).unwrap(),
+            augmentation
+        );
+        bytes[8] = 9;
+        assert!(Augmentation::read_from(&mut &bytes[..]).is_err());
+    }
+
+    #[test]
+    fn translate() {
+        let image = dot();
+        let mut random = Random::new(1);
+        let shifted = Transform::Translate { max: 3.0 }.apply(&image, (7, 7), &mut random);
+        assert_eq!(shifted.len(), 49);
+        assert_ne!(shifted, image);
+
+        let unchanged = Transform::Translate { max: 0.0 }.apply(&image, (7, 7), &mut random);
+        assert_eq!(unchanged, image, "A zero translation is the identity");
+    }
+
+    #[test]
+    fn rotate_and_scale_keep_the_center() {
+        let image = dot();
+        let mut random = Random::new(2);
+        let rotated = Transform::Rotate { max_degrees: 90.0 }.apply(&image, (7, 7), &mut random);
+        assert_eq!(rotated[3 * 7 + 3], 255, "The center doesn't move");
+
+        let scaled = Transform::Scale { min: 0.5, max: 2.0 }.apply(&image, (7, 7), &mut random);
+        assert_eq!(scaled[3 * 7 + 3], 255, "The center doesn't move");
+    }
+
+    #[test]
+    fn elastic() {
+        let image: ImageData = (0..49).map(|i| (i * 5) as u8).collect();
+        let mut random = Random::new(3);
+        let warped = Transform::Elastic {
+            alpha: 2.0,
+            sigma: 1.0,
+        }
+        .apply(&image, (7, 7), &mut random);
+        assert_eq!(warped.len(), 49);
+        assert_ne!(warped, image);
+    }
+
+    #[test]
+    fn channels_line_up() {
+        // The same dot in the red and blue channels of an RGB image.
+        let image: ImageData = dot().iter().flat_map(|pixel| [*pixel, 0, *pixel]).collect();
+        let augmentation = Augmentation::new().then(Transform::Translate { max: 3.0 });
+        let shifted = augmentation.apply_channels(&image, (7, 7), 3, &mut Random::new(5));
+        assert_eq!(shifted.len(), image.len());
+        assert_ne!(shifted, image);
+        for rgb in shifted.chunks_exact(3) {
+            assert_eq!(rgb[0], rgb[2]);
+            assert_eq!(rgb[1], 0);
+        }
+    }
+
+    #[test]
+    fn noise() {
+        let image = vec![128; 1000];
+        let mut random = Random::new(4);
+        let noisy =
+            Transform::GaussianNoise { std_dev: 10.0 }.apply(&image, (10, 100), &mut random);
+        let mean = noisy.iter().map(|p| *p as f64).sum::<f64>() / 1000.0;
+        assert!((mean - 128.0).abs() < 2.0, "The mean was {}", mean);
+        assert_ne!(noisy, image);
+    }
+
+    #[test]
+    fn random_erase() {
+        let image = vec![255; 100];
+        let mut random = Random::new(5);
+        let erased = Transform::RandomErase {
+            probability: 1.0,
+            max_fraction: 0.5,
+        }
+        .apply(&image, (10, 10), &mut random);
+        let erased_count = erased.iter().filter(|p| **p == 0).count();
+        assert!(erased_count > 0 && erased_count <= 60);
+
+        let skipped = Transform::RandomErase {
+            probability: 0.0,
+            max_fraction: 0.5,
+        }
+        .apply(&image, (10, 10), &mut random);
+        assert_eq!(skipped, image);
+    }
+
+    #[test]
+    fn seeded_pipeline() {
+        let image: ImageData = (0..49).map(|i| (i * 5) as u8).collect();
+        let pipeline = Augmentation::mnist().then(Transform::GaussianNoise { std_dev: 5.0 });
+        let a = pipeline.apply(&image, (7, 7), &mut Random::new(9));
+        let b = pipeline.apply(&image, (7, 7), &mut Random::new(9));
+        let c = pipeline.apply(&image, (7, 7), &mut Random::new(10));
+        assert_eq!(a, b, "The same seed gives the same result");
+        assert_ne!(a, c);
+    }
+}
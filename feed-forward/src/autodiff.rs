@@ -0,0 +1,563 @@
+//! A small tape-based reverse-mode automatic differentiation engine.
+//!
+//! Every operation on a `Var` is recorded onto a `Tape` along with the value it
+//! produced. Calling `Tape::backward` then walks the tape in reverse, applying the
+//! chain rule to each recorded operation, which produces the gradient of the output
+//! with respect to every variable that was used to compute it. This replaces the
+//! hand-derived partial derivatives in the README with something that works for
+//! arbitrary expressions.
+//!
+//! https://en.wikipedia.org/wiki/Automatic_differentiation#Reverse_accumulation
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::cell::RefCell;
+use std::f64::consts::E;
+use std::io::{self, Read, Write};
+use std::iter::zip;
+use std::ops;
+
+/// A dense, row-major matrix of values. Scalars are 1x1 tensors, and vectors are
+/// stored as a single column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tensor {
+    pub rows: usize,
+    pub cols: usize,
+    pub data: Vec<f64>,
+}
+
+impl Tensor {
+    pub fn new(rows: usize, cols: usize, data: Vec<f64>) -> Tensor {
+        assert_eq!(
+            rows * cols,
+            data.len(),
+            "The tensor data does not match its shape."
+        );
+        Tensor { rows, cols, data }
+    }
+
+    pub fn scalar(value: f64) -> Tensor {
+        Tensor::new(1, 1, vec![value])
+    }
+
+    /// Create a vector, which is stored as a single column.
+    pub fn column(data: Vec<f64>) -> Tensor {
+        Tensor::new(data.len(), 1, data)
+    }
+
+    pub fn zeros(rows: usize, cols: usize) -> Tensor {
+        Tensor::new(rows, cols, vec![0.0; rows * cols])
+    }
+
+    pub fn shape(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f64 {
+        self.data[row * self.cols + col]
+    }
+
+    /// Get the value of a 1x1 tensor.
+    pub fn value(&self) -> f64 {
+        assert_eq!(self.shape(), (1, 1), "The tensor is not a scalar.");
+        self.data[0]
+    }
+
+    /// Write out the shape followed by the values, which round-trip exactly.
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_u64::<BigEndian>(self.rows as u64)?;
+        writer.write_u64::<BigEndian>(self.cols as u64)?;
+        for value in &self.data {
+            writer.write_f64::<BigEndian>(*value)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Tensor> {
+        let rows = reader.read_u64::<BigEndian>()? as usize;
+        let cols = reader.read_u64::<BigEndian>()? as usize;
+        let len = rows.checked_mul(cols).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "The tensor shape is too large.")
+        })?;
+        let mut data = Vec::new();
+        for _ in 0..len {
+            data.push(reader.read_f64::<BigEndian>()?);
+        }
+        Ok(Tensor::new(rows, cols, data))
+    }
+
+    fn map(&self, f: impl Fn(f64) -> f64) -> Tensor {
+        Tensor::new(
+            self.rows,
+            self.cols,
+            self.data.iter().map(|v| f(*v)).collect(),
+        )
+    }
+
+    fn zip_map(&self, other: &Tensor, f: impl Fn(f64, f64) -> f64) -> Tensor {
+        assert_eq!(
+            self.shape(),
+            other.shape(),
+            "The tensor shapes do not match."
+        );
+        Tensor::new(
+            self.rows,
+            self.cols,
+            zip(&self.data, &other.data)
+                .map(|(a, b)| f(*a, *b))
+                .collect(),
+        )
+    }
+
+    fn transpose(&self) -> Tensor {
+        let mut data = Vec::with_capacity(self.data.len());
+        for col in 0..self.cols {
+            for row in 0..self.rows {
+                data.push(self.get(row, col));
+            }
+        }
+        Tensor::new(self.cols, self.rows, data)
+    }
+
+    fn matmul(&self, other: &Tensor) -> Tensor {
+        assert_eq!(
+            self.cols, other.rows,
+            "The tensor shapes can not be multiplied."
+        );
+        let mut data = vec![0.0; self.rows * other.cols];
+        for row in 0..self.rows {
+            for inner in 0..self.cols {
+                let a = self.get(row, inner);
+                for col in 0..other.cols {
+                    data[row * other.cols + col] += a * other.get(inner, col);
+                }
+            }
+        }
+        Tensor::new(self.rows, other.cols, data)
+    }
+
+    fn add_assign(&mut self, other: &Tensor) {
+        assert_eq!(
+            self.shape(),
+            other.shape(),
+            "The tensor shapes do not match."
+        );
+        for (a, b) in zip(self.data.iter_mut(), &other.data) {
+            *a += b;
+        }
+    }
+}
+
+/// The operations that can be recorded. Each one references the tape indexes of its
+/// inputs.
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    /// A leaf of the expression, such as a weight or an input.
+    Leaf,
+    Add(usize, usize),
+    Sub(usize, usize),
+    /// Element-wise multiplication.
+    Mul(usize, usize),
+    MatMul(usize, usize),
+    Scale(usize, f64),
+    Exp(usize),
+    Log(usize),
+    Sigmoid(usize),
+    Relu(usize),
+    Tanh(usize),
+    /// Sum all of the elements into a scalar.
+    Sum(usize),
+}
+
+#[derive(Debug)]
+struct Entry {
+    op: Op,
+    value: Tensor,
+}
+
+/// Records operations so that they can be differentiated later. Create a new tape
+/// for each expression that needs to be evaluated.
+#[derive(Debug, Default)]
+pub struct Tape {
+    entries: RefCell<Vec<Entry>>,
+}
+
+/// A handle to a value that was recorded on a tape.
+#[derive(Debug, Clone, Copy)]
+pub struct Var<'t> {
+    tape: &'t Tape,
+    index: usize,
+}
+
+impl Tape {
+    pub fn new() -> Tape {
+        Tape::default()
+    }
+
+    /// Record a leaf value. Gradients can be requested for these once `backward` is
+    /// run.
+    pub fn var(&self, value: Tensor) -> Var<'_> {
+        self.push(Op::Leaf, value)
+    }
+
+    pub fn scalar(&self, value: f64) -> Var<'_> {
+        self.var(Tensor::scalar(value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    fn push(&self, op: Op, value: Tensor) -> Var<'_> {
+        let mut entries = self.entries.borrow_mut();
+        entries.push(Entry { op, value });
+        Var {
+            tape: self,
+            index: entries.len() - 1,
+        }
+    }
+
+    /// Compute the gradients of `output` with respect to everything recorded before
+    /// it. The output's own gradient is seeded with ones, so a non-scalar output is
+    /// treated as the sum of its elements.
+    pub fn backward(&self, output: Var) -> Gradients {
+        assert!(
+            std::ptr::eq(self, output.tape),
+            "The output variable was recorded on a different tape."
+        );
+        let entries = self.entries.borrow();
+        let mut grads: Vec<Option<Tensor>> = vec![None; output.index + 1];
+        let seed = &entries[output.index].value;
+        grads[output.index] = Some(Tensor::new(
+            seed.rows,
+            seed.cols,
+            vec![1.0; seed.data.len()],
+        ));
+
+        for index in (0..=output.index).rev() {
+            let grad = match grads[index].take() {
+                Some(grad) => grad,
+                None => continue,
+            };
+            let entry = &entries[index];
+            let value_of = |i: usize| &entries[i].value;
+
+            match entry.op {
+                Op::Leaf => {}
+                Op::Add(a, b) => {
+                    accumulate(&mut grads, a, &grad);
+                    accumulate(&mut grads, b, &grad);
+                }
+                Op::Sub(a, b) => {
+                    accumulate(&mut grads, a, &grad);
+                    accumulate(&mut grads, b, &grad.map(|g| -g));
+                }
+                Op::Mul(a, b) => {
+                    accumulate(&mut grads, a, &grad.zip_map(value_of(b), |g, v| g * v));
+                    accumulate(&mut grads, b, &grad.zip_map(value_of(a), |g, v| g * v));
+                }
+                Op::MatMul(a, b) => {
+                    // C = AB, so ∂A = G·Bᵀ and ∂B = Aᵀ·G
+                    accumulate(&mut grads, a, &grad.matmul(&value_of(b).transpose()));
+                    accumulate(&mut grads, b, &value_of(a).transpose().matmul(&grad));
+                }
+                Op::Scale(a, factor) => {
+                    accumulate(&mut grads, a, &grad.map(|g| g * factor));
+                }
+                Op::Exp(a) => {
+                    // d/dx e^x = e^x, which is the value that was already computed.
+                    accumulate(&mut grads, a, &grad.zip_map(&entry.value, |g, v| g * v));
+                }
+                Op::Log(a) => {
+                    accumulate(&mut grads, a, &grad.zip_map(value_of(a), |g, v| g / v));
+                }
+                Op::Sigmoid(a) => {
+                    // σ'(x) = σ(x)(1 - σ(x))
+                    let local = entry.value.map(|v| v * (1.0 - v));
+                    accumulate(&mut grads, a, &grad.zip_map(&local, |g, l| g * l));
+                }
+                Op::Relu(a) => {
+                    let local = value_of(a).map(|v| if v > 0.0 { 1.0 } else { 0.0 });
+                    accumulate(&mut grads, a, &grad.zip_map(&local, |g, l| g * l));
+                }
+                Op::Tanh(a) => {
+                    // tanh'(x) = 1 - tanh²(x)
+                    let local = entry.value.map(|v| 1.0 - v * v);
+                    accumulate(&mut grads, a, &grad.zip_map(&local, |g, l| g * l));
+                }
+                Op::Sum(a) => {
+                    let input = value_of(a);
+                    let g = grad.value();
+                    accumulate(
+                        &mut grads,
+                        a,
+                        &Tensor::new(input.rows, input.cols, vec![g; input.data.len()]),
+                    );
+                }
+            }
+
+            // Only leaves need to keep their gradients around after this point.
+            if let Op::Leaf = entry.op {
+                grads[index] = Some(grad);
+            }
+        }
+
+        Gradients { grads }
+    }
+}
+
+fn accumulate(grads: &mut [Option<Tensor>], index: usize, grad: &Tensor) {
+    match grads[index] {
+        Some(ref mut existing) => existing.add_assign(grad),
+        None => grads[index] = Some(grad.clone()),
+    }
+}
+
+/// The result of `Tape::backward`.
+#[derive(Debug)]
+pub struct Gradients {
+    grads: Vec<Option<Tensor>>,
+}
+
+impl Gradients {
+    /// Get the gradient for a leaf variable. This is `None` if the output did not
+    /// depend on the variable.
+    pub fn get(&self, var: Var) -> Option<&Tensor> {
+        self.grads.get(var.index).and_then(|grad| grad.as_ref())
+    }
+
+    /// Get the gradient for a leaf variable, or zeros if the output did not depend
+    /// on it.
+    pub fn wrt(&self, var: Var) -> Tensor {
+        match self.get(var) {
+            Some(grad) => grad.clone(),
+            None => {
+                let (rows, cols) = var.value().shape();
+                Tensor::zeros(rows, cols)
+            }
+        }
+    }
+}
+
+impl<'t> Var<'t> {
+    /// Get a copy of the value that was computed for this variable.
+    pub fn value(&self) -> Tensor {
+        self.tape.entries.borrow()[self.index].value.clone()
+    }
+
+    /// Get the value of a scalar variable.
+    pub fn scalar(&self) -> f64 {
+        self.tape.entries.borrow()[self.index].value.value()
+    }
+
+    fn unary(&self, op: Op, f: impl Fn(&Tensor) -> Tensor) -> Var<'t> {
+        let value = f(&self.tape.entries.borrow()[self.index].value);
+        self.tape.push(op, value)
+    }
+
+    fn binary(&self, other: Var<'t>, op: Op, f: impl Fn(&Tensor, &Tensor) -> Tensor) -> Var<'t> {
+        assert!(
+            std::ptr::eq(self.tape, other.tape),
+            "Variables from different tapes can not be combined."
+        );
+        let value = {
+            let entries = self.tape.entries.borrow();
+            f(&entries[self.index].value, &entries[other.index].value)
+        };
+        self.tape.push(op, value)
+    }
+
+    pub fn add(&self, other: Var<'t>) -> Var<'t> {
+        self.binary(other, Op::Add(self.index, other.index), |a, b| {
+            a.zip_map(b, |a, b| a + b)
+        })
+    }
+
+    pub fn sub(&self, other: Var<'t>) -> Var<'t> {
+        self.binary(other, Op::Sub(self.index, other.index), |a, b| {
+            a.zip_map(b, |a, b| a - b)
+        })
+    }
+
+    /// Element-wise multiplication.
+    pub fn mul(&self, other: Var<'t>) -> Var<'t> {
+        self.binary(other, Op::Mul(self.index, other.index), |a, b| {
+            a.zip_map(b, |a, b| a * b)
+        })
+    }
+
+    pub fn matmul(&self, other: Var<'t>) -> Var<'t> {
+        self.binary(other, Op::MatMul(self.index, other.index), |a, b| {
+            a.matmul(b)
+        })
+    }
+
+    /// Multiply every element by a constant.
+    pub fn scale(&self, factor: f64) -> Var<'t> {
+        self.unary(Op::Scale(self.index, factor), |a| a.map(|v| v * factor))
+    }
+
+    pub fn exp(&self) -> Var<'t> {
+        self.unary(Op::Exp(self.index), |a| a.map(|v| E.powf(v)))
+    }
+
+    /// The natural logarithm.
+    pub fn ln(&self) -> Var<'t> {
+        self.unary(Op::Log(self.index), |a| a.map(f64::ln))
+    }
+
+    pub fn sigmoid(&self) -> Var<'t> {
+        self.unary(Op::Sigmoid(self.index), |a| a.map(sigmoid))
+    }
+
+    pub fn relu(&self) -> Var<'t> {
+        self.unary(Op::Relu(self.index), |a| a.map(|v| v.max(0.0)))
+    }
+
+    pub fn tanh(&self) -> Var<'t> {
+        self.unary(Op::Tanh(self.index), |a| a.map(f64::tanh))
+    }
+
+    /// Sum all of the elements into a scalar.
+    pub fn sum(&self) -> Var<'t> {
+        self.unary(Op::Sum(self.index), |a| Tensor::scalar(a.data.iter().sum()))
+    }
+
+    /// Square every element.
+    pub fn square(&self) -> Var<'t> {
+        self.mul(*self)
+    }
+}
+
+impl<'t> ops::Add for Var<'t> {
+    type Output = Var<'t>;
+    fn add(self, other: Var<'t>) -> Var<'t> {
+        Var::add(&self, other)
+    }
+}
+
+impl<'t> ops::Sub for Var<'t> {
+    type Output = Var<'t>;
+    fn sub(self, other: Var<'t>) -> Var<'t> {
+        Var::sub(&self, other)
+    }
+}
+
+impl<'t> ops::Mul for Var<'t> {
+    type Output = Var<'t>;
+    fn mul(self, other: Var<'t>) -> Var<'t> {
+        Var::mul(&self, other)
+    }
+}
+
+/// Sigmoid functions keep the values ranged from 0 to 1. This particular function
+/// is the logistic function given by: σ(x) = 1 / ( 1 + e^-x )
+///
+/// https://en.wikipedia.org/wiki/Sigmoid_function
+pub fn sigmoid(value: f64) -> f64 {
+    1.0 / (1.0 + E.powf(-value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Compare the tape's gradient with a numerical estimate using central
+    /// differences.
+    fn assert_gradient(input: Tensor, f: impl for<'t> Fn(Var<'t>) -> Var<'t>) {
+        let tape = Tape::new();
+        let x = tape.var(input.clone());
+        let output = f(x);
+        let grad = tape.backward(output).wrt(x);
+
+        let epsilon = 1e-6;
+        for i in 0..input.data.len() {
+            let evaluate = |offset: f64| {
+                let mut nudged = input.clone();
+                nudged.data[i] += offset;
+                let tape = Tape::new();
+                let output = f(tape.var(nudged));
+                let sum: f64 = output.value().data.iter().sum();
+                sum
+            };
+            let numerical = (evaluate(epsilon) - evaluate(-epsilon)) / (2.0 * epsilon);
+            assert!(
+                (numerical - grad.data[i]).abs() < 1e-5,
+                "Gradient {} was {} but expected {}",
+                i,
+                grad.data[i],
+                numerical
+            );
+        }
+    }
+
+    #[test]
+    fn scalar_ops() {
+        let tape = Tape::new();
+        let a = tape.scalar(3.0);
+        let b = tape.scalar(4.0);
+        // f(a, b) = a * b + a
+        let c = a * b + a;
+        assert_eq!(c.scalar(), 15.0);
+
+        let gradients = tape.backward(c);
+        assert_eq!(gradients.wrt(a).value(), 5.0, "∂f/∂a = b + 1");
+        assert_eq!(gradients.wrt(b).value(), 3.0, "∂f/∂b = a");
+    }
+
+    #[test]
+    fn unused_variables() {
+        let tape = Tape::new();
+        let a = tape.scalar(3.0);
+        let b = tape.scalar(4.0);
+        let c = a.exp();
+        let gradients = tape.backward(c);
+        assert!(gradients.get(b).is_none());
+        assert_eq!(gradients.wrt(b).value(), 0.0);
+    }
+
+    #[test]
+    fn element_wise_gradients() {
+        let input = Tensor::column(vec![-1.5, -0.2, 0.3, 2.0]);
+        assert_gradient(input.clone(), |x| x.exp());
+        assert_gradient(input.clone(), |x| x.sigmoid());
+        assert_gradient(input.clone(), |x| x.tanh());
+        assert_gradient(input.clone(), |x| x.relu());
+        assert_gradient(input.clone(), |x| x.square().scale(0.5));
+        assert_gradient(input.clone(), |x| x.sigmoid().ln());
+        assert_gradient(input, |x| (x * x.exp() - x).sum());
+    }
+
+    #[test]
+    fn matmul_gradients() {
+        let weights = Tensor::new(2, 3, vec![0.1, -0.2, 0.3, 0.4, 0.5, -0.6]);
+        let input = Tensor::column(vec![1.0, -2.0, 0.5]);
+
+        assert_gradient(weights.clone(), |w| {
+            let x = w.tape.var(input.clone());
+            w.matmul(x).sigmoid().sum()
+        });
+        assert_gradient(input, |x| {
+            let w = x.tape.var(weights.clone());
+            w.matmul(x).tanh().sum()
+        });
+    }
+
+    #[test]
+    fn matmul_shape() {
+        let tape = Tape::new();
+        let a = tape.var(Tensor::new(2, 3, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]));
+        let b = tape.var(Tensor::new(3, 1, vec![1.0, 0.0, -1.0]));
+        let c = a.matmul(b);
+        assert_eq!(c.value(), Tensor::column(vec![-2.0, -2.0]));
+    }
+
+    #[test]
+    fn matmul_propagates_nan() {
+        let a = Tensor::new(1, 2, vec![0.0, 1.0]);
+        let b = Tensor::column(vec![f64::NAN, 2.0]);
+        assert!(a.matmul(&b).get(0, 0).is_nan(), "0 * NaN is NaN");
+    }
+}
@@ -0,0 +1,252 @@
+//! K-fold cross-validation. The images are split into k stratified folds, and a fresh
+//! network is trained k times, each time holding out a different fold to evaluate on.
+//! The spread of the results shows how much of a difference between two
+//! configurations is just noise from the choice of split.
+use crate::image_data::Images;
+use crate::network::{Evaluation, Network};
+use crate::normalize::Normalization;
+use crate::train::{Trainer, TrainingConfig};
+use std::io;
+
+#[derive(Debug, Clone)]
+pub struct CrossValidation {
+    /// The number of folds, k.
+    pub folds: usize,
+    pub hidden_layer_count: usize,
+    pub hidden_node_count: usize,
+    pub output_node_count: usize,
+    /// How each fold is trained. Checkpoints, metrics and the dashboard are turned off,
+    /// as every fold would write over the others.
+    pub training: TrainingConfig,
+    /// The name of the normalization, which is fitted to the training folds each time.
+    /// See `Normalization::fit`.
+    pub normalize: String,
+    /// The seed for splitting the images into folds.
+    pub seed: u64,
+}
+
+impl Default for CrossValidation {
+    fn default() -> CrossValidation {
+        CrossValidation {
+            folds: 5,
+            hidden_layer_count: 2,
+            hidden_node_count: 16,
+            output_node_count: 10,
+            training: TrainingConfig::default(),
+            normalize: String::from("none"),
+            seed: 0,
+        }
+    }
+}
+
+/// The evaluation of every fold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrossValidationReport {
+    pub folds: Vec<Evaluation>,
+}
+
+impl CrossValidation {
+    /// Split the images into `folds` parts with the same proportion of each label, and
+    /// pair each one with the rest of the images. Returns (training, validation) pairs.
+    /// Every class needs at least one image per fold, so that no fold is left empty.
+    pub fn split(&self, images: &Images) -> io::Result<Vec<(Images, Images)>> {
+        if self.folds < 2 {
+            return Err(invalid_input("Cross-validation needs at least 2 folds."));
+        }
+        let smallest_class = images
+            .class_histogram()
+            .into_iter()
+            .filter(|count| *count > 0)
+            .min()
+            .unwrap_or(0);
+        if self.folds > smallest_class {
+            return Err(invalid_input(
+                "Every class needs at least as many images as there are folds.",
+            ));
+        }
+        let fractions = vec![1.0 / self.folds as f64; self.folds];
+        let folds = images.stratified_split(&fractions, self.seed);
+        Ok((0..folds.len())
+            .map(|held_out| {
+                let training = folds
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| *index != held_out)
+                    .map(|(_, fold)| fold)
+                    .fold(None, |joined: Option<Images>, fold| match joined {
+                        Some(joined) => Some(joined.concat(fold)),
+                        None => Some(fold.clone()),
+                    })
+                    .expect("There are at least 2 folds.");
+                (training, folds[held_out].clone())
+            })
+            .collect())
+    }
+
+    /// Train a fresh network on the training images, and evaluate it on the
+    /// validation images.
+    pub fn train_fold(&self, training: Images, validation: &Images) -> io::Result<Evaluation> {
+        let normalization = Normalization::fit(&self.normalize, &training)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Unknown normalization."))?;
+        let mut network = Network::new(
+            training,
+            self.hidden_layer_count,
+            self.hidden_node_count,
+            self.output_node_count,
+        );
+        network.normalization = normalization;
+
+        let mut trainer = Trainer::new(TrainingConfig {
+            checkpoint_path: None,
+            metrics_path: None,
+            dashboard: false,
+            ..self.training.clone()
+        });
+        trainer.train(&mut network)?;
+        Ok(network.evaluate(validation))
+    }
+
+    /// Train and evaluate a network for every fold.
+    pub fn run(&self, images: &Images) -> io::Result<CrossValidationReport> {
+        let mut folds = Vec::with_capacity(self.folds);
+        for (training, validation) in self.split(images)? {
+            folds.push(self.train_fold(training, &validation)?);
+        }
+        Ok(CrossValidationReport { folds })
+    }
+}
+
+impl CrossValidationReport {
+    pub fn mean_accuracy(&self) -> f64 {
+        mean(self.folds.iter().map(|fold| fold.accuracy))
+    }
+
+    pub fn std_dev_accuracy(&self) -> f64 {
+        std_dev(self.folds.iter().map(|fold| fold.accuracy))
+    }
+
+    pub fn mean_loss(&self) -> f64 {
+        mean(self.folds.iter().map(|fold| fold.loss))
+    }
+
+    pub fn std_dev_loss(&self) -> f64 {
+        std_dev(self.folds.iter().map(|fold| fold.loss))
+    }
+}
+
+fn invalid_input(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+    sum / (count as f64).max(1.0)
+}
+
+/// The population standard deviation, as every fold is included.
+fn std_dev(values: impl Iterator<Item = f64> + Clone) -> f64 {
+    let mean = mean(values.clone());
+    self::mean(values.map(|value| (value - mean) * (value - mean))).sqrt()
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// Two classes of 1x2 images that are easy to tell apart, with 10 of each. The
+    /// search tests use them too.
+    pub(crate) fn images() -> Images {
+        let mut pixels = Vec::new();
+        let mut labels = Vec::new();
+        for index in 0..20u8 {
+            let label = index % 2;
+            pixels.extend_from_slice(if label == 0 { &[255, 0] } else { &[0, 255] });
+            labels.push(label);
+        }
+        Images::new((1, 2), pixels, labels)
+    }
+
+    fn cross_validation() -> CrossValidation {
+        CrossValidation {
+            folds: 5,
+            hidden_layer_count: 1,
+            hidden_node_count: 4,
+            output_node_count: 2,
+            training: TrainingConfig {
+                epochs: 30,
+                batch_size: 1,
+                learning_rate: 1.0,
+                ..TrainingConfig::default()
+            },
+            ..CrossValidation::default()
+        }
+    }
+
+    #[test]
+    fn stratified_folds() {
+        let folds = cross_validation().split(&images()).unwrap();
+        assert_eq!(folds.len(), 5);
+        for (training, validation) in &folds {
+            assert_eq!(training.class_histogram(), vec![8, 8]);
+            assert_eq!(validation.class_histogram(), vec![2, 2]);
+        }
+    }
+
+    #[test]
+    fn reports() {
+        let report = cross_validation().run(&images()).unwrap();
+        assert_eq!(report.folds.len(), 5);
+        assert!(
+            report.mean_accuracy() > 0.9,
+            "The classes are easy to learn, got {:?}",
+            report
+        );
+        assert!(report.mean_loss() < 0.5);
+
+        let report = CrossValidationReport {
+            folds: vec![
+                Evaluation {
+                    loss: 1.0,
+                    accuracy: 0.5,
+                },
+                Evaluation {
+                    loss: 3.0,
+                    accuracy: 0.7,
+                },
+            ],
+        };
+        assert_eq!(report.mean_loss(), 2.0);
+        assert_eq!(report.std_dev_loss(), 1.0);
+        assert!((report.mean_accuracy() - 0.6).abs() < 1e-12);
+        assert!((report.std_dev_accuracy() - 0.1).abs() < 1e-12);
+    }
+
+    #[test]
+    fn rejects_empty_folds() {
+        for folds in [0, 1, 11].iter() {
+            let cross_validation = CrossValidation {
+                folds: *folds,
+                ..cross_validation()
+            };
+            let error = cross_validation.split(&images()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{} folds", folds);
+        }
+        let ten_folds = CrossValidation {
+            folds: 10,
+            ..cross_validation()
+        };
+        for (_, validation) in ten_folds.split(&images()).unwrap() {
+            assert_eq!(validation.class_histogram(), vec![1, 1]);
+        }
+        assert!(ten_folds.run(&images().subset(&[])).is_err());
+    }
+
+    #[test]
+    fn unknown_normalization() {
+        let cross_validation = CrossValidation {
+            normalize: String::from("unknown"),
+            ..cross_validation()
+        };
+        assert!(cross_validation.run(&images()).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+//! A live view of training in the terminal. Rather than printing a line for every
+//! batch, the dashboard redraws itself in place with a progress bar, sparklines for
+//! the loss and accuracy, the throughput, and the learning rate.
+use crate::train::Trainer;
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+use term_painter::Color::{BrightBlack, Cyan, Green, Yellow};
+use term_painter::{Attr, ToStyle};
+
+const BAR_WIDTH: usize = 40;
+const SPARKLINE_WIDTH: usize = 40;
+const SPARK_CHARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Everything the dashboard displays at a single point in time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DashboardState {
+    pub epoch: usize,
+    pub epochs: usize,
+    pub batch: usize,
+    pub batches: usize,
+    pub train_loss: f64,
+    /// The loss samples, oldest first.
+    pub losses: Vec<f64>,
+    /// The validation accuracy at the end of each epoch, from 0 to 1.
+    pub accuracies: Vec<f64>,
+    pub images_per_second: f64,
+    pub learning_rate: f64,
+}
+
+#[derive(Debug)]
+pub struct Dashboard {
+    /// How many lines were drawn last time, so the cursor can move back over them.
+    lines_drawn: usize,
+    last_draw: Option<Instant>,
+    /// The throughput is measured between draws.
+    last_images: usize,
+    images_per_second: f64,
+    losses: Vec<f64>,
+    /// Only redraw in place when writing to a terminal, otherwise a new view is
+    /// printed at the end of every epoch.
+    is_terminal: bool,
+}
+
+impl Default for Dashboard {
+    fn default() -> Dashboard {
+        Dashboard::new()
+    }
+}
+
+impl Dashboard {
+    pub fn new() -> Dashboard {
+        Dashboard {
+            lines_drawn: 0,
+            last_draw: None,
+            last_images: 0,
+            images_per_second: 0.0,
+            losses: Vec::new(),
+            is_terminal: io::stdout().is_terminal(),
+        }
+    }
+
+    /// Update the view after a training step. This is throttled so that drawing
+    /// doesn't slow down training, but the end of an epoch is always drawn.
+    pub fn update(&mut self, trainer: &Trainer, image_count: usize) {
+        let end_of_epoch = trainer.batch() == 0;
+        let now = Instant::now();
+        if let Some(last_draw) = self.last_draw {
+            let is_throttled = now.duration_since(last_draw) < REFRESH_INTERVAL;
+            if (is_throttled || !self.is_terminal) && !end_of_epoch {
+                return;
+            }
+        }
+
+        let batch_size = trainer.config.batch_size;
+        let batches = image_count.div_ceil(batch_size).max(1);
+        let images = trainer.epoch() * image_count + (trainer.batch() * batch_size);
+        if let Some(last_draw) = self.last_draw {
+            let seconds = now.duration_since(last_draw).as_secs_f64();
+            if seconds > 0.0 {
+                self.images_per_second = images.saturating_sub(self.last_images) as f64 / seconds;
+            }
+        }
+        self.last_draw = Some(now);
+        self.last_images = images;
+
+        let metrics = if end_of_epoch {
+            trainer.history.last().cloned()
+        } else {
+            None
+        }
+        .unwrap_or_else(|| trainer.current_metrics());
+        self.losses.push(metrics.train_loss);
+
+        let state = DashboardState {
+            // Show the epoch that just finished rather than the next one.
+            epoch: if end_of_epoch {
+                trainer.epoch()
+            } else {
+                trainer.epoch() + 1
+            },
+            epochs: trainer.config.epochs,
+            batch: if end_of_epoch {
+                batches
+            } else {
+                trainer.batch()
+            },
+            batches,
+            train_loss: metrics.train_loss,
+            losses: self.losses.clone(),
+            accuracies: trainer
+                .history
+                .iter()
+                .filter_map(|metrics| metrics.validation_accuracy)
+                .collect(),
+            images_per_second: self.images_per_second,
+            learning_rate: metrics.learning_rate,
+        };
+        // Drawing is best effort, training shouldn't stop if the terminal goes away.
+        let _ = self.draw(&state);
+    }
+
+    fn draw(&mut self, state: &DashboardState) -> io::Result<()> {
+        let lines = render(state);
+        let mut stdout = io::stdout();
+        if !self.is_terminal {
+            for line in &lines {
+                writeln!(stdout, "{}", line)?;
+            }
+            writeln!(stdout)?;
+            return stdout.flush();
+        }
+
+        // Move the cursor up to the start of the previous view.
+        if self.lines_drawn > 0 {
+            write!(stdout, "\x1b[{}F", self.lines_drawn)?;
+        }
+        let styles = [
+            Green.to_style(),
+            Yellow.to_style(),
+            Cyan.to_style(),
+            Attr::Plain.to_style(),
+            BrightBlack.to_style(),
+        ];
+        for (line, style) in lines.iter().zip(styles.iter()) {
+            // Clear the rest of the line in case the previous view was longer.
+            writeln!(stdout, "{}\x1b[K", style.paint(line))?;
+        }
+        self.lines_drawn = lines.len();
+        stdout.flush()
+    }
+}
+
+/// Render the view as plain lines of text.
+pub fn render(state: &DashboardState) -> Vec<String> {
+    let fraction = if state.batches == 0 {
+        0.0
+    } else {
+        (state.batch as f64 / state.batches as f64).min(1.0)
+    };
+    let filled = (fraction * BAR_WIDTH as f64).round() as usize;
+    let bar: String = "█".repeat(filled) + &"░".repeat(BAR_WIDTH - filled);
+
+    let accuracy = match state.accuracies.last() {
+        Some(accuracy) => format!(
+            "{:>8.2}%  {}",
+            accuracy * 100.0,
+            sparkline(&state.accuracies, SPARKLINE_WIDTH)
+        ),
+        None => "     n/a   (no validation set)".to_string(),
+    };
+
+    vec![
+        format!(
+            "Epoch {}/{} [{}] {:>3.0}%  batch {}/{}",
+            state.epoch,
+            state.epochs,
+            bar,
+            fraction * 100.0,
+            state.batch,
+            state.batches
+        ),
+        format!(
+            "loss      {:>9.5}  {}",
+            state.train_loss,
+            sparkline(&state.losses, SPARKLINE_WIDTH)
+        ),
+        format!("accuracy  {}", accuracy),
+        format!("speed     {:>9.0} images/s", state.images_per_second),
+        format!("learning rate {}", state.learning_rate),
+    ]
+}
+
+/// Draw the most recent values as a row of block characters that are scaled between
+/// the minimum and maximum value.
+pub fn sparkline(values: &[f64], width: usize) -> String {
+    let values = &values[values.len().saturating_sub(width)..];
+    let finite = values.iter().filter(|value| value.is_finite());
+    let min = finite.clone().fold(f64::INFINITY, |a, b| a.min(*b));
+    let max = finite.fold(f64::NEG_INFINITY, |a, b| a.max(*b));
+    values
+        .iter()
+        .map(|value| {
+            if !value.is_finite() {
+                return ' ';
+            }
+            let normalized = if max > min {
+                (value - min) / (max - min)
+            } else {
+                0.5
+            };
+            let index = (normalized * (SPARK_CHARS.len() - 1) as f64).round() as usize;
+            SPARK_CHARS[index]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sparklines() {
+        assert_eq!(sparkline(&[0.0, 0.5, 1.0], 10), "▁▅█");
+        assert_eq!(
+            sparkline(&[2.0, 2.0], 10),
+            "▅▅",
+            "Flat lines sit in the middle"
+        );
+        assert_eq!(
+            sparkline(&[0.0, 1.0, 2.0, 3.0], 2),
+            "▁█",
+            "Only the latest values fit"
+        );
+        assert_eq!(sparkline(&[], 10), "");
+    }
+
+    #[test]
+    fn renders() {
+        let lines = render(&DashboardState {
+            epoch: 2,
+            epochs: 10,
+            batch: 5,
+            batches: 20,
+            train_loss: 0.25,
+            losses: vec![0.5, 0.25],
+            accuracies: vec![],
+            images_per_second: 1500.0,
+            learning_rate: 0.1,
+        });
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("Epoch 2/10 [██████████░░░"));
+        assert!(lines[0].ends_with(" 25%  batch 5/20"));
+        assert_eq!(lines[1], "loss        0.25000  █▁");
+        assert!(lines[2].contains("n/a"));
+        assert_eq!(lines[3], "speed          1500 images/s");
+    }
+}
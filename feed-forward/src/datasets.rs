@@ -0,0 +1,393 @@
+//! Datasets that are drop-in replacements for MNIST. Most are 28x28 grayscale images
+//! in the same IDX format, but they have different classes, and EMNIST stores its
+//! images transposed. CIFAR-10 has 32x32 color images in its own binary format.
+//!
+//! Fashion-MNIST: https://github.com/zalandoresearch/fashion-mnist
+//! KMNIST: https://github.com/rois-codh/kmnist
+//! EMNIST: https://www.nist.gov/itl/products-and-services/emnist-dataset
+//! CIFAR-10: https://www.cs.toronto.edu/~kriz/cifar.html
+use crate::image_data::{
+    data_root, find_data_file, load_cifar_batches, load_images, load_images_mapped, Error, Images,
+    Split,
+};
+use std::path::{Path, PathBuf};
+
+const DIGITS: &str = "0123456789";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+/// The lowercase letters that are kept in the merged EMNIST splits. The rest look too
+/// much like their uppercase versions, so they are merged into them.
+const UNMERGED_LOWERCASE: &str = "abdefghnqrt";
+
+const FASHION_NAMES: [&str; 10] = [
+    "T-shirt/top",
+    "Trouser",
+    "Pullover",
+    "Dress",
+    "Coat",
+    "Sandal",
+    "Shirt",
+    "Sneaker",
+    "Bag",
+    "Ankle boot",
+];
+
+/// The ten hiragana characters in KMNIST, with their romanizations.
+const KMNIST_NAMES: [&str; 10] = [
+    "お (o)",
+    "き (ki)",
+    "す (su)",
+    "つ (tsu)",
+    "な (na)",
+    "は (ha)",
+    "ま (ma)",
+    "や (ya)",
+    "れ (re)",
+    "を (wo)",
+];
+
+const CIFAR_NAMES: [&str; 10] = [
+    "airplane",
+    "automobile",
+    "bird",
+    "cat",
+    "deer",
+    "dog",
+    "frog",
+    "horse",
+    "ship",
+    "truck",
+];
+
+/// The number of training batch files in CIFAR-10, which each hold 10,000 images.
+const CIFAR_TRAINING_BATCHES: usize = 5;
+
+/// The splits of EMNIST, which each cover a different set of characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmnistSplit {
+    /// Digits, uppercase and lowercase letters.
+    ByClass,
+    /// Like `ByClass`, but with similar looking letters merged.
+    ByMerge,
+    /// Like `ByMerge`, but with the same number of images in every class.
+    Balanced,
+    /// Letters, with the uppercase and lowercase versions merged.
+    Letters,
+    Digits,
+    /// A set of digits with the same size as MNIST.
+    Mnist,
+}
+
+impl EmnistSplit {
+    fn name(self) -> &'static str {
+        match self {
+            EmnistSplit::ByClass => "byclass",
+            EmnistSplit::ByMerge => "bymerge",
+            EmnistSplit::Balanced => "balanced",
+            EmnistSplit::Letters => "letters",
+            EmnistSplit::Digits => "digits",
+            EmnistSplit::Mnist => "mnist",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dataset {
+    Mnist,
+    FashionMnist,
+    Kmnist,
+    Emnist(EmnistSplit),
+    Cifar10,
+}
+
+impl Dataset {
+    /// Look up a dataset by the name used on the command line, e.g. "fashion-mnist" or
+    /// "emnist-letters".
+    pub fn from_name(name: &str) -> Option<Dataset> {
+        let split = match name {
+            "mnist" => return Some(Dataset::Mnist),
+            "fashion-mnist" => return Some(Dataset::FashionMnist),
+            "kmnist" => return Some(Dataset::Kmnist),
+            "cifar-10" => return Some(Dataset::Cifar10),
+            "emnist-byclass" => EmnistSplit::ByClass,
+            "emnist-bymerge" => EmnistSplit::ByMerge,
+            "emnist-balanced" => EmnistSplit::Balanced,
+            "emnist-letters" => EmnistSplit::Letters,
+            "emnist-digits" => EmnistSplit::Digits,
+            "emnist-mnist" => EmnistSplit::Mnist,
+            _ => return None,
+        };
+        Some(Dataset::Emnist(split))
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Dataset::Mnist => "mnist".to_string(),
+            Dataset::FashionMnist => "fashion-mnist".to_string(),
+            Dataset::Kmnist => "kmnist".to_string(),
+            Dataset::Emnist(split) => format!("emnist-{}", split.name()),
+            Dataset::Cifar10 => "cifar-10".to_string(),
+        }
+    }
+
+    /// The number of classes, which is the number of output nodes a network needs.
+    pub fn class_count(self) -> usize {
+        self.label_names().len()
+    }
+
+    /// A human readable name for every label.
+    pub fn label_names(self) -> Vec<String> {
+        let characters = |sets: &[&str]| -> Vec<String> {
+            sets.iter()
+                .flat_map(|set| set.chars())
+                .map(|character| character.to_string())
+                .collect()
+        };
+        match self {
+            Dataset::Mnist
+            | Dataset::Emnist(EmnistSplit::Digits)
+            | Dataset::Emnist(EmnistSplit::Mnist) => characters(&[DIGITS]),
+            Dataset::FashionMnist => FASHION_NAMES.iter().map(|name| name.to_string()).collect(),
+            Dataset::Kmnist => KMNIST_NAMES.iter().map(|name| name.to_string()).collect(),
+            Dataset::Cifar10 => CIFAR_NAMES.iter().map(|name| name.to_string()).collect(),
+            Dataset::Emnist(EmnistSplit::ByClass) => characters(&[DIGITS, UPPERCASE, LOWERCASE]),
+            Dataset::Emnist(EmnistSplit::ByMerge) | Dataset::Emnist(EmnistSplit::Balanced) => {
+                characters(&[DIGITS, UPPERCASE, UNMERGED_LOWERCASE])
+            }
+            Dataset::Emnist(EmnistSplit::Letters) => characters(&[UPPERCASE]),
+        }
+    }
+
+    /// The directory the dataset is expected in when no other location is given. MNIST
+    /// uses `data_root`, and the others are next to it in `./data`.
+    pub fn default_root(self) -> PathBuf {
+        match self {
+            Dataset::Mnist => data_root(),
+            Dataset::FashionMnist => PathBuf::from("./data/fashion-mnist"),
+            Dataset::Kmnist => PathBuf::from("./data/kmnist"),
+            Dataset::Emnist(_) => PathBuf::from("./data/emnist"),
+            Dataset::Cifar10 => PathBuf::from("./data/cifar-10-batches-bin"),
+        }
+    }
+
+    /// The number of color channels in the images.
+    pub fn channels(self) -> usize {
+        match self {
+            Dataset::Cifar10 => 3,
+            _ => 1,
+        }
+    }
+
+    /// The paths to the data files in the directory. For the IDX datasets this is the
+    /// images file followed by the labels file, and these can be the downloaded `.gz`
+    /// files. For CIFAR-10 it is the batch files, which hold both.
+    pub fn paths(self, root: impl AsRef<Path>, split: Split) -> Vec<PathBuf> {
+        let root = root.as_ref();
+        let prefix = match (self, split) {
+            (Dataset::Cifar10, Split::Training) => {
+                return (1..=CIFAR_TRAINING_BATCHES)
+                    .map(|batch| root.join(format!("data_batch_{}.bin", batch)))
+                    .collect()
+            }
+            (Dataset::Cifar10, Split::Test) => return vec![root.join("test_batch.bin")],
+            (Dataset::Emnist(emnist), Split::Training) => format!("emnist-{}-train", emnist.name()),
+            (Dataset::Emnist(emnist), Split::Test) => format!("emnist-{}-test", emnist.name()),
+            // The other datasets use the same file names as MNIST.
+            (_, Split::Training) => "train".to_string(),
+            (_, Split::Test) => "t10k".to_string(),
+        };
+        vec![
+            find_data_file(root, &format!("{}-images-idx3-ubyte", prefix)),
+            find_data_file(root, &format!("{}-labels-idx1-ubyte", prefix)),
+        ]
+    }
+
+    /// Load the dataset like `load`, but memory map the images. EMNIST can't be mapped,
+    /// as its images need to be transposed, and neither can CIFAR-10, as its channels
+    /// need to be interleaved.
+    pub fn load_mapped(self, root: impl AsRef<Path>, split: Split) -> Result<Images, Error> {
+        match self {
+            Dataset::Emnist(_) => Err(Error::Message(
+                "EMNIST images are stored transposed, so they can't be memory mapped.",
+            )),
+            Dataset::Cifar10 => Err(Error::Message(
+                "CIFAR-10 images are stored as planes of color, so they can't be memory mapped.",
+            )),
+            _ => match &self.paths(root, split)[..] {
+                [images_path, labels_path] => load_images_mapped(images_path, labels_path),
+                _ => unreachable!("The IDX datasets have an images and a labels file."),
+            },
+        }
+    }
+
+    /// Load the dataset from the directory, with the labels ranging from 0 to
+    /// `class_count`, and the images in the same orientation as MNIST.
+    pub fn load(self, root: impl AsRef<Path>, split: Split) -> Result<Images, Error> {
+        let paths = self.paths(root, split);
+        let mut images = match (self, &paths[..]) {
+            (Dataset::Cifar10, _) => load_cifar_batches(&paths)?,
+            (_, [images_path, labels_path]) => load_images(images_path, labels_path)?,
+            _ => unreachable!("The IDX datasets have an images and a labels file."),
+        };
+        if let Dataset::Emnist(split) = self {
+            images = transpose(&images);
+            // The letters start at 1, as 0 was reserved for "not a letter".
+            if split == EmnistSplit::Letters {
+                for label in images.labels.iter_mut() {
+                    *label = label.wrapping_sub(1);
+                }
+            }
+        }
+        if images
+            .labels
+            .iter()
+            .any(|label| *label as usize >= self.class_count())
+        {
+            return Err(Error::Message(
+                "A label is out of range for the number of classes in the dataset.",
+            ));
+        }
+        Ok(images)
+    }
+}
+
+/// Swap the rows and columns of every image.
+pub fn transpose(images: &Images) -> Images {
+    let (rows, cols) = images.dimensions;
+    let channels = images.channels;
+    let mut pixels = Vec::with_capacity(images.pixels().len());
+    for image in images.iter() {
+        for col in 0..cols {
+            for row in 0..rows {
+                let start = (row * cols + col) * channels;
+                pixels.extend_from_slice(&image[start..start + channels]);
+            }
+        }
+    }
+    Images::with_channels((cols, rows), channels, pixels, images.labels.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image_data::{write_images, write_labels};
+    use std::{env, fs};
+
+    #[test]
+    fn class_counts() {
+        assert_eq!(Dataset::Mnist.class_count(), 10);
+        assert_eq!(Dataset::FashionMnist.class_count(), 10);
+        assert_eq!(Dataset::Kmnist.class_count(), 10);
+        assert_eq!(Dataset::Emnist(EmnistSplit::ByClass).class_count(), 62);
+        assert_eq!(Dataset::Emnist(EmnistSplit::ByMerge).class_count(), 47);
+        assert_eq!(Dataset::Emnist(EmnistSplit::Balanced).class_count(), 47);
+        assert_eq!(Dataset::Emnist(EmnistSplit::Letters).class_count(), 26);
+        assert_eq!(Dataset::Emnist(EmnistSplit::Digits).class_count(), 10);
+        assert_eq!(Dataset::Cifar10.class_count(), 10);
+
+        assert_eq!(Dataset::FashionMnist.label_names()[9], "Ankle boot");
+        assert_eq!(Dataset::Emnist(EmnistSplit::ByClass).label_names()[61], "z");
+        assert_eq!(
+            Dataset::Emnist(EmnistSplit::Balanced).label_names()[46],
+            "t"
+        );
+    }
+
+    #[test]
+    fn names() {
+        for name in [
+            "mnist",
+            "fashion-mnist",
+            "kmnist",
+            "emnist-letters",
+            "cifar-10",
+        ] {
+            assert_eq!(Dataset::from_name(name).unwrap().name(), name);
+        }
+        assert_eq!(Dataset::from_name("cifar"), None);
+    }
+
+    #[test]
+    fn paths() {
+        let paths = Dataset::Emnist(EmnistSplit::Balanced).paths("data", Split::Test);
+        assert_eq!(
+            paths,
+            vec![
+                Path::new("data").join("emnist-balanced-test-images-idx3-ubyte"),
+                Path::new("data").join("emnist-balanced-test-labels-idx1-ubyte"),
+            ]
+        );
+        let paths = Dataset::FashionMnist.paths("data", Split::Training);
+        assert_eq!(paths[0], Path::new("data").join("train-images-idx3-ubyte"));
+
+        let paths = Dataset::Cifar10.paths("data", Split::Training);
+        assert_eq!(paths.len(), 5);
+        assert_eq!(paths[4], Path::new("data").join("data_batch_5.bin"));
+        assert_eq!(
+            Dataset::Cifar10.paths("data", Split::Test),
+            vec![Path::new("data").join("test_batch.bin")]
+        );
+    }
+
+    #[test]
+    fn emnist_letters() {
+        let root = env::temp_dir().join(format!("ml-rs-emnist-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let dataset = Dataset::Emnist(EmnistSplit::Letters);
+        let paths = dataset.paths(&root, Split::Training);
+        let (images_path, labels_path) = (&paths[0], &paths[1]);
+        // A 2x3 image, stored transposed.
+        let stored = Images::new((3, 2), vec![1, 4, 2, 5, 3, 6], vec![26]);
+        write_images(images_path, &stored).unwrap();
+        write_labels(labels_path, &stored.labels).unwrap();
+        let loaded = dataset.load(&root, Split::Training);
+
+        write_labels(labels_path, &[0]).unwrap();
+        let out_of_range = dataset.load(&root, Split::Training);
+        fs::remove_dir_all(&root).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.dimensions, (2, 3));
+        assert_eq!(loaded.image(0), &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(loaded.labels, vec![25], "The letters start at 0");
+        assert_eq!(dataset.label_names()[25], "Z");
+        assert!(out_of_range.is_err(), "0 is not a letter");
+    }
+
+    #[test]
+    fn cifar() {
+        let root = env::temp_dir().join(format!("ml-rs-cifar-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        // One record for each batch, where the label is the batch number, and every
+        // pixel is red=label, green=label+1 and blue=label+2.
+        let record = |label: u8| -> Vec<u8> {
+            let mut record = vec![label];
+            for channel in 0..3 {
+                record.extend(vec![label + channel; 32 * 32]);
+            }
+            record
+        };
+        for (index, path) in Dataset::Cifar10
+            .paths(&root, Split::Training)
+            .iter()
+            .enumerate()
+        {
+            fs::write(path, record(index as u8)).unwrap();
+        }
+        let loaded = Dataset::Cifar10.load(&root, Split::Training);
+        let mapped = Dataset::Cifar10.load_mapped(&root, Split::Training);
+        fs::remove_dir_all(&root).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.dimensions, (32, 32));
+        assert_eq!(loaded.channels, 3);
+        assert_eq!(loaded.pixel_count, 3072);
+        assert_eq!(loaded.labels, vec![0, 1, 2, 3, 4]);
+        assert_eq!(&loaded.image(4)[..6], &[4, 5, 6, 4, 5, 6]);
+        assert_eq!(
+            Dataset::Cifar10.label_names()[loaded.labels[3] as usize],
+            "cat"
+        );
+        assert!(mapped.is_err());
+    }
+}
@@ -0,0 +1,647 @@
+//! An encoder and decoder for the IDX file format that the MNIST data is distributed in. IDX files
+//! store a single n-dimensional array of numbers.
+//!
+//! According to: http://yann.lecun.com/exdb/mnist/
+//!
+//! The magic number is 4 bytes. The first 2 bytes are always 0, the third byte codes
+//! the type of the data, and the fourth byte codes the number of dimensions.
+//!
+//! 0x08: unsigned byte
+//! 0x09: signed byte
+//! 0x0B: short (2 bytes)
+//! 0x0C: int (4 bytes)
+//! 0x0D: float (4 bytes)
+//! 0x0E: double (8 bytes)
+//!
+//! The sizes of each dimension follow as 4 byte integers, and then the data itself. All
+//! of the numbers are big endian.
+//!
+//! MNIST is distributed as gzip files, so files that start with the gzip or zlib magic
+//! bytes are decompressed on the fly. An IDX file always starts with two zero bytes, so
+//! there is no ambiguity.
+use crate::image_data::Error;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use flate2::bufread::{MultiGzDecoder, ZlibDecoder};
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// The type of the elements in an IDX file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdxType {
+    U8,
+    I8,
+    I16,
+    I32,
+    F32,
+    F64,
+}
+
+impl IdxType {
+    /// Look up the type from the third byte of the magic number.
+    pub fn from_code(code: u8) -> Option<IdxType> {
+        match code {
+            0x08 => Some(IdxType::U8),
+            0x09 => Some(IdxType::I8),
+            0x0B => Some(IdxType::I16),
+            0x0C => Some(IdxType::I32),
+            0x0D => Some(IdxType::F32),
+            0x0E => Some(IdxType::F64),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> u8 {
+        match self {
+            IdxType::U8 => 0x08,
+            IdxType::I8 => 0x09,
+            IdxType::I16 => 0x0B,
+            IdxType::I32 => 0x0C,
+            IdxType::F32 => 0x0D,
+            IdxType::F64 => 0x0E,
+        }
+    }
+
+    /// The number of bytes used by each element.
+    pub fn size(self) -> usize {
+        match self {
+            IdxType::U8 | IdxType::I8 => 1,
+            IdxType::I16 => 2,
+            IdxType::I32 | IdxType::F32 => 4,
+            IdxType::F64 => 8,
+        }
+    }
+}
+
+/// The elements of an IDX array, in row-major order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdxData {
+    U8(Vec<u8>),
+    I8(Vec<i8>),
+    I16(Vec<i16>),
+    I32(Vec<i32>),
+    F32(Vec<f32>),
+    F64(Vec<f64>),
+}
+
+impl IdxData {
+    pub fn element_type(&self) -> IdxType {
+        match self {
+            IdxData::U8(_) => IdxType::U8,
+            IdxData::I8(_) => IdxType::I8,
+            IdxData::I16(_) => IdxType::I16,
+            IdxData::I32(_) => IdxType::I32,
+            IdxData::F32(_) => IdxType::F32,
+            IdxData::F64(_) => IdxType::F64,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            IdxData::U8(data) => data.len(),
+            IdxData::I8(data) => data.len(),
+            IdxData::I16(data) => data.len(),
+            IdxData::I32(data) => data.len(),
+            IdxData::F32(data) => data.len(),
+            IdxData::F64(data) => data.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The start of an IDX file, which describes the array that follows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdxHeader {
+    pub element_type: IdxType,
+    pub dimensions: Vec<usize>,
+}
+
+/// The magic number of an IDX file, as a big-endian u32. The two zero bytes are
+/// followed by the type code and the number of dimensions, e.g. 0x00000803 for the
+/// MNIST images.
+pub fn magic_number(element_type: IdxType, dimension_count: usize) -> u32 {
+    (element_type.code() as u32) << 8 | dimension_count as u32
+}
+
+impl IdxHeader {
+    pub fn magic_number(&self) -> u32 {
+        magic_number(self.element_type, self.dimensions.len())
+    }
+
+    /// The number of bytes in the header itself, which is where the data starts.
+    pub fn size(&self) -> usize {
+        4 + 4 * self.dimensions.len()
+    }
+
+    /// The number of bytes of data, or None if it would overflow.
+    pub fn data_size(&self) -> Option<usize> {
+        self.dimensions
+            .iter()
+            .try_fold(self.element_type.size(), |len, size| len.checked_mul(*size))
+    }
+
+    /// The number of bytes in each item along the first dimension, such as an image,
+    /// or None if it would overflow.
+    pub fn item_data_size(&self) -> Option<usize> {
+        self.dimensions
+            .iter()
+            .skip(1)
+            .try_fold(self.element_type.size(), |len, size| len.checked_mul(*size))
+    }
+
+    /// The error for data that stops after `available` bytes.
+    fn truncated(&self, available: usize) -> Error {
+        Error::Truncated {
+            image_index: available / self.item_data_size().unwrap_or(usize::MAX).max(1),
+        }
+    }
+}
+
+/// An n-dimensional array that was read in from an IDX file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdxArray {
+    /// The size of each dimension, with the outermost first. For the MNIST images
+    /// this is (images, rows, columns).
+    pub dimensions: Vec<usize>,
+    pub data: IdxData,
+}
+
+impl IdxArray {
+    pub fn magic_number(&self) -> u32 {
+        magic_number(self.element_type(), self.dimensions.len())
+    }
+
+    pub fn element_type(&self) -> IdxType {
+        self.data.element_type()
+    }
+
+    /// The number of items along the first dimension, e.g. the number of images.
+    pub fn item_count(&self) -> usize {
+        self.dimensions.first().copied().unwrap_or(0)
+    }
+
+    /// The number of elements in each item, which is the product of every dimension
+    /// after the first.
+    pub fn item_size(&self) -> usize {
+        self.dimensions.iter().skip(1).product()
+    }
+
+    /// Take the data if it is made up of unsigned bytes.
+    pub fn into_u8(self) -> Option<Vec<u8>> {
+        match self.data {
+            IdxData::U8(data) => Some(data),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    Zlib,
+}
+
+impl Compression {
+    /// Detect the compression from the first bytes of a file.
+    fn detect(bytes: &[u8]) -> Compression {
+        match bytes {
+            [0x1f, 0x8b, ..] => Compression::Gzip,
+            // The compression method is 8 (DEFLATE), and the header is a multiple of 31.
+            [cmf, flg, ..]
+                if cmf & 0x0f == 8 && (*cmf as u16 * 256 + *flg as u16).is_multiple_of(31) =>
+            {
+                Compression::Zlib
+            }
+            _ => Compression::None,
+        }
+    }
+}
+
+/// Whether the bytes at the start of a file are from a gzip or zlib stream rather than
+/// an uncompressed IDX file.
+pub fn is_compressed(bytes: &[u8]) -> bool {
+    Compression::detect(bytes) != Compression::None
+}
+
+/// Open a data file, with an error that names the file when it doesn't exist.
+pub fn open_file(path: &Path) -> Result<File, Error> {
+    File::open(path).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => Error::MissingFile(path.to_path_buf()),
+        _ => Error::IO(err),
+    })
+}
+
+/// Read an IDX array from a file, which may be gzip or zlib compressed. The size in
+/// the header of an uncompressed file is checked against the size of the file before
+/// any of the data is read. There can't be anything after the data.
+pub fn read_idx_file(path: impl AsRef<Path>) -> Result<IdxArray, Error> {
+    let mut file = BufReader::new(open_file(path.as_ref())?);
+    match Compression::detect(file.fill_buf()?) {
+        Compression::None => {
+            let file_size = file.get_ref().metadata()?.len();
+            read_entire_idx(&mut file, Some(file_size))
+        }
+        Compression::Gzip => read_entire_idx(&mut MultiGzDecoder::new(file), None),
+        Compression::Zlib => read_entire_idx(&mut ZlibDecoder::new(file), None),
+    }
+}
+
+fn read_entire_idx(reader: &mut impl Read, file_size: Option<u64>) -> Result<IdxArray, Error> {
+    let array = read_idx_sized(reader, file_size)?;
+    if reader.read(&mut [0])? != 0 {
+        return Err(Error::Message(
+            "The IDX file has more data than its header describes.",
+        ));
+    }
+    Ok(array)
+}
+
+/// Decode the magic number and dimensions at the start of an IDX file.
+pub fn read_idx_header(reader: &mut impl Read) -> Result<IdxHeader, Error> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic[0] != 0 || magic[1] != 0 {
+        return Err(Error::Message(
+            "The IDX data's magic number is not correct.",
+        ));
+    }
+    let element_type = IdxType::from_code(magic[2])
+        .ok_or(Error::Message("The IDX data has an unknown element type."))?;
+
+    let mut dimensions = Vec::with_capacity(magic[3] as usize);
+    for _ in 0..magic[3] {
+        dimensions.push(reader.read_u32::<BigEndian>()? as usize);
+    }
+    Ok(IdxHeader {
+        element_type,
+        dimensions,
+    })
+}
+
+/// Decode an IDX array from a reader.
+pub fn read_idx(reader: &mut impl Read) -> Result<IdxArray, Error> {
+    read_idx_sized(reader, None)
+}
+
+/// Decode an IDX array, where the total size of the data is known ahead of time for
+/// files. The header can claim any size, so it is checked before allocating memory
+/// for the data. When the size isn't known, the memory only grows as the data is
+/// actually read.
+fn read_idx_sized(reader: &mut impl Read, file_size: Option<u64>) -> Result<IdxArray, Error> {
+    let header = read_idx_header(reader)?;
+    let byte_len = header
+        .data_size()
+        .ok_or(Error::Message("The IDX dimensions are too large."))?;
+
+    let mut bytes = match file_size {
+        Some(file_size) => {
+            let available = file_size.saturating_sub(header.size() as u64);
+            if available < byte_len as u64 {
+                return Err(header.truncated(available as usize));
+            }
+            Vec::with_capacity(byte_len)
+        }
+        None => Vec::new(),
+    };
+    reader.take(byte_len as u64).read_to_end(&mut bytes)?;
+    // Double check that what we read in agrees with the header.
+    if bytes.len() != byte_len {
+        return Err(header.truncated(bytes.len()));
+    }
+
+    let IdxHeader {
+        element_type,
+        dimensions,
+    } = header;
+    let data = match element_type {
+        IdxType::U8 => IdxData::U8(bytes),
+        IdxType::I8 => IdxData::I8(bytes.iter().map(|byte| *byte as i8).collect()),
+        IdxType::I16 => IdxData::I16(decode(&bytes, i16::from_be_bytes)),
+        IdxType::I32 => IdxData::I32(decode(&bytes, i32::from_be_bytes)),
+        IdxType::F32 => IdxData::F32(decode(&bytes, f32::from_be_bytes)),
+        IdxType::F64 => IdxData::F64(decode(&bytes, f64::from_be_bytes)),
+    };
+
+    Ok(IdxArray { dimensions, data })
+}
+
+/// Write an IDX array to a file, replacing it if it exists.
+pub fn write_idx_file(path: impl AsRef<Path>, array: &IdxArray) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write_idx(&mut file, array)?;
+    file.flush()
+}
+
+/// Encode an IDX array. The dimensions must fit in the header, and agree with the
+/// length of the data.
+pub fn write_idx(writer: &mut impl Write, array: &IdxArray) -> io::Result<()> {
+    let invalid = |message| io::Error::new(io::ErrorKind::InvalidInput, message);
+    if array.dimensions.len() > u8::MAX as usize {
+        return Err(invalid("An IDX array can have at most 255 dimensions."));
+    }
+    if array
+        .dimensions
+        .iter()
+        .any(|size| *size > u32::MAX as usize)
+    {
+        return Err(invalid("An IDX dimension does not fit in 32 bits."));
+    }
+    let len = array
+        .dimensions
+        .iter()
+        .try_fold(1usize, |len, size| len.checked_mul(*size));
+    if len != Some(array.data.len()) {
+        return Err(invalid(
+            "The IDX dimensions do not match the length of the data.",
+        ));
+    }
+
+    writer.write_all(&[
+        0,
+        0,
+        array.element_type().code(),
+        array.dimensions.len() as u8,
+    ])?;
+    for size in &array.dimensions {
+        writer.write_u32::<BigEndian>(*size as u32)?;
+    }
+    match &array.data {
+        IdxData::U8(data) => writer.write_all(data),
+        IdxData::I8(data) => data.iter().try_for_each(|value| writer.write_i8(*value)),
+        IdxData::I16(data) => data
+            .iter()
+            .try_for_each(|value| writer.write_i16::<BigEndian>(*value)),
+        IdxData::I32(data) => data
+            .iter()
+            .try_for_each(|value| writer.write_i32::<BigEndian>(*value)),
+        IdxData::F32(data) => data
+            .iter()
+            .try_for_each(|value| writer.write_f32::<BigEndian>(*value)),
+        IdxData::F64(data) => data
+            .iter()
+            .try_for_each(|value| writer.write_f64::<BigEndian>(*value)),
+    }
+}
+
+/// Convert every N bytes into an element.
+fn decode<T, const N: usize>(bytes: &[u8], from_be_bytes: fn([u8; N]) -> T) -> Vec<T> {
+    bytes
+        .chunks_exact(N)
+        .map(|chunk| from_be_bytes(chunk.try_into().unwrap()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(code: u8, dimensions: &[u32]) -> Vec<u8> {
+        let mut bytes = vec![0, 0, code, dimensions.len() as u8];
+        for size in dimensions {
+            bytes.extend_from_slice(&size.to_be_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn unsigned_bytes() {
+        let mut bytes = header(0x08, &[2, 1, 3]);
+        bytes.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+        let array = read_idx(&mut bytes.as_slice()).unwrap();
+        assert_eq!(array.dimensions, vec![2, 1, 3]);
+        assert_eq!(array.item_count(), 2);
+        assert_eq!(array.item_size(), 3);
+        assert_eq!(array.into_u8(), Some(vec![1, 2, 3, 4, 5, 6]));
+    }
+
+    #[test]
+    fn element_types() {
+        let mut bytes = header(0x09, &[2]);
+        bytes.extend_from_slice(&[0xff, 0x7f]);
+        assert_eq!(
+            read_idx(&mut bytes.as_slice()).unwrap().data,
+            IdxData::I8(vec![-1, 127])
+        );
+
+        let mut bytes = header(0x0B, &[2]);
+        bytes.extend_from_slice(&[0xff, 0xfe, 0x01, 0x00]);
+        assert_eq!(
+            read_idx(&mut bytes.as_slice()).unwrap().data,
+            IdxData::I16(vec![-2, 256])
+        );
+
+        let mut bytes = header(0x0C, &[1]);
+        bytes.extend_from_slice(&(-70000i32).to_be_bytes());
+        assert_eq!(
+            read_idx(&mut bytes.as_slice()).unwrap().data,
+            IdxData::I32(vec![-70000])
+        );
+
+        let mut bytes = header(0x0D, &[1, 1]);
+        bytes.extend_from_slice(&1.5f32.to_be_bytes());
+        assert_eq!(
+            read_idx(&mut bytes.as_slice()).unwrap().data,
+            IdxData::F32(vec![1.5])
+        );
+
+        let mut bytes = header(0x0E, &[2]);
+        bytes.extend_from_slice(&0.25f64.to_be_bytes());
+        bytes.extend_from_slice(&(-8.0f64).to_be_bytes());
+        let array = read_idx(&mut bytes.as_slice()).unwrap();
+        assert_eq!(array.element_type(), IdxType::F64);
+        assert_eq!(array.data, IdxData::F64(vec![0.25, -8.0]));
+    }
+
+    #[test]
+    fn scalars() {
+        // Zero dimensions is a single element.
+        let mut bytes = header(0x08, &[]);
+        bytes.push(42);
+        let array = read_idx(&mut bytes.as_slice()).unwrap();
+        assert_eq!(array.dimensions, Vec::<usize>::new());
+        assert_eq!(array.data, IdxData::U8(vec![42]));
+    }
+
+    #[test]
+    fn round_trip() {
+        let arrays = [
+            IdxArray {
+                dimensions: vec![2, 2],
+                data: IdxData::U8(vec![0, 1, 254, 255]),
+            },
+            IdxArray {
+                dimensions: vec![3],
+                data: IdxData::I8(vec![-128, 0, 127]),
+            },
+            IdxArray {
+                dimensions: vec![1, 2],
+                data: IdxData::I16(vec![-300, 300]),
+            },
+            IdxArray {
+                dimensions: vec![2, 1, 1],
+                data: IdxData::I32(vec![i32::MIN, i32::MAX]),
+            },
+            IdxArray {
+                dimensions: vec![1],
+                data: IdxData::F32(vec![-0.5]),
+            },
+            IdxArray {
+                dimensions: vec![],
+                data: IdxData::F64(vec![std::f64::consts::PI]),
+            },
+        ];
+        for array in &arrays {
+            let mut bytes = Vec::new();
+            write_idx(&mut bytes, array).unwrap();
+            assert_eq!(bytes[2], array.element_type().code());
+            assert_eq!(&read_idx(&mut bytes.as_slice()).unwrap(), array);
+        }
+
+        let mismatched = IdxArray {
+            dimensions: vec![3],
+            data: IdxData::U8(vec![1, 2]),
+        };
+        assert!(write_idx(&mut Vec::new(), &mismatched).is_err());
+    }
+
+    #[test]
+    fn compressed_files() {
+        use flate2::write::{GzEncoder, ZlibEncoder};
+
+        let array = IdxArray {
+            dimensions: vec![2, 3],
+            data: IdxData::U8(vec![1, 2, 3, 4, 5, 6]),
+        };
+        let mut bytes = Vec::new();
+        write_idx(&mut bytes, &array).unwrap();
+
+        let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&bytes).unwrap();
+        let mut zlib = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        zlib.write_all(&bytes).unwrap();
+
+        for (name, contents) in [
+            ("raw", bytes.clone()),
+            ("gz", gzip.finish().unwrap()),
+            ("zlib", zlib.finish().unwrap()),
+        ] {
+            let path =
+                std::env::temp_dir().join(format!("ml-rs-idx-{}-{}", name, std::process::id()));
+            std::fs::write(&path, &contents).unwrap();
+            let read = read_idx_file(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(read.unwrap(), array, "Reading a {} file", name);
+        }
+    }
+
+    #[test]
+    fn bad_data() {
+        let mut bytes = header(0x08, &[4]);
+        bytes.extend_from_slice(&[1, 2, 3]);
+        match read_idx(&mut bytes.as_slice()) {
+            Err(Error::Truncated { image_index }) => assert_eq!(image_index, 3),
+            other => panic!("Expected the data to be truncated, got {:?}", other),
+        }
+        bytes = header(0x08, &[2, 3]);
+        bytes.extend_from_slice(&[1, 2, 3, 4]);
+        match read_idx(&mut bytes.as_slice()) {
+            Err(Error::Truncated { image_index }) => assert_eq!(image_index, 1),
+            other => panic!("Expected the data to be truncated, got {:?}", other),
+        }
+
+        let bytes = header(0x0A, &[0]);
+        assert!(read_idx(&mut bytes.as_slice()).is_err(), "Unknown type");
+
+        let mut bytes = header(0x08, &[0]);
+        bytes[0] = 1;
+        assert!(read_idx(&mut bytes.as_slice()).is_err(), "Bad magic");
+    }
+
+    /// Read the bytes as if they were a whole uncompressed file.
+    fn read_file_bytes(bytes: &[u8]) -> Result<IdxArray, Error> {
+        read_entire_idx(&mut &bytes[..], Some(bytes.len() as u64))
+    }
+
+    #[test]
+    fn file_size() {
+        let mut bytes = header(0x08, &[2, 3]);
+        bytes.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+        assert!(read_file_bytes(&bytes).is_ok());
+
+        bytes.push(7);
+        assert!(
+            read_file_bytes(&bytes).is_err(),
+            "Extra data after the array"
+        );
+
+        let mut bytes = header(0x0D, &[1000, 1000]);
+        bytes.extend_from_slice(&[0; 4 * 1500]);
+        match read_file_bytes(&bytes) {
+            Err(Error::Truncated { image_index }) => assert_eq!(image_index, 1),
+            other => panic!("Expected the data to be truncated, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn corrupted_headers() {
+        let max = u32::MAX;
+        // Headers that claim far more data than there is, or sizes that overflow.
+        let hostile = [
+            header(0x08, &[max]),
+            header(0x08, &[max, max, max]),
+            header(0x0E, &[max, max, max, max]),
+            header(0x0B, &[max, max]),
+            header(0x0C, &[max / 2, 2]),
+            header(0x08, &[1; 255]),
+            header(0x08, &[max; 255]),
+            vec![0, 0, 0x08, 3, 0, 0],
+            vec![0, 0, 0x08],
+            vec![],
+        ];
+        for bytes in &hostile {
+            assert!(
+                read_file_bytes(bytes).is_err(),
+                "{:?}",
+                &bytes[..bytes.len().min(12)]
+            );
+            assert!(read_idx(&mut bytes.as_slice()).is_err());
+        }
+        assert_eq!(
+            read_file_bytes(&header(0x08, &[0, max, max]))
+                .unwrap()
+                .item_count(),
+            0,
+            "No items is valid, even if each item would be huge"
+        );
+
+        // Randomly corrupt the header and truncate a valid file. Whatever the result is,
+        // it must not panic, and anything that is read must agree with its dimensions.
+        let mut valid = header(0x08, &[3, 2, 2]);
+        valid.extend(1..=12);
+        let mut random = crate::random::Random::new(45);
+        for case in 0..2000 {
+            let mut bytes = valid.clone();
+            for _ in 0..1 + random.below(3) {
+                let index = random.below(16);
+                bytes[index] = match random.below(3) {
+                    0 => random.below(256) as u8,
+                    1 => 0xff,
+                    _ => 0,
+                };
+            }
+            if case % 4 == 0 {
+                bytes.truncate(random.below(bytes.len() + 1));
+            }
+            let results = [read_file_bytes(&bytes), read_idx(&mut bytes.as_slice())];
+            for array in results.iter().flatten() {
+                let len: usize = array.dimensions.iter().product();
+                assert_eq!(array.data.len(), len, "{:?}", bytes);
+            }
+        }
+    }
+}
@@ -1,17 +1,105 @@
 extern crate byteorder;
 extern crate term_painter;
 
-use self::byteorder::{BigEndian, ReadBytesExt};
+use self::byteorder::{BigEndian, WriteBytesExt};
+use crate::idx::{
+    is_compressed, magic_number, open_file, read_idx_file, read_idx_header, write_idx_file,
+    IdxArray, IdxData, IdxType,
+};
+use crate::network::Prediction;
+use crate::random::Random;
+use crate::render::{paint, render_grid, render_image, PaletteColor, RenderMode};
+use memmap2::Mmap;
 use std::convert::From;
+use std::env;
+use std::error;
+use std::fmt;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The magic number of an MNIST image file: unsigned bytes in 3 dimensions.
+const IMAGES_MAGIC: u32 = 0x0000_0803;
+/// The magic number of an image file with a fourth dimension for the color channels.
+const COLOR_IMAGES_MAGIC: u32 = 0x0000_0804;
+/// The magic number of an MNIST label file: unsigned bytes in 1 dimension.
+const LABELS_MAGIC: u32 = 0x0000_0801;
 
 // Collect all potential error messages here:
 #[derive(Debug)]
 pub enum Error {
-    Message(&'static str), // No reason to over-complicate with specific enums.
+    /// Any other problem with the data, described by the message.
+    Message(&'static str),
     IO(io::Error),
+    /// A data file does not exist at this path.
+    MissingFile(PathBuf),
+    /// The file starts with the wrong magic number, such as a label file that was given
+    /// in place of an image file.
+    BadMagic {
+        expected: u32,
+        found: u32,
+        path: PathBuf,
+    },
+    /// The file ends part way through the data. This is the index of the first image
+    /// (or label) that is incomplete.
+    Truncated {
+        image_index: usize,
+    },
+    /// The image and label files don't have the same number of items.
+    LabelCountMismatch {
+        images: usize,
+        labels: usize,
+    },
+    /// The images are not the (rows, columns) that were expected, such as test images
+    /// that don't match the training images.
+    DimensionMismatch {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Message(message) => write!(f, "{}", message),
+            Error::IO(err) => write!(f, "{}", err),
+            Error::MissingFile(path) => write!(f, "The file {} does not exist.", path.display()),
+            Error::BadMagic {
+                expected,
+                found,
+                path,
+            } => write!(
+                f,
+                "The file {} starts with the magic number 0x{:08x}, but 0x{:08x} was expected.",
+                path.display(),
+                found,
+                expected
+            ),
+            Error::Truncated { image_index } => {
+                write!(f, "The data ends part way through image {}.", image_index)
+            }
+            Error::LabelCountMismatch { images, labels } => {
+                write!(f, "There are {} images, but {} labels.", images, labels)
+            }
+            Error::DimensionMismatch { expected, found } => write!(
+                f,
+                "The images are {}x{}, but {}x{} images were expected.",
+                found.0, found.1, expected.0, expected.1
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::IO(err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
 impl From<io::Error> for Error {
@@ -22,15 +110,258 @@ impl From<io::Error> for Error {
 
 pub type ImageData = Vec<u8>;
 
-#[derive(Debug)]
+/// The pixels of every image, stored one image after another in a single buffer.
+#[derive(Debug, Clone)]
+enum Pixels {
+    Owned(Vec<u8>),
+    /// The pixels are paged in lazily from a memory-mapped IDX file by the operating
+    /// system. They start at the offset, which is just after the header.
+    Mapped {
+        map: Arc<Mmap>,
+        offset: usize,
+        len: usize,
+    },
+}
+
+impl Pixels {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Pixels::Owned(pixels) => pixels,
+            Pixels::Mapped { map, offset, len } => &map[*offset..*offset + *len],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Images {
     pub dimensions: (usize, usize),
+    /// The number of color channels, which is 1 for grayscale and 3 for RGB. The
+    /// channels of each pixel are stored together, e.g. RGBRGBRGB.
+    pub channels: usize,
+    /// The number of bytes in each image, which is rows * columns * channels.
     pub pixel_count: usize,
-    pub list: Vec<ImageData>,
+    pixels: Pixels,
+    count: usize,
     pub labels: Vec<u8>,
 }
 
-fn read_in_images(path: &str) -> Result<Images, Error> {
+impl Images {
+    /// Create grayscale images from a buffer that holds the pixels of every image one
+    /// after the other. The dimensions are (rows, columns).
+    pub fn new(dimensions: (usize, usize), pixels: Vec<u8>, labels: Vec<u8>) -> Images {
+        Images::with_channels(dimensions, 1, pixels, labels)
+    }
+
+    /// Create images with the given number of interleaved color channels.
+    pub fn with_channels(
+        dimensions: (usize, usize),
+        channels: usize,
+        pixels: Vec<u8>,
+        labels: Vec<u8>,
+    ) -> Images {
+        let pixel_count = dimensions
+            .0
+            .checked_mul(dimensions.1)
+            .and_then(|pixels| pixels.checked_mul(channels))
+            .expect("The image dimensions are too large.");
+        assert!(
+            pixel_count > 0 || pixels.is_empty(),
+            "Images with no pixels can not have pixel data."
+        );
+        assert_eq!(
+            pixels.len() % pixel_count.max(1),
+            0,
+            "The pixel data is not a whole number of images."
+        );
+        Images {
+            dimensions,
+            channels,
+            pixel_count,
+            count: pixels.len() / pixel_count.max(1),
+            pixels: Pixels::Owned(pixels),
+            labels,
+        }
+    }
+
+    /// Create images from a separate list of pixels for each image, which are copied
+    /// into a single buffer.
+    pub fn from_list(dimensions: (usize, usize), list: Vec<ImageData>, labels: Vec<u8>) -> Images {
+        let pixel_count = dimensions.0 * dimensions.1;
+        assert!(
+            list.iter().all(|image| image.len() == pixel_count),
+            "An image does not match the dimensions."
+        );
+        let mut images = Images::new(dimensions, list.concat(), labels);
+        images.count = list.len();
+        images
+    }
+
+    /// The number of images.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The pixels of a single image. This panics if the index is out of bounds.
+    pub fn image(&self, index: usize) -> &[u8] {
+        self.get(index).expect("The image index is out of bounds.")
+    }
+
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        if index < self.count {
+            Some(&self.pixels()[index * self.pixel_count..(index + 1) * self.pixel_count])
+        } else {
+            None
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        (0..self.count).map(move |index| self.image(index))
+    }
+
+    /// The pixels of every image, one after the other.
+    pub fn pixels(&self) -> &[u8] {
+        self.pixels.as_slice()
+    }
+
+    /// A grayscale copy of an image, for displaying color images in the terminal.
+    pub fn grayscale(&self, index: usize) -> Vec<u8> {
+        grayscale(self.image(index), self.channels)
+    }
+
+    /// Whether the pixels are read from a memory-mapped file rather than held in memory.
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.pixels, Pixels::Mapped { .. })
+    }
+
+    /// Add an image to the end. Memory-mapped images are copied into memory first.
+    pub fn push(&mut self, image: &[u8], label: u8) {
+        assert_eq!(
+            image.len(),
+            self.pixel_count,
+            "The image does not match the dimensions."
+        );
+        if let Pixels::Mapped { .. } = self.pixels {
+            self.pixels = Pixels::Owned(self.pixels().to_vec());
+        }
+        if let Pixels::Owned(ref mut pixels) = self.pixels {
+            pixels.extend_from_slice(image);
+        }
+        self.count += 1;
+        self.labels.push(label);
+    }
+
+    /// Copy the images at the indexes, in that order, along with their labels.
+    pub fn subset(&self, indexes: &[usize]) -> Images {
+        let mut pixels = Vec::with_capacity(indexes.len() * self.pixel_count);
+        for &index in indexes {
+            pixels.extend_from_slice(self.image(index));
+        }
+        let labels = indexes.iter().map(|index| self.labels[*index]).collect();
+        Images::with_channels(self.dimensions, self.channels, pixels, labels)
+    }
+
+    /// Shuffle the images and their labels into a random order that is the same for
+    /// the same seed.
+    pub fn shuffle(&mut self, seed: u64) {
+        let mut order: Vec<usize> = (0..self.len()).collect();
+        Random::new(seed).shuffle(&mut order);
+        *self = self.subset(&order);
+    }
+
+    /// Keep only the images with one of the labels.
+    pub fn filter_labels(&self, labels: &[u8]) -> Images {
+        let indexes: Vec<usize> = (0..self.len())
+            .filter(|index| labels.contains(&self.labels[*index]))
+            .collect();
+        self.subset(&indexes)
+    }
+
+    /// Join two sets of images with the same dimensions and channels.
+    pub fn concat(&self, other: &Images) -> Images {
+        assert!(
+            self.dimensions == other.dimensions && self.channels == other.channels,
+            "The images do not have the same dimensions."
+        );
+        let mut pixels = Vec::with_capacity(self.pixels().len() + other.pixels().len());
+        pixels.extend_from_slice(self.pixels());
+        pixels.extend_from_slice(other.pixels());
+        let labels = self.labels.iter().chain(&other.labels).copied().collect();
+        Images::with_channels(self.dimensions, self.channels, pixels, labels)
+    }
+
+    /// The number of images with each label, indexed by the label.
+    pub fn class_histogram(&self) -> Vec<usize> {
+        let class_count = self.labels.iter().max().map_or(0, |max| *max as usize + 1);
+        let mut histogram = vec![0; class_count];
+        for label in &self.labels {
+            histogram[*label as usize] += 1;
+        }
+        histogram
+    }
+
+    /// Randomly split the images into parts with the given fractions of the images,
+    /// e.g. `[0.8, 0.1, 0.1]` for training, validation and test sets. If the fractions
+    /// add up to less than 1, the rest of the images are left out.
+    pub fn random_split(&self, fractions: &[f64], seed: u64) -> Vec<Images> {
+        let mut order: Vec<usize> = (0..self.len()).collect();
+        Random::new(seed).shuffle(&mut order);
+        split_indexes(&order, fractions)
+            .iter()
+            .map(|indexes| self.subset(indexes))
+            .collect()
+    }
+
+    /// Split the images like `random_split`, but so that every part has the same
+    /// proportion of each label as the whole set.
+    pub fn stratified_split(&self, fractions: &[f64], seed: u64) -> Vec<Images> {
+        let mut random = Random::new(seed);
+        let mut parts = vec![Vec::new(); fractions.len()];
+        for label in 0..self.class_histogram().len() {
+            let mut class: Vec<usize> = (0..self.len())
+                .filter(|index| self.labels[*index] as usize == label)
+                .collect();
+            random.shuffle(&mut class);
+            for (part, indexes) in parts.iter_mut().zip(split_indexes(&class, fractions)) {
+                part.extend(indexes);
+            }
+        }
+        parts
+            .iter_mut()
+            .map(|part| {
+                // Mix the classes back together.
+                random.shuffle(part);
+                self.subset(part)
+            })
+            .collect()
+    }
+}
+
+/// Cut the indexes into consecutive parts with the given fractions of the length.
+/// The boundaries are rounded from the running total of the fractions, so the parts
+/// add up to the whole when the fractions add up to 1.
+fn split_indexes<'a>(indexes: &'a [usize], fractions: &[f64]) -> Vec<&'a [usize]> {
+    assert!(
+        fractions.iter().all(|fraction| *fraction >= 0.0)
+            && fractions.iter().sum::<f64>() <= 1.0 + 1e-9,
+        "The split fractions must be positive and add up to at most 1."
+    );
+    let mut parts = Vec::with_capacity(fractions.len());
+    let mut total = 0.0;
+    let mut start = 0;
+    for fraction in fractions {
+        total += fraction;
+        let end = ((total * indexes.len() as f64).round() as usize).min(indexes.len());
+        parts.push(&indexes[start..end]);
+        start = end;
+    }
+    parts
+}
+
+fn read_in_images(path: impl AsRef<Path>) -> Result<Images, Error> {
     /*
      * According to: http://yann.lecun.com/exdb/mnist/
      *
@@ -44,50 +375,101 @@ fn read_in_images(path: &str) -> Result<Images, Error> {
      * ........
      * xxxx     unsigned byte   ??               pixel
      */
-    match File::open(path) {
-        Ok(ref mut file) => {
-            let magic_number = file.read_i32::<BigEndian>()?;
-            let number_of_images = file.read_i32::<BigEndian>()? as usize;
-            let number_of_rows = file.read_i32::<BigEndian>()? as usize;
-            let number_of_cols = file.read_i32::<BigEndian>()? as usize;
-            let bytes_per_image = number_of_rows * number_of_cols;
-
-            if magic_number != 2051 {
-                return Err(Error::Message(
-                    "The image data's magic number is not correct.",
-                ));
-            }
-
-            let mut images = Vec::with_capacity(number_of_images as usize);
-            for _ in 0..number_of_images {
-                // Create the new vector.
-                let mut image: Vec<u8> = Vec::with_capacity(bytes_per_image as usize);
+    let path = path.as_ref();
+    let array = read_idx_file(path)?;
+    let (number_of_images, number_of_rows, number_of_cols, channels) =
+        image_shape(array.element_type(), &array.dimensions, path)?;
+    let mut images = Images::with_channels(
+        (number_of_rows, number_of_cols),
+        channels,
+        array.into_u8().unwrap_or_default(),
+        Vec::new(),
+    );
+    // Images with no pixels still have a count.
+    images.count = number_of_images;
+    Ok(images)
+}
 
-                // Read the data in from the file.
-                file.take(bytes_per_image as u64).read_to_end(&mut image)?;
+/// Memory map an MNIST-style image file, so that the pixels are only read from disk as
+/// they are used. Compressed files can't be mapped, so they are read into memory.
+fn map_in_images(path: impl AsRef<Path>) -> Result<Images, Error> {
+    let path = path.as_ref();
+    let file = open_file(path)?;
+    // This is only sound as long as the file isn't modified while it is mapped, which
+    // is a reasonable expectation for a dataset.
+    let map = unsafe { Mmap::map(&file)? };
+    if is_compressed(&map) {
+        return read_in_images(path);
+    }
 
-                // Double check that what we read in agrees with the header.
-                if image.len() != bytes_per_image {
-                    return Err(Error::Message(
-                        "An image being read in was truncated and not the expected length",
-                    ));
-                }
+    let header = read_idx_header(&mut &map[..])?;
+    let (number_of_images, number_of_rows, number_of_cols, channels) =
+        image_shape(header.element_type, &header.dimensions, path)?;
+    let pixel_count = number_of_rows * number_of_cols * channels;
+    let len = header
+        .data_size()
+        .ok_or(Error::Message("The IDX dimensions are too large."))?;
+    let end = header
+        .size()
+        .checked_add(len)
+        .ok_or(Error::Message("The IDX dimensions are too large."))?;
+    if map.len() < end {
+        return Err(Error::Truncated {
+            image_index: map.len().saturating_sub(header.size()) / pixel_count.max(1),
+        });
+    }
+    if map.len() > end {
+        return Err(Error::Message(
+            "The IDX file has more data than its header describes.",
+        ));
+    }
 
-                images.push(image);
-            }
+    Ok(Images {
+        dimensions: (number_of_rows, number_of_cols),
+        channels,
+        pixel_count,
+        count: number_of_images,
+        pixels: Pixels::Mapped {
+            offset: header.size(),
+            len,
+            map: Arc::new(map),
+        },
+        labels: Vec::new(),
+    })
+}
 
-            Ok(Images {
-                dimensions: (number_of_rows, number_of_cols),
-                pixel_count: bytes_per_image,
-                list: images,
-                labels: Vec::new(),
+/// Get the (images, rows, columns, channels) of an IDX image file. MNIST only has the
+/// first three dimensions, but color images have a fourth. The size of an image is
+/// checked to fit in memory, even if there are no images.
+fn image_shape(
+    element_type: IdxType,
+    dimensions: &[usize],
+    path: &Path,
+) -> Result<(usize, usize, usize, usize), Error> {
+    let shape = match (magic_number(element_type, dimensions.len()), dimensions) {
+        (IMAGES_MAGIC, &[images, rows, cols]) => (images, rows, cols, 1),
+        (COLOR_IMAGES_MAGIC, &[images, rows, cols, channels]) => (images, rows, cols, channels),
+        (found, _) => {
+            return Err(Error::BadMagic {
+                expected: IMAGES_MAGIC,
+                found,
+                path: path.to_path_buf(),
             })
         }
-        Err(err) => Err(Error::IO(err)),
+    };
+    let (_, rows, cols, channels) = shape;
+    if channels != 1 && channels != 3 {
+        return Err(Error::Message(
+            "Images can only have 1 or 3 color channels.",
+        ));
     }
+    rows.checked_mul(cols)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .ok_or(Error::Message("The image dimensions are too large."))?;
+    Ok(shape)
 }
 
-fn read_in_labels(path: &str) -> Result<Vec<u8>, Error> {
+fn read_in_labels(path: impl AsRef<Path>) -> Result<Vec<u8>, Error> {
     /*
      * According to: http://yann.lecun.com/exdb/mnist/
      *
@@ -99,109 +481,1094 @@ fn read_in_labels(path: &str) -> Result<Vec<u8>, Error> {
      * ........
      * xxxx     unsigned byte   ??               label
      */
-    let mut file = File::open(path)?;
-
-    // Get the header data.
-    let magic_number = file.read_i32::<BigEndian>()?;
-    let number_of_items = file.read_i32::<BigEndian>()? as usize;
+    let path = path.as_ref();
+    let array = read_idx_file(path)?;
 
     // Assert that the header makes sense.
-    if magic_number != 2049 {
-        return Err(Error::Message(
-            "The image data's magic number is not correct.",
-        ));
+    let found = array.magic_number();
+    match array.into_u8() {
+        Some(labels) if found == LABELS_MAGIC => Ok(labels),
+        _ => Err(Error::BadMagic {
+            expected: LABELS_MAGIC,
+            found,
+            path: path.to_path_buf(),
+        }),
     }
+}
 
-    // Slurp out the labels.
-    let mut labels = Vec::with_capacity(number_of_items);
-    file.read_to_end(&mut labels)?;
+/// Check that there is a label for every image.
+fn check_label_count(images: &Images, labels: &[u8]) -> Result<(), Error> {
+    if images.len() != labels.len() {
+        return Err(Error::LabelCountMismatch {
+            images: images.len(),
+            labels: labels.len(),
+        });
+    }
+    Ok(())
+}
 
-    // Verify the length
-    if labels.len() != number_of_items {
-        return Err(Error::Message(
-            "The data read in for labels is not the correct length as stated by the header.",
-        ));
+/// Check that the images have the dimensions that were expected, such as the
+/// dimensions that a network was trained on.
+pub fn check_dimensions(images: &Images, expected: (usize, usize)) -> Result<(), Error> {
+    if images.dimensions != expected {
+        return Err(Error::DimensionMismatch {
+            expected,
+            found: images.dimensions,
+        });
+    }
+    Ok(())
+}
+
+/// Write the images to an IDX file in the same format as the MNIST images, so that
+/// they can be read back in by any MNIST loader. Color images have a fourth dimension
+/// for the channels. The labels are not written, see `write_labels`.
+pub fn write_images(path: impl AsRef<Path>, images: &Images) -> io::Result<()> {
+    let (rows, cols) = images.dimensions;
+    let mut dimensions = vec![images.len(), rows, cols];
+    if images.channels != 1 {
+        dimensions.push(images.channels);
+    }
+    write_idx_file(
+        path,
+        &IdxArray {
+            dimensions,
+            data: IdxData::U8(images.pixels().to_vec()),
+        },
+    )
+}
+
+/// Write the labels to an IDX file in the same format as the MNIST labels.
+pub fn write_labels(path: impl AsRef<Path>, labels: &[u8]) -> io::Result<()> {
+    write_idx_file(
+        path,
+        &IdxArray {
+            dimensions: vec![labels.len()],
+            data: IdxData::U8(labels.to_vec()),
+        },
+    )
+}
+
+/// Draw the image as text for the terminal, followed by its label.
+pub fn output_image(images: &Images, index: usize, mode: RenderMode) -> String {
+    let mut string = render_image(
+        images.image(index),
+        images.dimensions,
+        images.channels,
+        mode,
+    );
+    string.push_str(&format!(
+        "\n^ This image is labeled \"{:?}\"\n",
+        images.labels[index]
+    ));
+    string
+}
+
+/// Draw several images side by side, `columns` to a row, each captioned with its label.
+/// When predictions are given, one for each index, the captions show the predicted
+/// label and its probability as well, and misclassified images are captioned in red.
+pub fn output_image_grid(
+    images: &Images,
+    indexes: &[usize],
+    predictions: Option<&[Prediction]>,
+    columns: usize,
+    mode: RenderMode,
+) -> String {
+    if let Some(predictions) = predictions {
+        assert_eq!(
+            predictions.len(),
+            indexes.len(),
+            "There must be a prediction for every image."
+        );
+    }
+    let cells: Vec<(String, String)> = indexes
+        .iter()
+        .enumerate()
+        .map(|(cell, &index)| {
+            let image = render_image(
+                images.image(index),
+                images.dimensions,
+                images.channels,
+                mode,
+            );
+            let label = images.labels[index];
+            let caption = match predictions.map(|predictions| predictions[cell]) {
+                Some(prediction) => {
+                    let caption = format!(
+                        "true {} pred {} {:.0}%",
+                        label,
+                        prediction.label,
+                        prediction.probability * 100.0
+                    );
+                    if prediction.label == label as usize {
+                        caption
+                    } else {
+                        paint(&caption, PaletteColor::RED)
+                    }
+                }
+                None => format!("label {}", label),
+            };
+            (image, caption)
+        })
+        .collect();
+    render_grid(&cells, columns)
+}
+
+/// Write a grayscale image in the binary PGM format, which most image viewers can
+/// open. The dimensions are (rows, columns).
+///
+/// http://netpbm.sourceforge.net/doc/pgm.html
+pub fn write_pgm(
+    writer: &mut impl Write,
+    dimensions: (usize, usize),
+    pixels: &[u8],
+) -> io::Result<()> {
+    let (rows, cols) = dimensions;
+    assert_eq!(
+        rows * cols,
+        pixels.len(),
+        "The pixels do not match the dimensions."
+    );
+    write!(writer, "P5\n{} {}\n255\n", cols, rows)?;
+    writer.write_all(pixels)
+}
+
+/// Write a color image in the binary PPM format. The pixels are interleaved RGB
+/// triplets, and the dimensions are (rows, columns).
+///
+/// http://netpbm.sourceforge.net/doc/ppm.html
+pub fn write_ppm(
+    writer: &mut impl Write,
+    dimensions: (usize, usize),
+    pixels: &[u8],
+) -> io::Result<()> {
+    let (rows, cols) = dimensions;
+    assert_eq!(
+        rows * cols * 3,
+        pixels.len(),
+        "The pixels do not match the dimensions."
+    );
+    write!(writer, "P6\n{} {}\n255\n", cols, rows)?;
+    writer.write_all(pixels)
+}
+
+/// Write a PNG with either 1 (grayscale) or 3 (interleaved RGB) channels. The image
+/// data is not actually compressed. It is wrapped in "stored" DEFLATE blocks, which
+/// keeps the encoder small while still producing a valid PNG.
+///
+/// http://www.libpng.org/pub/png/spec/1.2/PNG-Structure.html
+pub fn write_png(
+    writer: &mut impl Write,
+    dimensions: (usize, usize),
+    channels: usize,
+    pixels: &[u8],
+) -> io::Result<()> {
+    let (rows, cols) = dimensions;
+    let color_type = match channels {
+        1 => 0, // Grayscale
+        3 => 2, // Truecolor
+        _ => panic!("PNG images can only be written with 1 or 3 channels."),
+    };
+    assert_eq!(
+        rows * cols * channels,
+        pixels.len(),
+        "The pixels do not match the dimensions."
+    );
+
+    writer.write_all(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'])?;
+
+    let mut header = Vec::with_capacity(13);
+    header.write_u32::<BigEndian>(cols as u32)?;
+    header.write_u32::<BigEndian>(rows as u32)?;
+    // Bit depth, color type, compression, filter, and interlace method.
+    header.extend_from_slice(&[8, color_type, 0, 0, 0]);
+    write_png_chunk(writer, b"IHDR", &header)?;
+
+    // Every scanline starts with its filter type, which is 0 for no filtering.
+    let mut scanlines = Vec::with_capacity(rows * (cols * channels + 1));
+    for row in pixels.chunks(cols * channels).take(rows) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
     }
+    write_png_chunk(writer, b"IDAT", &zlib_stored(&scanlines))?;
+    write_png_chunk(writer, b"IEND", &[])
+}
 
-    Ok(labels)
+pub(crate) fn write_png_chunk(
+    writer: &mut impl Write,
+    kind: &[u8; 4],
+    data: &[u8],
+) -> io::Result<()> {
+    writer.write_u32::<BigEndian>(data.len() as u32)?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let mut crc = Crc32::new();
+    crc.update(kind);
+    crc.update(data);
+    writer.write_u32::<BigEndian>(crc.finish())
 }
 
-/// Outputs the image to the terminal using ascii art.
-pub fn output_image(images: &Images, index: usize) -> String {
-    let &Images {
-        dimensions: (width, height),
-        ref list,
-        ref labels,
-        ..
-    } = images;
+/// Wrap the data in a zlib stream made of uncompressed DEFLATE blocks.
+///
+/// https://tools.ietf.org/html/rfc1950
+/// https://tools.ietf.org/html/rfc1951#section-3.2.4
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 0xffff;
+    let mut out = Vec::with_capacity(data.len() + data.len() / MAX_BLOCK * 5 + 11);
+    // Deflate with a 32k window, and no preset dictionary.
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        // An empty stream still needs a final block.
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let is_final = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(is_final as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    // The Adler-32 checksum of the uncompressed data.
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    out
+}
 
-    let image = list.get(index).unwrap();
-    let label = labels.get(index).unwrap();
+/// The CRC-32 that is used by PNG chunks.
+pub(crate) struct Crc32(u32);
 
-    let string = String::new();
+impl Crc32 {
+    pub(crate) fn new() -> Crc32 {
+        Crc32(0xffff_ffff)
+    }
 
-    for i in 0..height {
-        for j in 0..width {
-            let index = i * height + j;
-            if *image.get(index).unwrap() > 50 {
-                print!("X");
-            } else {
-                print!(".");
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u32;
+            for _ in 0..8 {
+                let mask = (self.0 & 1).wrapping_neg();
+                self.0 = (self.0 >> 1) ^ (0xedb8_8320 & mask);
             }
         }
-        println!();
     }
 
-    println!("\n^ This image is labeled \"{:?}\"\n", label);
+    pub(crate) fn finish(&self) -> u32 {
+        !self.0
+    }
+}
 
-    string
+/// Save a grayscale or RGB image, choosing the format from the file extension. This
+/// can be "png", "pgm", or "ppm". The number of channels is worked out from the length
+/// of the pixels, and color images are saved as grayscale in PGM files.
+pub fn save_image(
+    path: impl AsRef<Path>,
+    dimensions: (usize, usize),
+    pixels: &[u8],
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let channels = pixels.len() / (dimensions.0 * dimensions.1).max(1);
+    if channels != 1 && channels != 3 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Images can only be saved with 1 or 3 channels.",
+        ));
+    }
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+    let mut file = BufWriter::new(File::create(path)?);
+    match extension.as_deref() {
+        Some("png") => write_png(&mut file, dimensions, channels, pixels)?,
+        Some("pgm") => write_pgm(&mut file, dimensions, &grayscale(pixels, channels))?,
+        Some("ppm") if channels == 3 => write_ppm(&mut file, dimensions, pixels)?,
+        Some("ppm") => {
+            let rgb: Vec<u8> = pixels.iter().flat_map(|pixel| [*pixel; 3]).collect();
+            write_ppm(&mut file, dimensions, &rgb)?
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Images can only be saved as png, pgm, or ppm files.",
+            ))
+        }
+    }
+    file.flush()
 }
 
-pub fn load_in_test_images() -> Result<Images, Error> {
-    let labels = read_in_labels("./data/mnist/t10k-labels-idx1-ubyte")?;
-    let mut images = read_in_images("./data/mnist/t10k-images-idx3-ubyte")?;
+/// Convert interleaved RGB pixels to grayscale using the luma weights from Rec. 601.
+/// Grayscale pixels are returned as they are.
+pub fn grayscale(pixels: &[u8], channels: usize) -> Vec<u8> {
+    match channels {
+        3 => pixels
+            .chunks_exact(3)
+            .map(|rgb| {
+                (0.299 * rgb[0] as f64 + 0.587 * rgb[1] as f64 + 0.114 * rgb[2] as f64).round()
+                    as u8
+            })
+            .collect(),
+        _ => pixels.to_vec(),
+    }
+}
+
+/// A 3x5 pixel font for the digits 0-9, where each row is 3 bits from left to right.
+const DIGIT_FONT: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// Lay out several images in a grid with `columns` images per row, where each image
+/// has its label drawn underneath it. Returns the dimensions and the pixels of the
+/// grayscale sheet.
+pub fn contact_sheet(
+    images: &Images,
+    indexes: &[usize],
+    columns: usize,
+) -> ((usize, usize), Vec<u8>) {
+    const PADDING: usize = 2;
+    const CAPTION_HEIGHT: usize = 7;
+    const BACKGROUND: u8 = 64;
+
+    let (image_rows, image_cols) = images.dimensions;
+    let columns = columns.max(1).min(indexes.len().max(1));
+    let grid_rows = indexes.len().div_ceil(columns);
+    let cell_width = image_cols + PADDING;
+    let cell_height = image_rows + CAPTION_HEIGHT + PADDING;
+    let sheet_cols = columns * cell_width + PADDING;
+    let sheet_rows = grid_rows * cell_height + PADDING;
+    let mut sheet = vec![BACKGROUND; sheet_rows * sheet_cols];
+
+    for (cell, &index) in indexes.iter().enumerate() {
+        let top = PADDING + (cell / columns) * cell_height;
+        let left = PADDING + (cell % columns) * cell_width;
+        let image = images.grayscale(index);
+        for row in 0..image_rows {
+            let start = (top + row) * sheet_cols + left;
+            sheet[start..start + image_cols]
+                .copy_from_slice(&image[row * image_cols..(row + 1) * image_cols]);
+        }
+
+        // Draw the label in white, on a black strip under the image.
+        let caption_top = top + image_rows;
+        for row in caption_top..caption_top + CAPTION_HEIGHT {
+            let start = row * sheet_cols + left;
+            sheet[start..start + image_cols].fill(0);
+        }
+        let label = images
+            .labels
+            .get(index)
+            .map_or(String::new(), |label| label.to_string());
+        for (digit_index, digit) in label.bytes().enumerate() {
+            let glyph = DIGIT_FONT[(digit - b'0') as usize];
+            for (glyph_row, bits) in glyph.iter().enumerate() {
+                for glyph_col in 0..3 {
+                    let col = 1 + digit_index * 4 + glyph_col;
+                    if bits & (0b100 >> glyph_col) != 0 && col < image_cols {
+                        sheet[(caption_top + 1 + glyph_row) * sheet_cols + left + col] = 255;
+                    }
+                }
+            }
+        }
+    }
+
+    ((sheet_rows, sheet_cols), sheet)
+}
+
+/// Load a pair of IDX image and label files, in the format of the MNIST data.
+pub fn load_images(
+    images_path: impl AsRef<Path>,
+    labels_path: impl AsRef<Path>,
+) -> Result<Images, Error> {
+    let labels = read_in_labels(labels_path)?;
+    let mut images = read_in_images(images_path)?;
+    check_label_count(&images, &labels)?;
     images.labels = labels;
     Ok(images)
 }
 
-pub fn load_in_training_images() -> Result<Images, Error> {
-    let labels = read_in_labels("./data/mnist/train-labels-idx1-ubyte")?;
-    let mut images = read_in_images("./data/mnist/train-images-idx3-ubyte")?;
+/// Load a pair of IDX image and label files like `load_images`, but memory map the
+/// images so that they don't need to fit in memory.
+pub fn load_images_mapped(
+    images_path: impl AsRef<Path>,
+    labels_path: impl AsRef<Path>,
+) -> Result<Images, Error> {
+    let labels = read_in_labels(labels_path)?;
+    let mut images = map_in_images(images_path)?;
+    check_label_count(&images, &labels)?;
     images.labels = labels;
     Ok(images)
 }
 
+/// The images in CIFAR-10 are 32x32 pixels with 3 color channels.
+const CIFAR_SIZE: usize = 32;
+const CIFAR_PIXEL_COUNT: usize = CIFAR_SIZE * CIFAR_SIZE * 3;
+
+/// Read in one of the CIFAR-10 binary batch files.
+///
+/// According to: https://www.cs.toronto.edu/~kriz/cifar.html
+///
+/// Each record is 1 byte for the label, followed by 3072 bytes for the pixels. The
+/// pixels are stored as a plane of 1024 red values, then green, then blue, where each
+/// plane is 32x32 in row-major order. The channels are interleaved when they are read
+/// in, to match how `Images` stores color.
+pub fn read_cifar_batch(path: impl AsRef<Path>) -> Result<Images, Error> {
+    let mut bytes = Vec::new();
+    open_file(path.as_ref())?.read_to_end(&mut bytes)?;
+    if bytes.len() % (CIFAR_PIXEL_COUNT + 1) != 0 {
+        return Err(Error::Message(
+            "The CIFAR data is not a whole number of records.",
+        ));
+    }
+
+    let record_count = bytes.len() / (CIFAR_PIXEL_COUNT + 1);
+    let plane = CIFAR_SIZE * CIFAR_SIZE;
+    let mut labels = Vec::with_capacity(record_count);
+    let mut pixels = Vec::with_capacity(record_count * CIFAR_PIXEL_COUNT);
+    for record in bytes.chunks_exact(CIFAR_PIXEL_COUNT + 1) {
+        labels.push(record[0]);
+        let planes = &record[1..];
+        for index in 0..plane {
+            pixels.extend_from_slice(&[
+                planes[index],
+                planes[plane + index],
+                planes[2 * plane + index],
+            ]);
+        }
+    }
+    Ok(Images::with_channels(
+        (CIFAR_SIZE, CIFAR_SIZE),
+        3,
+        pixels,
+        labels,
+    ))
+}
+
+/// Load and combine several CIFAR-10 batch files.
+pub fn load_cifar_batches(paths: &[PathBuf]) -> Result<Images, Error> {
+    let mut images = Images::with_channels((CIFAR_SIZE, CIFAR_SIZE), 3, Vec::new(), Vec::new());
+    for path in paths {
+        let batch = read_cifar_batch(path)?;
+        for (image, label) in batch.iter().zip(&batch.labels) {
+            images.push(image, *label);
+        }
+    }
+    Ok(images)
+}
+
+/// The environment variable that sets the directory of the MNIST files.
+pub const DATA_DIR_VAR: &str = "MNIST_DATA_DIR";
+
+/// The directory of the MNIST files. This is the `MNIST_DATA_DIR` environment variable
+/// when it is set, otherwise `./data/mnist` relative to the working directory.
+pub fn data_root() -> PathBuf {
+    match env::var_os(DATA_DIR_VAR) {
+        Some(root) if !root.is_empty() => PathBuf::from(root),
+        _ => PathBuf::from("./data/mnist"),
+    }
+}
+
+/// The MNIST data comes split into training and test sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Split {
+    Training,
+    Test,
+}
+
+/// The paths to the image and label files of an MNIST split in the directory.
+pub fn mnist_paths(root: impl AsRef<Path>, split: Split) -> (PathBuf, PathBuf) {
+    let root = root.as_ref();
+    let prefix = match split {
+        Split::Training => "train",
+        Split::Test => "t10k",
+    };
+    (
+        find_data_file(root, &format!("{}-images-idx3-ubyte", prefix)),
+        find_data_file(root, &format!("{}-labels-idx1-ubyte", prefix)),
+    )
+}
+
+/// Find a data file in the directory, falling back to the gzip archive that MNIST is
+/// downloaded as when the file hasn't been extracted.
+pub fn find_data_file(root: &Path, name: &str) -> PathBuf {
+    let path = root.join(name);
+    let archive = root.join(format!("{}.gz", name));
+    if !path.exists() && archive.exists() {
+        archive
+    } else {
+        path
+    }
+}
+
+/// Load the 10k MNIST test images from the directory.
+pub fn load_test_images_from(root: impl AsRef<Path>) -> Result<Images, Error> {
+    let (images_path, labels_path) = mnist_paths(root, Split::Test);
+    load_images(images_path, labels_path)
+}
+
+/// Load the 60k MNIST training images from the directory.
+pub fn load_training_images_from(root: impl AsRef<Path>) -> Result<Images, Error> {
+    let (images_path, labels_path) = mnist_paths(root, Split::Training);
+    load_images(images_path, labels_path)
+}
+
+pub fn load_in_test_images() -> Result<Images, Error> {
+    load_test_images_from(data_root())
+}
+
+pub fn load_in_training_images() -> Result<Images, Error> {
+    load_training_images_from(data_root())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::env;
+    use std::fs;
+
+    /// The MNIST files in the root of the project.
+    fn mnist_root() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("../data/mnist")
+    }
+
+    /// Write a small synthetic dataset in the MNIST format, returning the paths to the
+    /// image and label files.
+    fn write_fixture(name: &str, images: &Images) -> (PathBuf, PathBuf) {
+        let directory = env::temp_dir();
+        let prefix = format!("ml-rs-{}-{}", name, std::process::id());
+        let images_path = directory.join(format!("{}-images-idx3-ubyte", prefix));
+        let labels_path = directory.join(format!("{}-labels-idx1-ubyte", prefix));
+        write_images(&images_path, images).unwrap();
+        write_labels(&labels_path, &images.labels).unwrap();
+        (images_path, labels_path)
+    }
+
+    fn fixture_images() -> Images {
+        Images::from_list(
+            (3, 2),
+            (0..5).map(|i| vec![i * 10; 6]).collect(),
+            vec![4, 3, 2, 1, 0],
+        )
+    }
+
+    #[test]
+    fn output_grids() {
+        let images = Images::new((1, 2), vec![0, 255, 255, 0], vec![1, 0]);
+        assert_eq!(
+            output_image_grid(&images, &[0, 1], None, 2, RenderMode::Threshold(50)),
+            ".X       X.\nlabel 1  label 0\n"
+        );
+        let predictions = [
+            Prediction {
+                label: 1,
+                probability: 0.9,
+            },
+            Prediction {
+                label: 1,
+                probability: 0.55,
+            },
+        ];
+        let grid = output_image_grid(
+            &images,
+            &[0, 1],
+            Some(&predictions),
+            1,
+            RenderMode::Threshold(50),
+        );
+        assert_eq!(
+            grid,
+            format!(
+                ".X\ntrue 1 pred 1 90%\n\nX.\n{}\n",
+                paint("true 0 pred 1 55%", PaletteColor::RED)
+            )
+        );
+    }
+
+    #[test]
+    fn output_rectangular_images() {
+        let images = Images::new((2, 3), vec![0, 255, 0, 255, 0, 0], vec![7]);
+        assert_eq!(
+            output_image(&images, 0, RenderMode::Threshold(50)),
+            ".X.\nX..\n\n^ This image is labeled \"7\"\n"
+        );
+    }
+
+    #[test]
+    fn pgm() {
+        let mut bytes = Vec::new();
+        write_pgm(&mut bytes, (2, 3), &[0, 1, 2, 3, 4, 5]).unwrap();
+        assert_eq!(bytes, b"P5\n3 2\n255\n\x00\x01\x02\x03\x04\x05");
+    }
+
+    #[test]
+    fn ppm() {
+        let mut bytes = Vec::new();
+        write_ppm(&mut bytes, (1, 2), &[255, 0, 0, 0, 0, 255]).unwrap();
+        assert_eq!(bytes, b"P6\n2 1\n255\n\xff\x00\x00\x00\x00\xff");
+    }
+
+    #[test]
+    fn png() {
+        let mut bytes = Vec::new();
+        write_png(&mut bytes, (2, 2), 1, &[0, 64, 128, 255]).unwrap();
+        assert_eq!(&bytes[..8], b"\x89PNG\r\n\x1a\n");
+        // The length, type, and contents of the header chunk.
+        assert_eq!(&bytes[8..16], b"\x00\x00\x00\x0dIHDR");
+        assert_eq!(&bytes[16..29], &[0, 0, 0, 2, 0, 0, 0, 2, 8, 0, 0, 0, 0]);
+        // The well-known CRC of the IEND chunk.
+        assert_eq!(
+            &bytes[bytes.len() - 12..],
+            b"\x00\x00\x00\x00IEND\xae\x42\x60\x82"
+        );
+    }
+
+    #[test]
+    fn zlib() {
+        // The Adler-32 of "Wikipedia" is 0x11E60398.
+        let stream = zlib_stored(b"Wikipedia");
+        assert_eq!(&stream[..7], &[0x78, 0x01, 1, 9, 0, 0xf6, 0xff]);
+        assert_eq!(&stream[7..16], b"Wikipedia");
+        assert_eq!(&stream[16..], &[0x11, 0xe6, 0x03, 0x98]);
+
+        // Large data is split into multiple blocks.
+        let stream = zlib_stored(&vec![0; 70000]);
+        assert_eq!(stream[2], 0, "The first block is not final");
+        assert_eq!(stream[2 + 5 + 0xffff], 1, "The second block is final");
+    }
+
+    #[test]
+    fn contact_sheets() {
+        let images = Images::from_list((8, 8), vec![vec![200; 64]; 3], vec![1, 2, 3]);
+        let ((rows, cols), pixels) = contact_sheet(&images, &[0, 2], 4);
+        assert_eq!((rows, cols), (2 + 8 + 7 + 2, 2 + 2 * 10));
+        assert_eq!(pixels.len(), rows * cols);
+        assert_eq!(
+            pixels[2 * cols + 2],
+            200,
+            "The first image is in the corner"
+        );
+        assert_eq!(pixels[0], 64, "The padding is the background color");
+        // The top of a "1" is drawn in the middle column of the glyph.
+        let caption_top = 2 + 8 + 1;
+        assert_eq!(pixels[caption_top * cols + 2 + 1 + 1], 255);
+        assert_eq!(pixels[caption_top * cols + 2 + 1], 0);
+    }
+
+    #[test]
+    fn write_and_load() {
+        let images = fixture_images();
+        let (images_path, labels_path) = write_fixture("write-and-load", &images);
+        let loaded = load_images(&images_path, &labels_path);
+        let bytes = fs::read(&images_path).unwrap();
+        fs::remove_file(&images_path).unwrap();
+        fs::remove_file(&labels_path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(&bytes[..4], &[0, 0, 8, 3], "The MNIST image magic number");
+        assert_eq!(loaded.dimensions, (3, 2));
+        assert_eq!(loaded.pixel_count, 6);
+        assert_eq!(loaded.pixels(), images.pixels());
+        assert_eq!(loaded.labels, images.labels);
+    }
+
+    #[test]
+    #[should_panic(expected = "An image does not match the dimensions.")]
+    fn rejects_mismatched_images() {
+        Images::from_list((2, 2), vec![vec![0; 4], vec![0; 3]], vec![0, 1]);
+    }
+
+    #[test]
+    fn flat_storage() {
+        let mut images = fixture_images();
+        assert_eq!(images.len(), 5);
+        assert_eq!(images.pixels().len(), 5 * 6, "The images are contiguous");
+        assert_eq!(images.image(2), &[20; 6]);
+        assert_eq!(images.get(5), None);
+        assert_eq!(images.iter().count(), 5);
 
-    /// The data paths are relative to the root of the project.
-    fn set_cwd() {
-        let cwd = env::current_dir().unwrap();
-        if cwd.file_name().unwrap() == "feed-forward" {
-            // Go up a directory.
-            env::set_current_dir(cwd.parent().unwrap()).unwrap();
+        images.push(&[7; 6], 9);
+        assert_eq!(images.len(), 6);
+        assert_eq!(images.image(5), &[7; 6]);
+        assert_eq!(images.labels[5], 9);
+    }
+
+    #[test]
+    fn subsets() {
+        let images = fixture_images();
+        let subset = images.subset(&[4, 1]);
+        assert_eq!(subset.len(), 2);
+        assert_eq!(subset.image(0), &[40; 6]);
+        assert_eq!(subset.labels, vec![0, 3]);
+
+        let filtered = images.filter_labels(&[1, 3]);
+        assert_eq!(filtered.labels, vec![3, 1]);
+        assert_eq!(filtered.image(1), &[30; 6]);
+
+        let joined = subset.concat(&filtered);
+        assert_eq!(joined.labels, vec![0, 3, 3, 1]);
+        assert_eq!(joined.image(3), &[30; 6]);
+        assert_eq!(joined.class_histogram(), vec![1, 1, 0, 2]);
+        assert_eq!(
+            Images::new((1, 1), Vec::new(), Vec::new()).class_histogram(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn shuffles() {
+        let mut images = fixture_images();
+        images.shuffle(1);
+        let mut again = fixture_images();
+        again.shuffle(1);
+        assert_eq!(
+            images.labels, again.labels,
+            "The same seed gives the same order"
+        );
+        assert_ne!(images.labels, fixture_images().labels);
+        for (image, label) in images.iter().zip(&images.labels) {
+            assert_eq!(
+                image[0],
+                (4 - label) * 10,
+                "The labels stay with the images"
+            );
+        }
+    }
+
+    #[test]
+    fn splits() {
+        // 10 images of each of 3 labels, where every pixel is the image's index.
+        let labels: Vec<u8> = (0..30).map(|index| (index % 3) as u8).collect();
+        let images = Images::new((1, 1), (0..30).collect(), labels);
+
+        let parts = images.random_split(&[0.5, 0.3, 0.2], 7);
+        assert_eq!(
+            parts.iter().map(|part| part.len()).collect::<Vec<_>>(),
+            vec![15, 9, 6]
+        );
+        let mut seen: Vec<u8> = parts
+            .iter()
+            .flat_map(|part| part.pixels().to_vec())
+            .collect();
+        seen.sort_unstable();
+        assert_eq!(
+            seen,
+            (0..30).collect::<Vec<u8>>(),
+            "Every image is used once"
+        );
+        for part in &parts {
+            for (image, label) in part.iter().zip(&part.labels) {
+                assert_eq!(image[0] % 3, *label);
+            }
+        }
+
+        let parts = images.stratified_split(&[0.8, 0.2], 7);
+        assert_eq!(parts[0].class_histogram(), vec![8, 8, 8]);
+        assert_eq!(parts[1].class_histogram(), vec![2, 2, 2]);
+
+        let partial = images.random_split(&[0.1], 7);
+        assert_eq!(partial[0].len(), 3, "The rest of the images are left out");
+    }
+
+    #[test]
+    #[should_panic(expected = "The split fractions must be positive")]
+    fn rejects_large_fractions() {
+        fixture_images().random_split(&[0.8, 0.8], 0);
+    }
+
+    #[test]
+    fn memory_mapped() {
+        let images = fixture_images();
+        let (images_path, labels_path) = write_fixture("memory-mapped", &images);
+        let mapped = load_images_mapped(&images_path, &labels_path).unwrap();
+        fs::remove_file(&images_path).unwrap();
+        fs::remove_file(&labels_path).unwrap();
+
+        assert!(mapped.is_mapped());
+        assert_eq!(mapped.dimensions, (3, 2));
+        assert_eq!(mapped.len(), 5);
+        assert_eq!(mapped.pixels(), images.pixels());
+        assert_eq!(mapped.image(4), &[40; 6]);
+        assert_eq!(mapped.labels, images.labels);
+
+        let mut copy = mapped.clone();
+        copy.push(&[1; 6], 1);
+        assert!(!copy.is_mapped(), "Adding an image copies the pixels");
+        assert_eq!(copy.image(4), &[40; 6]);
+    }
+
+    #[test]
+    fn missing_files() {
+        let images = fixture_images();
+        let root = env::temp_dir().join(format!("ml-rs-missing-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        write_labels(root.join("t10k-labels-idx1-ubyte"), &images.labels).unwrap();
+        let result = load_test_images_from(&root);
+        fs::remove_dir_all(&root).unwrap();
+
+        match result {
+            Err(Error::MissingFile(path)) => {
+                assert_eq!(path, root.join("t10k-images-idx3-ubyte"))
+            }
+            other => panic!("Expected a missing file error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn color_images() {
+        // Two 1x2 RGB images.
+        let images = Images::with_channels(
+            (1, 2),
+            3,
+            vec![255, 0, 0, 0, 0, 255, 9, 9, 9, 1, 2, 3],
+            vec![0, 1],
+        );
+        assert_eq!(images.len(), 2);
+        assert_eq!(images.pixel_count, 6);
+        assert_eq!(images.image(1), &[9, 9, 9, 1, 2, 3]);
+        assert_eq!(images.grayscale(0), vec![76, 29]);
+        assert_eq!(grayscale(&[7, 8], 1), vec![7, 8]);
+
+        let (images_path, labels_path) = write_fixture("color", &images);
+        let loaded = load_images(&images_path, &labels_path);
+        fs::remove_file(&images_path).unwrap();
+        fs::remove_file(&labels_path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.channels, 3);
+        assert_eq!(loaded.pixels(), images.pixels());
+    }
+
+    #[test]
+    fn cifar_batches() {
+        let path = env::temp_dir().join(format!("ml-rs-cifar-batch-{}", std::process::id()));
+        // Two records, where the planes are all red, then all green, then all blue.
+        let mut bytes = Vec::new();
+        for label in [3, 7] {
+            bytes.push(label);
+            bytes.extend((0..3072).map(|index| (index / 1024) as u8 + label));
+        }
+        fs::write(&path, &bytes).unwrap();
+        let batch = read_cifar_batch(&path);
+        fs::write(&path, &bytes[1..]).unwrap();
+        let truncated = read_cifar_batch(&path);
+        fs::remove_file(&path).unwrap();
+
+        let batch = batch.unwrap();
+        assert_eq!(batch.dimensions, (32, 32));
+        assert_eq!(batch.channels, 3);
+        assert_eq!(batch.labels, vec![3, 7]);
+        assert_eq!(
+            &batch.image(0)[..6],
+            &[3, 4, 5, 3, 4, 5],
+            "The planes are interleaved"
+        );
+        assert_eq!(&batch.image(1)[3069..], &[7, 8, 9]);
+        assert!(truncated.is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let images = fixture_images();
+        let (images_path, labels_path) = write_fixture("errors", &images);
+        let swapped = load_images(&labels_path, &labels_path);
+        let swapped_labels = load_images(&images_path, &images_path);
+        write_labels(&labels_path, &[1, 2, 3]).unwrap();
+        let mismatched = load_images(&images_path, &labels_path);
+        let mismatched_mapped = load_images_mapped(&images_path, &labels_path);
+        fs::remove_file(&images_path).unwrap();
+        fs::remove_file(&labels_path).unwrap();
+
+        match swapped {
+            Err(Error::BadMagic {
+                expected,
+                found,
+                path,
+            }) => {
+                assert_eq!(expected, 0x0803);
+                assert_eq!(found, 0x0801);
+                assert_eq!(path, labels_path);
+            }
+            other => panic!("Expected a bad magic number, got {:?}", other),
+        }
+        match swapped_labels {
+            Err(Error::BadMagic {
+                expected, found, ..
+            }) => assert_eq!((expected, found), (0x0801, 0x0803)),
+            other => panic!("Expected a bad magic number, got {:?}", other),
+        }
+        for result in [mismatched, mismatched_mapped] {
+            match result {
+                Err(err @ Error::LabelCountMismatch { .. }) => {
+                    assert_eq!(err.to_string(), "There are 5 images, but 3 labels.")
+                }
+                other => panic!("Expected mismatched counts, got {:?}", other),
+            }
+        }
+
+        match check_dimensions(&images, (28, 28)) {
+            Err(err @ Error::DimensionMismatch { .. }) => assert_eq!(
+                err.to_string(),
+                "The images are 3x2, but 28x28 images were expected."
+            ),
+            other => panic!("Expected mismatched dimensions, got {:?}", other),
+        }
+        assert!(check_dimensions(&images, (3, 2)).is_ok());
+
+        let err: Box<dyn error::Error> = Box::new(Error::from(io::Error::other("disk")));
+        assert_eq!(err.to_string(), "disk");
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn truncated_images() {
+        let images = fixture_images();
+        let (images_path, labels_path) = write_fixture("truncated", &images);
+        let mut bytes = fs::read(&images_path).unwrap();
+        // Cut off the last pixel of the fourth image and everything after it.
+        bytes.truncate(16 + 3 * 6 + 5);
+        fs::write(&images_path, &bytes).unwrap();
+        let read = load_images(&images_path, &labels_path);
+        let mapped = load_images_mapped(&images_path, &labels_path);
+        fs::remove_file(&images_path).unwrap();
+        fs::remove_file(&labels_path).unwrap();
+
+        for result in [read, mapped] {
+            match result {
+                Err(Error::Truncated { image_index }) => assert_eq!(image_index, 3),
+                other => panic!("Expected truncated images, got {:?}", other),
+            }
         }
     }
 
     #[test]
+    fn hostile_image_files() {
+        let idx = |magic: [u8; 4], dimensions: &[u32], data_len: usize| -> Vec<u8> {
+            let mut bytes = magic.to_vec();
+            for size in dimensions {
+                bytes.extend_from_slice(&size.to_be_bytes());
+            }
+            bytes.resize(bytes.len() + data_len, 7);
+            bytes
+        };
+        let max = u32::MAX;
+        // Each image file is paired with the number of labels to write, which matches
+        // the images wherever it can, so that only the check under test can fail.
+        type Case = (Vec<u8>, usize, fn(&Error) -> bool);
+        let corpus: Vec<Case> = vec![
+            // More images than there is data for.
+            (idx([0, 0, 8, 3], &[max, 28, 28], 784), 1, |err| {
+                matches!(err, Error::Truncated { image_index: 1 })
+            }),
+            (idx([0, 0, 8, 3], &[6, 3, 2], 5 * 6), 6, |err| {
+                matches!(err, Error::Truncated { image_index: 5 })
+            }),
+            // Each image is too large to address, even though there are none of them.
+            (idx([0, 0, 8, 4], &[0, max, max, 3], 0), 0, |err| {
+                matches!(err, Error::Message("The image dimensions are too large."))
+            }),
+            // Extra data after the last image.
+            (idx([0, 0, 8, 3], &[5, 3, 2], 5 * 6 + 1), 5, |err| {
+                matches!(
+                    err,
+                    Error::Message("The IDX file has more data than its header describes.")
+                )
+            }),
+            // An unsupported number of channels.
+            (idx([0, 0, 8, 4], &[5, 3, 2, 2], 5 * 12), 5, |err| {
+                matches!(
+                    err,
+                    Error::Message("Images can only have 1 or 3 color channels.")
+                )
+            }),
+            // The wrong element type or number of dimensions.
+            (idx([0, 0, 9, 3], &[5, 3, 2], 5 * 6), 5, |err| {
+                matches!(err, Error::BadMagic { found: 0x0903, .. })
+            }),
+            (idx([0, 0, 8, 2], &[5, 6], 5 * 6), 5, |err| {
+                matches!(err, Error::BadMagic { found: 0x0802, .. })
+            }),
+            (idx([0xff, 0xff, 8, 3], &[5, 3, 2], 5 * 6), 5, |err| {
+                matches!(
+                    err,
+                    Error::Message("The IDX data's magic number is not correct.")
+                )
+            }),
+            // The header itself is cut off.
+            (
+                vec![0, 0, 8, 3, 0, 0, 0, 5, 0],
+                5,
+                |err| matches!(err, Error::IO(err) if err.kind() == io::ErrorKind::UnexpectedEof),
+            ),
+            (
+                vec![],
+                0,
+                |err| matches!(err, Error::IO(err) if err.kind() == io::ErrorKind::UnexpectedEof),
+            ),
+        ];
+
+        let images = fixture_images();
+        let (images_path, labels_path) = write_fixture("hostile", &images);
+        let mut results = Vec::new();
+        for (bytes, label_count, _) in &corpus {
+            fs::write(&images_path, bytes).unwrap();
+            write_labels(&labels_path, &vec![0; *label_count]).unwrap();
+            results.push(load_images(&images_path, &labels_path));
+            results.push(load_images_mapped(&images_path, &labels_path));
+        }
+
+        // The same shape is fine when there are no images and it has a single channel.
+        fs::write(&images_path, idx([0, 0, 8, 3], &[0, max, max], 0)).unwrap();
+        write_labels(&labels_path, &[]).unwrap();
+        let empty = load_images(&images_path, &labels_path);
+        fs::remove_file(&images_path).unwrap();
+        fs::remove_file(&labels_path).unwrap();
+        assert_eq!(empty.unwrap().len(), 0);
+
+        for (index, result) in results.iter().enumerate() {
+            let is_expected = corpus[index / 2].2;
+            match result {
+                Err(err) => assert!(is_expected(err), "Case {} failed with {:?}", index / 2, err),
+                Ok(images) => panic!("Case {} loaded {} images", index / 2, images.len()),
+            }
+        }
+    }
+
+    #[test]
+    fn load_labels() {
+        let labels = read_in_labels(mnist_root().join("t10k-labels-idx1-ubyte")).unwrap();
+        assert_eq!(labels.len(), 10000);
+        assert!(labels.iter().all(|label| *label < 10));
+    }
+
+    #[test]
+    #[ignore = "needs the full MNIST download in ./data/mnist"]
     fn load_test() {
-        set_cwd();
-        let images = load_in_test_images().unwrap();
+        let images = load_test_images_from(mnist_root()).unwrap();
         assert_eq!(
-            images.list.len(),
+            images.len(),
             10000,
             "The correct number of test images were loaded in"
         )
     }
 
     #[test]
+    #[ignore = "needs the full MNIST download in ./data/mnist"]
     fn load_training() {
-        set_cwd();
-        let images = load_in_training_images().unwrap();
+        let images = load_training_images_from(mnist_root()).unwrap();
         assert_eq!(
-            images.list.len(),
+            images.len(),
             60000,
             "The correct number of test images were loaded in"
         )
@@ -0,0 +1,503 @@
+//! Decode images from files, so that images from outside of the datasets can be
+//! classified. PNG, PGM and PPM files are supported, which are the same formats that
+//! `image_data::save_image` writes.
+use crate::image_data::{Crc32, ImageData};
+use byteorder::{BigEndian, ReadBytesExt};
+use flate2::bufread::ZlibDecoder;
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+
+/// The dimensions (rows, columns), the number of channels, and the pixels of an image
+/// that was read from a file.
+pub type DecodedImage = ((usize, usize), usize, ImageData);
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+fn invalid_image(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Load a PNG, PGM or PPM image, working out the format from the start of the file.
+/// Grayscale images have 1 channel, and everything else is converted to interleaved
+/// RGB. Transparent pixels are drawn on a white background.
+pub fn load_image(path: impl AsRef<Path>) -> io::Result<DecodedImage> {
+    let mut file = io::BufReader::new(File::open(path)?);
+    let start = file.fill_buf()?;
+    if start.starts_with(&PNG_SIGNATURE) {
+        read_png(&mut file)
+    } else if start.starts_with(b"P") {
+        read_netpbm(&mut file)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Images can only be loaded from png, pgm, or ppm files.",
+        ))
+    }
+}
+
+/// Read a PGM or PPM image, in either the binary or the plain text format. The pixels
+/// are scaled to 0-255 from the maximum value in the header.
+///
+/// http://netpbm.sourceforge.net/doc/pgm.html
+pub fn read_netpbm(reader: &mut impl Read) -> io::Result<DecodedImage> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let mut position = 0;
+    let (channels, is_binary) = match netpbm_token(&data, &mut position) {
+        Some(b"P2") => (1, false),
+        Some(b"P3") => (3, false),
+        Some(b"P5") => (1, true),
+        Some(b"P6") => (3, true),
+        _ => return Err(invalid_image("The file is not a PGM or PPM image.")),
+    };
+    let number = |position: &mut usize| -> io::Result<usize> {
+        netpbm_token(&data, position)
+            .and_then(|token| std::str::from_utf8(token).ok())
+            .and_then(|token| token.parse().ok())
+            .ok_or_else(|| invalid_image("The image has a malformed number."))
+    };
+    let cols = number(&mut position)?;
+    let rows = number(&mut position)?;
+    let max = number(&mut position)?;
+    if max == 0 || max > 0xffff {
+        return Err(invalid_image(
+            "The maximum value of the image is out of range.",
+        ));
+    }
+    let count = rows
+        .checked_mul(cols)
+        .and_then(|count| count.checked_mul(channels))
+        .ok_or_else(|| invalid_image("The image is too large."))?;
+
+    let values: Vec<usize> = if is_binary {
+        // A single whitespace character separates the header from the pixels, which
+        // take up two bytes each when the maximum value doesn't fit in one.
+        let width = if max > 0xff { 2 } else { 1 };
+        let start = position + 1;
+        let bytes = count
+            .checked_mul(width)
+            .and_then(|size| data.get(start..start.checked_add(size)?))
+            .ok_or_else(|| invalid_image("The image is truncated."))?;
+        bytes
+            .chunks(width)
+            .map(|value| value.iter().fold(0, |sum, byte| sum << 8 | *byte as usize))
+            .collect()
+    } else {
+        (0..count)
+            .map(|_| number(&mut position))
+            .collect::<io::Result<_>>()?
+    };
+    let pixels = values
+        .iter()
+        .map(|value| ((*value).min(max) * 255 + max / 2) / max)
+        .map(|value| value as u8)
+        .collect();
+    Ok(((rows, cols), channels, pixels))
+}
+
+/// The next whitespace separated token of a PGM or PPM header, skipping comments.
+fn netpbm_token<'a>(data: &'a [u8], position: &mut usize) -> Option<&'a [u8]> {
+    loop {
+        match data.get(*position)? {
+            b'#' => {
+                while *data.get(*position)? != b'\n' {
+                    *position += 1;
+                }
+            }
+            byte if byte.is_ascii_whitespace() => *position += 1,
+            _ => break,
+        }
+    }
+    let start = *position;
+    while data
+        .get(*position)
+        .is_some_and(|byte| !byte.is_ascii_whitespace())
+    {
+        *position += 1;
+    }
+    Some(&data[start..*position])
+}
+
+/// Read a PNG image. Every color type and bit depth is supported, except for interlaced
+/// images. 16 bit samples are reduced to 8 bits.
+///
+/// http://www.libpng.org/pub/png/spec/1.2/PNG-Contents.html
+pub fn read_png(reader: &mut impl Read) -> io::Result<DecodedImage> {
+    let mut signature = [0; 8];
+    reader.read_exact(&mut signature)?;
+    if signature != PNG_SIGNATURE {
+        return Err(invalid_image("The file is not a PNG image."));
+    }
+
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut transparency = Vec::new();
+    let mut compressed = Vec::new();
+    loop {
+        let length = reader.read_u32::<BigEndian>()? as u64;
+        let mut kind = [0; 4];
+        reader.read_exact(&mut kind)?;
+        let mut data = Vec::new();
+        reader.by_ref().take(length).read_to_end(&mut data)?;
+        if data.len() as u64 != length {
+            return Err(invalid_image("The PNG image is truncated."));
+        }
+        let mut crc = Crc32::new();
+        crc.update(&kind);
+        crc.update(&data);
+        if reader.read_u32::<BigEndian>()? != crc.finish() {
+            return Err(invalid_image("A PNG chunk is corrupted."));
+        }
+        match &kind {
+            b"IHDR" => header = Some(PngHeader::parse(&data)?),
+            b"PLTE" => palette = data,
+            b"tRNS" => transparency = data,
+            b"IDAT" => compressed.extend_from_slice(&data),
+            b"IEND" => break,
+            // The other chunks don't change the pixels.
+            _ => {}
+        }
+    }
+    let header = header.ok_or_else(|| invalid_image("The PNG image has no header."))?;
+
+    let stride = header.stride()?;
+    let size = stride
+        .checked_add(1)
+        .and_then(|line| line.checked_mul(header.rows))
+        .ok_or_else(|| invalid_image("The PNG image is too large."))?;
+    let mut scanlines = Vec::new();
+    ZlibDecoder::new(&compressed[..])
+        .take(size as u64 + 1)
+        .read_to_end(&mut scanlines)?;
+    if scanlines.len() != size {
+        return Err(invalid_image("The PNG image data is the wrong size."));
+    }
+    let samples = unfilter_png(&scanlines, stride, header.bytes_per_pixel())?;
+
+    let (rows, cols) = (header.rows, header.cols);
+    let sample_count = header.samples_per_pixel();
+    let mut values = Vec::with_capacity(rows * cols * sample_count);
+    for line in samples.chunks(stride) {
+        for index in 0..cols * sample_count {
+            values.push(header.sample(line, index));
+        }
+    }
+
+    // Draw transparent pixels on white, which is the usual background of handwriting.
+    let over_white = |value: u8, alpha: u8| {
+        let alpha = alpha as u32;
+        ((value as u32 * alpha + 255 * (255 - alpha) + 127) / 255) as u8
+    };
+    let pixels: ImageData = match header.color_type {
+        0 | 2 => values,
+        4 => values
+            .chunks(2)
+            .map(|pixel| over_white(pixel[0], pixel[1]))
+            .collect(),
+        6 => values
+            .chunks(4)
+            .flat_map(|pixel| {
+                let alpha = pixel[3];
+                [
+                    over_white(pixel[0], alpha),
+                    over_white(pixel[1], alpha),
+                    over_white(pixel[2], alpha),
+                ]
+            })
+            .collect(),
+        _ => {
+            let mut pixels = Vec::with_capacity(values.len() * 3);
+            for index in values {
+                let index = index as usize;
+                let color = palette
+                    .get(index * 3..index * 3 + 3)
+                    .ok_or_else(|| invalid_image("The PNG palette is missing a color."))?;
+                let alpha = transparency.get(index).cloned().unwrap_or(0xff);
+                pixels.extend(color.iter().map(|value| over_white(*value, alpha)));
+            }
+            pixels
+        }
+    };
+    let channels = match header.color_type {
+        0 | 4 => 1,
+        _ => 3,
+    };
+    Ok(((rows, cols), channels, pixels))
+}
+
+struct PngHeader {
+    rows: usize,
+    cols: usize,
+    bit_depth: u8,
+    color_type: u8,
+}
+
+impl PngHeader {
+    fn parse(data: &[u8]) -> io::Result<PngHeader> {
+        if data.len() != 13 {
+            return Err(invalid_image("The PNG header is the wrong size."));
+        }
+        let mut reader = data;
+        let cols = reader.read_u32::<BigEndian>()? as usize;
+        let rows = reader.read_u32::<BigEndian>()? as usize;
+        if rows == 0 || cols == 0 {
+            return Err(invalid_image("The PNG image has no pixels."));
+        }
+        let (bit_depth, color_type, interlace) = (data[8], data[9], data[12]);
+        let is_valid = match color_type {
+            0 => [1, 2, 4, 8, 16].contains(&bit_depth),
+            3 => [1, 2, 4, 8].contains(&bit_depth),
+            2 | 4 | 6 => [8, 16].contains(&bit_depth),
+            _ => false,
+        };
+        if !is_valid {
+            return Err(invalid_image("The PNG image has an unknown pixel format."));
+        }
+        if interlace != 0 {
+            return Err(invalid_image("Interlaced PNG images are not supported."));
+        }
+        Ok(PngHeader {
+            rows,
+            cols,
+            bit_depth,
+            color_type,
+        })
+    }
+
+    fn samples_per_pixel(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    /// The number of bytes in a scanline, without its filter type.
+    fn stride(&self) -> io::Result<usize> {
+        self.cols
+            .checked_mul(self.samples_per_pixel() * self.bit_depth as usize)
+            .map(|bits| bits.div_ceil(8))
+            .ok_or_else(|| invalid_image("The PNG image is too large."))
+    }
+
+    /// The distance to the corresponding byte of the previous pixel, used by the filters.
+    fn bytes_per_pixel(&self) -> usize {
+        (self.samples_per_pixel() * self.bit_depth as usize / 8).max(1)
+    }
+
+    /// Read a sample out of an unfiltered scanline, scaled to 8 bits. Palette indexes are
+    /// left as they are.
+    fn sample(&self, line: &[u8], index: usize) -> u8 {
+        match self.bit_depth {
+            8 => line[index],
+            16 => line[index * 2],
+            bits => {
+                let bits = bits as usize;
+                let byte = line[index * bits / 8];
+                let shift = 8 - bits - index * bits % 8;
+                let value = (byte >> shift) & ((1 << bits) - 1);
+                if self.color_type == 3 {
+                    value
+                } else {
+                    (value as usize * 255 / ((1 << bits) - 1)) as u8
+                }
+            }
+        }
+    }
+}
+
+/// Undo the filter of every scanline, returning the scanlines without filter types.
+///
+/// http://www.libpng.org/pub/png/spec/1.2/PNG-Filters.html
+fn unfilter_png(scanlines: &[u8], stride: usize, bytes_per_pixel: usize) -> io::Result<Vec<u8>> {
+    let mut output: Vec<u8> = Vec::with_capacity(scanlines.len());
+    let mut previous = vec![0; stride];
+    for line in scanlines.chunks(stride + 1) {
+        let (filter, line) = (line[0], &line[1..]);
+        let mut current = Vec::with_capacity(stride);
+        for (index, byte) in line.iter().enumerate() {
+            let left = if index >= bytes_per_pixel {
+                current[index - bytes_per_pixel]
+            } else {
+                0
+            };
+            let above = previous[index];
+            let above_left = if index >= bytes_per_pixel {
+                previous[index - bytes_per_pixel]
+            } else {
+                0
+            };
+            let predictor = match filter {
+                0 => 0,
+                1 => left,
+                2 => above,
+                3 => ((left as u16 + above as u16) / 2) as u8,
+                4 => paeth(left, above, above_left),
+                _ => return Err(invalid_image("The PNG image has an unknown filter.")),
+            };
+            current.push(byte.wrapping_add(predictor));
+        }
+        output.extend_from_slice(&current);
+        previous = current;
+    }
+    Ok(output)
+}
+
+fn paeth(left: u8, above: u8, above_left: u8) -> u8 {
+    let estimate = left as i16 + above as i16 - above_left as i16;
+    let distance = |value: u8| (estimate - value as i16).abs();
+    if distance(left) <= distance(above) && distance(left) <= distance(above_left) {
+        left
+    } else if distance(above) <= distance(above_left) {
+        above
+    } else {
+        above_left
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image_data::{save_image, write_pgm, write_png, write_png_chunk, write_ppm};
+    use byteorder::WriteBytesExt;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+
+    #[test]
+    fn reads_netpbm() {
+        let plain = b"P2\n# A comment\n3 2\n4\n0 1 2\n3 4 9\n";
+        assert_eq!(
+            read_netpbm(&mut &plain[..]).unwrap(),
+            ((2, 3), 1, vec![0, 64, 128, 191, 255, 255])
+        );
+        let wide = b"P5 1 2 65535\n\xff\xff\x00\x00";
+        assert_eq!(
+            read_netpbm(&mut &wide[..]).unwrap(),
+            ((2, 1), 1, vec![255, 0])
+        );
+
+        let pixels: ImageData = (0..18).map(|i| i * 10).collect();
+        let mut ppm = Vec::new();
+        write_ppm(&mut ppm, (2, 3), &pixels).unwrap();
+        assert_eq!(
+            read_netpbm(&mut &ppm[..]).unwrap(),
+            ((2, 3), 3, pixels.clone())
+        );
+        let mut pgm = Vec::new();
+        write_pgm(&mut pgm, (3, 6), &pixels).unwrap();
+        assert_eq!(read_netpbm(&mut &pgm[..]).unwrap(), ((3, 6), 1, pixels));
+
+        assert!(read_netpbm(&mut &b"P5\n2 2\n255\n\x00"[..]).is_err());
+        assert!(read_netpbm(&mut &b"P7\n2 2\n255\n"[..]).is_err());
+        assert!(read_netpbm(&mut &b"P5\n2 x\n255\n"[..]).is_err());
+    }
+
+    /// Build a PNG out of scanlines that already start with their filter types.
+    fn encode_png(
+        dimensions: (u32, u32),
+        bit_depth: u8,
+        color_type: u8,
+        chunks: &[(&[u8; 4], &[u8])],
+        scanlines: &[u8],
+    ) -> Vec<u8> {
+        use flate2::write::ZlibEncoder;
+        let mut header = Vec::new();
+        header.write_u32::<BigEndian>(dimensions.1).unwrap();
+        header.write_u32::<BigEndian>(dimensions.0).unwrap();
+        header.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(scanlines).unwrap();
+
+        let mut png = PNG_SIGNATURE.to_vec();
+        write_png_chunk(&mut png, b"IHDR", &header).unwrap();
+        for (kind, data) in chunks {
+            write_png_chunk(&mut png, kind, data).unwrap();
+        }
+        write_png_chunk(&mut png, b"IDAT", &encoder.finish().unwrap()).unwrap();
+        write_png_chunk(&mut png, b"IEND", &[]).unwrap();
+        png
+    }
+
+    #[test]
+    fn reads_png() {
+        for channels in [1, 3].iter() {
+            let pixels: ImageData = (0..6 * channels).map(|i| i as u8 * 7).collect();
+            let mut file = Vec::new();
+            write_png(&mut file, (2, 3), *channels, &pixels).unwrap();
+            assert_eq!(
+                read_png(&mut &file[..]).unwrap(),
+                ((2, 3), *channels, pixels)
+            );
+        }
+
+        // Sub, Paeth, Average and Up filters.
+        let scanlines = [
+            1, 10, 5, 5, //
+            4, 0, 0, 0, //
+            3, 1, 1, 1, //
+            2, 1, 2, 3,
+        ];
+        let file = encode_png((4, 3), 8, 0, &[], &scanlines);
+        assert_eq!(
+            read_png(&mut &file[..]).unwrap(),
+            (
+                (4, 3),
+                1,
+                vec![10, 15, 20, 10, 15, 20, 6, 11, 16, 7, 13, 19]
+            )
+        );
+
+        // A 1 bit palette, where the first color is transparent.
+        let palette: &[u8] = &[0, 0, 0, 255, 0, 0];
+        let file = encode_png(
+            (1, 3),
+            1,
+            3,
+            &[(b"PLTE", palette), (b"tRNS", &[0])],
+            &[0, 0b1010_0000],
+        );
+        assert_eq!(
+            read_png(&mut &file[..]).unwrap(),
+            ((1, 3), 3, vec![255, 0, 0, 255, 255, 255, 255, 0, 0])
+        );
+
+        // Grayscale with alpha, and 16 bit grayscale.
+        let file = encode_png((1, 1), 8, 4, &[], &[0, 0, 128]);
+        assert_eq!(read_png(&mut &file[..]).unwrap(), ((1, 1), 1, vec![127]));
+        let file = encode_png((1, 1), 16, 0, &[], &[0, 0x12, 0x34]);
+        assert_eq!(read_png(&mut &file[..]).unwrap(), ((1, 1), 1, vec![0x12]));
+
+        let mut corrupted = encode_png((1, 1), 8, 0, &[], &[0, 0]);
+        corrupted[20] ^= 1;
+        assert!(read_png(&mut &corrupted[..]).is_err());
+        let mut interlaced = encode_png((1, 1), 8, 0, &[], &[0, 0]);
+        interlaced[28] = 1;
+        assert!(read_png(&mut &interlaced[..]).is_err());
+        assert!(read_png(&mut &encode_png((1, 2), 8, 0, &[], &[0, 0])[..]).is_err());
+        for empty in [(1, 0), (0, 1)].iter() {
+            let file = encode_png(*empty, 8, 0, &[], &[0]);
+            let error = read_png(&mut &file[..]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn loads_images() {
+        let pixels: ImageData = (0..12).map(|i| i * 20).collect();
+        for extension in ["png", "pgm"].iter() {
+            let path = env::temp_dir().join(format!(
+                "ml-rs-load-image-{}.{}",
+                std::process::id(),
+                extension
+            ));
+            save_image(&path, (3, 4), &pixels).unwrap();
+            assert_eq!(load_image(&path).unwrap(), ((3, 4), 1, pixels.clone()));
+            fs::write(&path, b"GIF89a").unwrap();
+            assert!(load_image(&path).is_err());
+            fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -1,4 +1,20 @@
 #![allow(dead_code)]
 #![allow(unused_variables)]
+pub mod augment;
+pub mod autodiff;
+pub mod cross_validation;
+pub mod dashboard;
+pub mod datasets;
+pub mod idx;
 pub mod image_data;
+pub mod image_file;
+pub mod metrics;
 pub mod network;
+pub mod normalize;
+pub mod preprocess;
+pub mod random;
+pub mod render;
+pub mod search;
+pub mod tabular;
+pub mod train;
+pub mod visualize;
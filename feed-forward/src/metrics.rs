@@ -0,0 +1,163 @@
+//! Structured logging of training metrics. Each record is appended to a CSV or JSON
+//! Lines file, so that runs can be plotted and compared outside of this program.
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// A snapshot of how training is going. One of these is recorded at the end of every
+/// epoch, and optionally every N batches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metrics {
+    pub epoch: usize,
+    /// The number of batches that have been trained in total.
+    pub step: usize,
+    /// The average cost over the training images seen so far in the epoch.
+    pub train_loss: f64,
+    /// The average cost over the validation images. These are only computed at the
+    /// end of an epoch.
+    pub validation_loss: Option<f64>,
+    pub validation_accuracy: Option<f64>,
+    pub learning_rate: f64,
+    /// Seconds spent training, including time from before a resume.
+    pub wall_time: f64,
+    /// The L2 norm of the most recent batch's averaged gradient.
+    pub gradient_norm: f64,
+}
+
+const CSV_HEADER: &str = "epoch,step,train_loss,validation_loss,validation_accuracy,learning_rate,wall_time,gradient_norm";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricsFormat {
+    Csv,
+    /// One JSON object per line. http://jsonlines.org/
+    JsonLines,
+}
+
+impl MetricsFormat {
+    /// Guess the format from a file extension, defaulting to CSV.
+    pub fn from_path(path: impl AsRef<Path>) -> MetricsFormat {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl") | Some("json") => MetricsFormat::JsonLines,
+            _ => MetricsFormat::Csv,
+        }
+    }
+}
+
+impl Metrics {
+    pub fn to_csv(&self) -> String {
+        let optional = |value: Option<f64>| value.map_or(String::new(), |v| v.to_string());
+        format!(
+            "{},{},{},{},{},{},{},{}",
+            self.epoch,
+            self.step,
+            self.train_loss,
+            optional(self.validation_loss),
+            optional(self.validation_accuracy),
+            self.learning_rate,
+            self.wall_time,
+            self.gradient_norm
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"epoch\":{},\"step\":{},\"train_loss\":{},\"validation_loss\":{},\"validation_accuracy\":{},\"learning_rate\":{},\"wall_time\":{},\"gradient_norm\":{}}}",
+            self.epoch,
+            self.step,
+            json_number(Some(self.train_loss)),
+            json_number(self.validation_loss),
+            json_number(self.validation_accuracy),
+            json_number(Some(self.learning_rate)),
+            json_number(Some(self.wall_time)),
+            json_number(Some(self.gradient_norm))
+        )
+    }
+}
+
+/// JSON has no representation for NaN or infinity, so those become null.
+pub fn json_number(value: Option<f64>) -> String {
+    match value {
+        Some(value) if value.is_finite() => value.to_string(),
+        _ => "null".to_string(),
+    }
+}
+
+/// Appends metrics to a file.
+#[derive(Debug)]
+pub struct MetricsLog {
+    writer: BufWriter<File>,
+    format: MetricsFormat,
+}
+
+impl MetricsLog {
+    /// Open the file for appending, creating it if needed. CSV files get a header row
+    /// when they are empty.
+    pub fn open(path: impl AsRef<Path>, format: MetricsFormat) -> io::Result<MetricsLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let is_empty = file.metadata()?.len() == 0;
+        let mut writer = BufWriter::new(file);
+        if is_empty && format == MetricsFormat::Csv {
+            writeln!(writer, "{}", CSV_HEADER)?;
+        }
+        Ok(MetricsLog { writer, format })
+    }
+
+    /// Append a record. The file is flushed after every record so that it can be
+    /// watched while training.
+    pub fn write(&mut self, metrics: &Metrics) -> io::Result<()> {
+        let line = match self.format {
+            MetricsFormat::Csv => metrics.to_csv(),
+            MetricsFormat::JsonLines => metrics.to_json(),
+        };
+        writeln!(self.writer, "{}", line)?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{env, fs};
+
+    fn metrics() -> Metrics {
+        Metrics {
+            epoch: 2,
+            step: 40,
+            train_loss: 0.25,
+            validation_loss: Some(0.5),
+            validation_accuracy: None,
+            learning_rate: 0.1,
+            wall_time: 1.5,
+            gradient_norm: f64::NAN,
+        }
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(metrics().to_csv(), "2,40,0.25,0.5,,0.1,1.5,NaN");
+        assert_eq!(
+            metrics().to_json(),
+            "{\"epoch\":2,\"step\":40,\"train_loss\":0.25,\"validation_loss\":0.5,\"validation_accuracy\":null,\"learning_rate\":0.1,\"wall_time\":1.5,\"gradient_norm\":null}"
+        );
+        assert_eq!(
+            MetricsFormat::from_path("run.jsonl"),
+            MetricsFormat::JsonLines
+        );
+        assert_eq!(MetricsFormat::from_path("run.csv"), MetricsFormat::Csv);
+    }
+
+    #[test]
+    fn appends_csv() {
+        let path = env::temp_dir().join(format!("ml-rs-metrics-{}.csv", std::process::id()));
+        for _ in 0..2 {
+            let mut log = MetricsLog::open(&path, MetricsFormat::Csv).unwrap();
+            log.write(&metrics()).unwrap();
+        }
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3, "The header is only written once");
+        assert_eq!(lines[0], CSV_HEADER);
+    }
+}
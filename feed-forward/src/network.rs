@@ -1,8 +1,20 @@
+use crate::autodiff::{sigmoid, Tape, Tensor, Var};
 use crate::image_data::Images;
+use crate::normalize::Normalization;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use rand::distributions::{IndependentSample, Range};
-use std::{cell::RefCell, f64::consts::E, iter::zip};
-
-#[derive(Debug)]
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::path::Path;
+use std::{cell::RefCell, iter::zip};
+
+/// The magic number at the start of a saved network, "MLNN".
+const MODEL_MAGIC: u32 = 0x4d4c_4e4e;
+/// Version 2 added the input normalization. Version 1 files are still loaded, and use
+/// the default normalization that they were trained with.
+const MODEL_VERSION: u32 = 2;
+
+#[derive(Debug, Clone)]
 pub struct Node {
     // The weights are edges in a graph that point back to the nodes in the previous
     // layer.
@@ -15,7 +27,7 @@ pub struct Node {
 /// A network layer. This can be the hidden layers and the output layer. The input
 /// layer is static, and is loaded in from data.
 /// The function for the activation is given as: a¹ = σ(Wa⁰ + b)
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Layer(Vec<Node>);
 
 impl Layer {
@@ -43,7 +55,11 @@ impl Layer {
     }
 
     pub fn len(&self) -> usize {
-        return self.0.len();
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &Node> + '_ {
@@ -52,12 +68,88 @@ impl Layer {
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Node> + '_ {
         self.0.iter_mut()
     }
+
+    /// The weights as a matrix, where each row holds the weights of one node.
+    pub fn weights_tensor(&self) -> Tensor {
+        let cols = self.0.first().map_or(0, |node| node.weights.len());
+        let data = self
+            .iter()
+            .flat_map(|node| node.weights.iter().copied())
+            .collect();
+        Tensor::new(self.len(), cols, data)
+    }
+
+    /// The biases as a column vector with one entry per node.
+    pub fn biases_tensor(&self) -> Tensor {
+        Tensor::column(self.iter().map(|node| node.bias).collect())
+    }
+
+    /// Subtract a step from the weights and biases, e.g. the learning rate multiplied
+    /// by the gradient.
+    pub fn subtract(&mut self, step: &LayerGradients) {
+        for (row, node) in self.0.iter_mut().enumerate() {
+            for (col, weight) in node.weights.iter_mut().enumerate() {
+                *weight -= step.weights.get(row, col);
+            }
+            node.bias -= step.biases.get(row, 0);
+        }
+    }
+
+    /// Record the activation function a¹ = σ(Wa⁰ + b) for this layer onto the tape.
+    /// The weights and biases are recorded as leaves so that their gradients can be
+    /// retrieved after running backward on the tape.
+    pub fn forward<'t>(&self, tape: &'t Tape, input: Var<'t>) -> LayerVars<'t> {
+        let weights = tape.var(self.weights_tensor());
+        let biases = tape.var(self.biases_tensor());
+        let activations = (weights.matmul(input) + biases).sigmoid();
+        LayerVars {
+            weights,
+            biases,
+            activations,
+        }
+    }
+}
+
+/// The variables that were recorded onto a tape by `Layer::forward`.
+#[derive(Debug, Clone, Copy)]
+pub struct LayerVars<'t> {
+    pub weights: Var<'t>,
+    pub biases: Var<'t>,
+    pub activations: Var<'t>,
+}
+
+/// The partial derivatives of the cost with respect to a layer's weights and biases.
+/// These have the same shape as `Layer::weights_tensor` and `Layer::biases_tensor`.
+#[derive(Debug, Clone)]
+pub struct LayerGradients {
+    pub weights: Tensor,
+    pub biases: Tensor,
+}
+
+/// The result of `Network::evaluate`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Evaluation {
+    /// The average cost.
+    pub loss: f64,
+    /// The fraction of images that were classified correctly, from 0 to 1.
+    pub accuracy: f64,
+}
+
+/// A label that the network picked for an image, from `Network::predict`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Prediction {
+    /// The index of the output node, which is the label.
+    pub label: usize,
+    /// The output node's share of the summed sigmoid outputs, from 0 to 1. The
+    /// probabilities of every label add up to 1. This is not a softmax, and the network
+    /// is not calibrated, so it is only a rough measure of confidence.
+    pub probability: f64,
 }
 
 /// All of the data needed for a neural network implementation.
 /// This is an implementation of:
 /// https://www.youtube.com/watch?v=aircAruvnKk&list=PLZHQObOWTQDNU6R1_67000Dx_ZCJB-3pi
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Network {
     /// This network could be made more generic, but for now only operate on the images
     /// provided by mnist.
@@ -74,6 +166,9 @@ pub struct Network {
     pub hidden_node_count: usize,
     /// How many answers do we want? This is the output node count.
     pub output_node_count: usize,
+    /// How the pixels are mapped to the input activations. This is saved with the
+    /// network, so that it runs on new images the same way it was trained.
+    pub normalization: Normalization,
 }
 
 impl Network {
@@ -108,6 +203,7 @@ impl Network {
             hidden_layer_count,
             hidden_node_count,
             output_node_count,
+            normalization: Normalization::default(),
         }
     }
 
@@ -115,13 +211,18 @@ impl Network {
     /// it would be better to use a linear algebra library, but for this didactic
     /// implementation, I'm doing the linear algebra myself.
     pub fn run(&self, image_index: usize) -> Vec<f64> {
+        let image_data = self.images.image(image_index);
+        self.run_image(image_data)
+    }
+
+    /// Run the network on image data that is not part of the network's images. It must
+    /// have the same pixel count.
+    pub fn run_image(&self, image_data: &[u8]) -> Vec<f64> {
         {
-            let image_data = self.images.list.get(image_index).unwrap();
             let input_layer = self.layers.first().expect("Failed to get first layer.");
 
-            for (node, pixel) in zip(input_layer.iter(), image_data) {
-                // Images come in as u8 ranged 0-255, map them to f64 ranged 0-1.
-                *node.activation.borrow_mut() = (*pixel as f64) / 255f64
+            for (node, input) in zip(input_layer.iter(), self.input_activations(image_data)) {
+                *node.activation.borrow_mut() = input;
             }
         }
 
@@ -148,6 +249,244 @@ impl Network {
             .collect()
     }
 
+    /// The most likely label for the image data, which must have a value for every
+    /// input node.
+    pub fn predict(&self, image_data: &[u8]) -> Prediction {
+        self.predict_top_k(image_data, 1)[0]
+    }
+
+    /// The `k` most likely labels for the image data, from the most to the least likely.
+    pub fn predict_top_k(&self, image_data: &[u8], k: usize) -> Vec<Prediction> {
+        assert_eq!(
+            image_data.len(),
+            self.input_node_count,
+            "The image does not match the input nodes."
+        );
+        let mut predictions = ranked_predictions(&self.run_image(image_data));
+        predictions.truncate(k);
+        predictions
+    }
+
+    /// The most likely label for every image.
+    pub fn predict_images(&self, images: &Images) -> Vec<Prediction> {
+        images.iter().map(|image| self.predict(image)).collect()
+    }
+
+    /// Images come in as u8 ranged 0-255, map them to f64 using the normalization.
+    fn input_activations(&self, image_data: &[u8]) -> Vec<f64> {
+        self.normalization.apply(image_data)
+    }
+
+    /// Compute the cost C₀ = Σ(aᴸ - y)² for a single image, along with its partial
+    /// derivatives for every layer after the input layer. The layers are recorded onto
+    /// an autodiff tape, so there is no need to hand-derive the chain rule.
+    pub fn gradients(&self, image_index: usize) -> (f64, Vec<LayerGradients>) {
+        let image_data = self.images.image(image_index);
+        let label = *self.images.labels.get(image_index).unwrap();
+        self.image_gradients(image_data, label)
+    }
+
+    /// The same as `gradients`, but for image data that is not part of the network's
+    /// images, such as an augmented copy of one.
+    pub fn image_gradients(&self, image_data: &[u8], label: u8) -> (f64, Vec<LayerGradients>) {
+        let label = label as usize;
+        let mut answer = vec![0.0; self.output_node_count];
+        *answer
+            .get_mut(label)
+            .expect("Network does not have enough output nodes for that answer") = 1.0;
+
+        let tape = Tape::new();
+        let mut activations = tape.var(Tensor::column(self.input_activations(image_data)));
+        let mut layer_vars = Vec::with_capacity(self.layers.len() - 1);
+        for layer in self.layers.iter().skip(1) {
+            let vars = layer.forward(&tape, activations);
+            activations = vars.activations;
+            layer_vars.push(vars);
+        }
+
+        let cost = (activations - tape.var(Tensor::column(answer)))
+            .square()
+            .sum();
+        let gradients = tape.backward(cost);
+
+        let layer_gradients = layer_vars
+            .iter()
+            .map(|vars| LayerGradients {
+                weights: gradients.wrt(vars.weights),
+                biases: gradients.wrt(vars.biases),
+            })
+            .collect();
+
+        (cost.scalar(), layer_gradients)
+    }
+
+    /// Take a step down the gradient, where there is one step for every layer after the
+    /// input layer.
+    pub fn update(&mut self, steps: &[LayerGradients]) {
+        assert_eq!(
+            steps.len(),
+            self.layers.len() - 1,
+            "There must be a step for every layer after the input layer."
+        );
+        for (layer, step) in zip(self.layers.iter_mut().skip(1), steps) {
+            layer.subtract(step);
+        }
+    }
+
+    /// Write out the weights and biases of every layer after the input layer. This is
+    /// shared by saved models and training checkpoints.
+    pub fn write_weights(&self, writer: &mut impl Write) -> io::Result<()> {
+        for layer in self.layers.iter().skip(1) {
+            layer.weights_tensor().write_to(writer)?;
+            layer.biases_tensor().write_to(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Read in weights that were written by `write_weights`. The network must already
+    /// have the same shape as the one that wrote them.
+    pub fn read_weights(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        for layer in self.layers.iter_mut().skip(1) {
+            let weights = Tensor::read_from(reader)?;
+            let biases = Tensor::read_from(reader)?;
+            if weights.shape() != layer.weights_tensor().shape()
+                || biases.shape() != layer.biases_tensor().shape()
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The saved weights do not match the shape of the network.",
+                ));
+            }
+            for (row, node) in layer.0.iter_mut().enumerate() {
+                for (col, weight) in node.weights.iter_mut().enumerate() {
+                    *weight = weights.get(row, col);
+                }
+                node.bias = biases.get(row, 0);
+            }
+        }
+        Ok(())
+    }
+
+    /// Save the network's shape and weights to a file. The images are not saved.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_u32::<BigEndian>(MODEL_MAGIC)?;
+        file.write_u32::<BigEndian>(MODEL_VERSION)?;
+        file.write_u64::<BigEndian>(self.images.dimensions.0 as u64)?;
+        file.write_u64::<BigEndian>(self.images.dimensions.1 as u64)?;
+        file.write_u64::<BigEndian>(self.input_node_count as u64)?;
+        file.write_u64::<BigEndian>(self.hidden_layer_count as u64)?;
+        file.write_u64::<BigEndian>(self.hidden_node_count as u64)?;
+        file.write_u64::<BigEndian>(self.output_node_count as u64)?;
+        self.normalization.write_to(&mut file)?;
+        self.write_weights(&mut file)?;
+        file.flush()
+    }
+
+    /// Load a network that was written by `save`. The network is returned without any
+    /// images, but they can be assigned to `Network::images` to run it.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Network> {
+        let mut file = BufReader::new(File::open(path)?);
+        if file.read_u32::<BigEndian>()? != MODEL_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The file is not a saved network.",
+            ));
+        }
+        let version = file.read_u32::<BigEndian>()?;
+        if version == 0 || version > MODEL_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The saved network is from an unsupported version.",
+            ));
+        }
+        let rows = file.read_u64::<BigEndian>()? as usize;
+        let cols = file.read_u64::<BigEndian>()? as usize;
+        let input_node_count = file.read_u64::<BigEndian>()? as usize;
+        let hidden_layer_count = file.read_u64::<BigEndian>()? as usize;
+        let hidden_node_count = file.read_u64::<BigEndian>()? as usize;
+        let output_node_count = file.read_u64::<BigEndian>()? as usize;
+
+        // Color images have more input nodes than pixels.
+        let pixels = rows
+            .checked_mul(cols)
+            .filter(|pixels| *pixels > 0)
+            .ok_or_else(|| invalid_data("The saved network has invalid dimensions."))?;
+        let channels = input_node_count / pixels;
+        if (channels != 1 && channels != 3) || input_node_count != pixels * channels {
+            return Err(invalid_data(
+                "The saved network has an invalid number of input nodes.",
+            ));
+        }
+        if output_node_count == 0 || (hidden_layer_count > 0 && hidden_node_count == 0) {
+            return Err(invalid_data("The saved network has an empty layer."));
+        }
+
+        let normalization = if version >= 2 {
+            Normalization::read_from(&mut file)?
+        } else {
+            Normalization::default()
+        };
+        if let Normalization::PerPixel { ref mean, .. } = normalization {
+            if mean.len() != input_node_count {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The normalization does not match the input nodes.",
+                ));
+            }
+        }
+
+        // Check that the file holds all of the weights before allocating them, as the
+        // layer sizes could be anything in a corrupted file.
+        let remaining = file
+            .get_ref()
+            .metadata()?
+            .len()
+            .saturating_sub(file.stream_position()?);
+        let weights_size = weights_size(
+            input_node_count,
+            hidden_layer_count,
+            hidden_node_count,
+            output_node_count,
+        );
+        if weights_size.is_none_or(|size| size > remaining) {
+            return Err(invalid_data("The saved network is truncated."));
+        }
+
+        let mut network = Network::new(
+            Images::with_channels((rows, cols), channels, Vec::new(), Vec::new()),
+            hidden_layer_count,
+            hidden_node_count,
+            output_node_count,
+        );
+        network.normalization = normalization;
+        network.read_weights(&mut file)?;
+        Ok(network)
+    }
+
+    /// Run the network over a set of images, and compute the average cost and the
+    /// fraction of images where the most activated output node matches the label.
+    pub fn evaluate(&self, images: &Images) -> Evaluation {
+        let mut total_cost = 0.0;
+        let mut correct = 0;
+        for (image_data, label) in zip(images.iter(), &images.labels) {
+            let outputs = self.run_image(image_data);
+            let label = *label as usize;
+            for (index, output) in outputs.iter().enumerate() {
+                let answer = if index == label { 1.0 } else { 0.0 };
+                total_cost += (output - answer) * (output - answer);
+            }
+            if ranked_predictions(&outputs)[0].label == label {
+                correct += 1;
+            }
+        }
+        let count = images.len().max(1) as f64;
+        Evaluation {
+            loss: total_cost / count,
+            accuracy: correct as f64 / count,
+        }
+    }
+
     fn cost(&self, answer_index: usize) -> Vec<f64> {
         let mut answer_vec = vec![0.0; self.output_node_count];
         let answer_node = answer_vec
@@ -164,15 +503,60 @@ impl Network {
     }
 }
 
-/// Sigmoid functions keep the values ranged from -1 to 1. This particular function
-/// is the logistic function given by: σ(x) = 1 / ( 1 + e^-x )
-///
-/// https://en.wikipedia.org/wiki/Sigmoid_function
-fn sigmoid(value: f64) -> f64 {
-    1.0 / (1.0 + E.powf(value))
+fn average_cost() {}
+
+/// The number of bytes that `write_weights` writes for a network of this shape, or
+/// `None` if it is too large to count. Each layer after the input has a tensor of
+/// weights and a tensor of biases, which are each a 16 byte shape followed by the values.
+fn weights_size(
+    input_node_count: usize,
+    hidden_layer_count: usize,
+    hidden_node_count: usize,
+    output_node_count: usize,
+) -> Option<u64> {
+    let layer_size = |nodes: usize, previous: usize| -> Option<u64> {
+        let values = (previous as u64)
+            .checked_add(1)?
+            .checked_mul(nodes as u64)?;
+        values.checked_mul(8)?.checked_add(32)
+    };
+    let (first_hidden, other_hidden, output) = if hidden_layer_count == 0 {
+        (0, 0, layer_size(output_node_count, input_node_count)?)
+    } else {
+        (
+            layer_size(hidden_node_count, input_node_count)?,
+            layer_size(hidden_node_count, hidden_node_count)?
+                .checked_mul(hidden_layer_count as u64 - 1)?,
+            layer_size(output_node_count, hidden_node_count)?,
+        )
+    };
+    first_hidden.checked_add(other_hidden)?.checked_add(output)
 }
 
-fn average_cost() {}
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Turn the output activations into predictions, from the most to the least likely.
+/// Ties go to the lower label.
+fn ranked_predictions(outputs: &[f64]) -> Vec<Prediction> {
+    assert!(!outputs.is_empty(), "The network has no output nodes.");
+    let total: f64 = outputs.iter().sum();
+    let mut predictions: Vec<Prediction> = outputs
+        .iter()
+        .enumerate()
+        .map(|(label, output)| Prediction {
+            label,
+            probability: if total > 0.0 {
+                output / total
+            } else {
+                1.0 / outputs.len() as f64
+            },
+        })
+        .collect();
+    predictions.sort_by(|a, b| b.probability.total_cmp(&a.probability));
+    predictions
+}
 
 #[cfg(test)]
 mod test {
@@ -191,23 +575,23 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::get_first, clippy::bool_assert_comparison)]
     fn network() {
         let pixel_count = 4;
         let hidden_layer_count = 2;
         let hidden_node_count = 3;
         let output_node_count = 5;
         let network = Network::new(
-            Images {
-                dimensions: (2, 2),
-                pixel_count: pixel_count,
-                list: vec![
+            Images::from_list(
+                (2, 2),
+                vec![
                     vec![0, 1, 2, 3],
                     vec![4, 5, 6, 7],
                     vec![8, 9, 10, 11],
                     vec![12, 13, 14, 15],
                 ],
-                labels: vec![0, 1, 2, 3],
-            },
+                vec![0, 1, 2, 3],
+            ),
             hidden_layer_count,
             hidden_node_count,
             output_node_count,
@@ -245,22 +629,242 @@ mod test {
     fn feed_forward_test() {
         let pixel_count = 4;
         let network = Network::new(
-            Images {
-                dimensions: (2, 2),
-                pixel_count,
-                list: vec![
+            Images::from_list(
+                (2, 2),
+                vec![
                     vec![0, 1, 2, 3],
                     vec![4, 5, 6, 7],
                     vec![8, 9, 10, 11],
                     vec![12, 13, 14, 15],
                 ],
-                labels: vec![0, 1, 2, 3],
-            },
+                vec![0, 1, 2, 3],
+            ),
             2, // hidden layer count
             3, // hidden node count
             5, // output node count
         );
         let results = network.run(0);
-        println!("results: {:?}", results);
+        assert_eq!(results.len(), 5, "There is a result for every output node");
+    }
+
+    #[test]
+    fn tape_matches_feed_forward() {
+        let network = Network::new(
+            Images::from_list((2, 2), vec![vec![0, 64, 128, 255]], vec![1]),
+            1, // hidden layer count
+            3, // hidden node count
+            2, // output node count
+        );
+        let results = network.run(0);
+
+        let tape = Tape::new();
+        let mut activations = tape.var(Tensor::column(
+            network.input_activations(network.images.image(0)),
+        ));
+        for layer in network.layers.iter().skip(1) {
+            activations = layer.forward(&tape, activations).activations;
+        }
+        for (a, b) in zip(&results, &activations.value().data) {
+            assert!((a - b).abs() < 1e-12, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn gradients() {
+        let mut network = Network::new(
+            Images::from_list((2, 2), vec![vec![0, 64, 128, 255]], vec![1]),
+            1, // hidden layer count
+            3, // hidden node count
+            2, // output node count
+        );
+        let (_, gradients) = network.gradients(0);
+        assert_eq!(
+            gradients.len(),
+            2,
+            "There is a gradient for each non-input layer"
+        );
+        assert_eq!(gradients[0].weights.shape(), (3, 4));
+        assert_eq!(gradients[0].biases.shape(), (3, 1));
+        assert_eq!(gradients[1].weights.shape(), (2, 3));
+
+        // Check a weight in the first hidden layer against a numerical estimate.
+        let cost = |network: &Network| {
+            let answer = [0.0, 1.0];
+            zip(network.run(0), answer)
+                .map(|(a, y)| (a - y) * (a - y))
+                .sum::<f64>()
+        };
+        let epsilon = 1e-6;
+        network.layers[1].0[2].weights[1] += epsilon;
+        let above = cost(&network);
+        network.layers[1].0[2].weights[1] -= 2.0 * epsilon;
+        let below = cost(&network);
+        let numerical = (above - below) / (2.0 * epsilon);
+        let analytical = gradients[0].weights.get(2, 1);
+        assert!(
+            (numerical - analytical).abs() < 1e-6,
+            "{} != {}",
+            numerical,
+            analytical
+        );
+    }
+
+    #[test]
+    fn predictions() {
+        let images = Images::new((1, 2), vec![0, 255, 255, 0], vec![2, 1]);
+        let mut network = Network::new(images.clone(), 1, 2, 4);
+        // Zero weights leave the outputs up to the biases.
+        let output_layer = network.layers.last_mut().unwrap();
+        for (node, bias) in zip(output_layer.iter_mut(), &[0.0, 1.0, 2.0, 1.0]) {
+            node.weights.iter_mut().for_each(|weight| *weight = 0.0);
+            node.bias = *bias;
+        }
+
+        let total = sigmoid(0.0) + 2.0 * sigmoid(1.0) + sigmoid(2.0);
+        let prediction = network.predict(images.image(0));
+        assert_eq!(prediction.label, 2);
+        assert!((prediction.probability - sigmoid(2.0) / total).abs() < 1e-12);
+
+        let top = network.predict_top_k(images.image(0), 3);
+        let labels: Vec<usize> = top.iter().map(|prediction| prediction.label).collect();
+        assert_eq!(labels, vec![2, 1, 3], "Ties go to the lower label");
+        let all = network.predict_top_k(images.image(0), 10);
+        assert_eq!(all.len(), 4);
+        let sum: f64 = all.iter().map(|prediction| prediction.probability).sum();
+        assert!((sum - 1.0).abs() < 1e-12);
+
+        assert_eq!(network.predict_images(&images), vec![prediction; 2]);
+        assert_eq!(network.evaluate(&images).accuracy, 0.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "The image does not match the input nodes.")]
+    fn predict_rejects_mismatched_images() {
+        let network = Network::new(Images::new((1, 2), vec![0, 255], vec![1]), 1, 2, 2);
+        network.predict(&[0, 255, 0]);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let network = Network::new(
+            Images::from_list((2, 2), vec![vec![0, 64, 128, 255]], vec![1]),
+            2, // hidden layer count
+            3, // hidden node count
+            2, // output node count
+        );
+        let path = std::env::temp_dir().join(format!("ml-rs-network-{}", std::process::id()));
+        network.save(&path).unwrap();
+        let mut loaded = Network::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.images.dimensions, (2, 2));
+        assert_eq!(loaded.layers.len(), network.layers.len());
+        for (a, b) in zip(&loaded.layers, &network.layers).skip(1) {
+            assert_eq!(a.weights_tensor(), b.weights_tensor());
+            assert_eq!(a.biases_tensor(), b.biases_tensor());
+        }
+        loaded.images = network.images.clone();
+        assert_eq!(loaded.run(0), network.run(0));
+    }
+
+    #[test]
+    fn load_rejects_corrupted_headers() {
+        let network = Network::new(
+            Images::from_list((2, 2), vec![vec![0, 64, 128, 255]], vec![1]),
+            1, // hidden layer count
+            3, // hidden node count
+            2, // output node count
+        );
+        let path =
+            std::env::temp_dir().join(format!("ml-rs-network-corrupted-{}", std::process::id()));
+        network.save(&path).unwrap();
+        let saved = std::fs::read(&path).unwrap();
+
+        // The offsets of the counts in the header, after the magic number and version.
+        let (rows, input, hidden_layers, hidden_nodes, output) = (8, 24, 32, 40, 48);
+        let corruptions = [
+            (rows, u64::MAX),
+            (input, 8),
+            (input, 5),
+            (hidden_layers, u64::MAX),
+            (hidden_nodes, 1 << 40),
+            (hidden_nodes, 0),
+            (output, 0),
+        ];
+        for (offset, value) in corruptions.iter() {
+            let mut bytes = saved.clone();
+            bytes[*offset..*offset + 8].copy_from_slice(&value.to_be_bytes());
+            std::fs::write(&path, &bytes).unwrap();
+            let error = Network::load(&path).unwrap_err();
+            assert_eq!(
+                error.kind(),
+                io::ErrorKind::InvalidData,
+                "{} at {}",
+                value,
+                offset
+            );
+        }
+        std::fs::write(&path, &saved[..saved.len() - 8]).unwrap();
+        let error = Network::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData, "Truncated");
+    }
+
+    #[test]
+    fn saves_normalization() {
+        let mut network = Network::new(
+            Images::from_list(
+                (2, 2),
+                vec![vec![0, 64, 128, 255], vec![10, 20, 30, 40]],
+                vec![1, 0],
+            ),
+            1, // hidden layer count
+            3, // hidden node count
+            2, // output node count
+        );
+        network.normalization = Normalization::per_pixel(&network.images);
+        let path = std::env::temp_dir().join(format!(
+            "ml-rs-network-normalization-{}",
+            std::process::id()
+        ));
+        network.save(&path).unwrap();
+        let mut loaded = Network::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.normalization, network.normalization);
+        loaded.images = network.images.clone();
+        assert_eq!(loaded.run(1), network.run(1));
+
+        network.normalization = Normalization::PerPixel {
+            mean: vec![0.0; 3],
+            std_dev: vec![1.0; 3],
+        };
+        network.save(&path).unwrap();
+        let result = Network::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn color_images() {
+        let images = Images::with_channels(
+            (2, 2),
+            3,
+            (0..24).map(|pixel| pixel * 10).collect(),
+            vec![1, 0],
+        );
+        let network = Network::new(images, 1, 3, 2);
+        assert_eq!(
+            network.input_node_count, 12,
+            "There is an input per channel"
+        );
+        assert_eq!(network.run(1).len(), 2);
+
+        let path = std::env::temp_dir().join(format!("ml-rs-network-color-{}", std::process::id()));
+        network.save(&path).unwrap();
+        let loaded = Network::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.images.channels, 3);
+        assert_eq!(loaded.images.pixel_count, 12);
     }
 }
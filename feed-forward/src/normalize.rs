@@ -0,0 +1,264 @@
+//! Preprocessing that maps the u8 pixels of an image to the f64 input activations of
+//! the network. Some of these are fitted to the training set, and the fitted
+//! statistics are saved along with the network, so that running a loaded network
+//! applies exactly the same transform that it was trained with.
+use crate::autodiff::Tensor;
+use crate::image_data::Images;
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read, Write};
+
+/// Standard deviations smaller than this are treated as 1, so that pixels that are
+/// the same in every image (such as the border of an MNIST digit) become 0 instead of
+/// dividing by zero.
+const MIN_STD_DEV: f64 = 1e-8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Normalization {
+    /// Scale the pixels linearly so that `min` maps to 0 and `max` maps to 1. The
+    /// default is the full 0-255 range.
+    MinMax { min: f64, max: f64 },
+    /// Subtract each pixel's mean and divide by its standard deviation, where the
+    /// statistics are computed separately for every pixel position.
+    PerPixel { mean: Vec<f64>, std_dev: Vec<f64> },
+    /// Subtract the mean and divide by the standard deviation of all of the pixels.
+    Global { mean: f64, std_dev: f64 },
+}
+
+impl Default for Normalization {
+    fn default() -> Normalization {
+        Normalization::MinMax {
+            min: 0.0,
+            max: 255.0,
+        }
+    }
+}
+
+impl Normalization {
+    /// Min-max scaling using the darkest and brightest pixel in the images.
+    pub fn min_max(images: &Images) -> Normalization {
+        let pixels = images.pixels().iter();
+        match (pixels.clone().min(), pixels.max()) {
+            (Some(min), Some(max)) => Normalization::MinMax {
+                min: *min as f64,
+                max: *max as f64,
+            },
+            _ => Normalization::default(),
+        }
+    }
+
+    /// Standardize every pixel position using statistics from the images.
+    pub fn per_pixel(images: &Images) -> Normalization {
+        let count = images.len().max(1) as f64;
+        let mut mean = vec![0.0; images.pixel_count];
+        for image_data in images.iter() {
+            for (sum, pixel) in mean.iter_mut().zip(image_data) {
+                *sum += *pixel as f64;
+            }
+        }
+        for sum in mean.iter_mut() {
+            *sum /= count;
+        }
+
+        let mut std_dev = vec![0.0; images.pixel_count];
+        for image_data in images.iter() {
+            for ((sum, mean), pixel) in std_dev.iter_mut().zip(&mean).zip(image_data) {
+                *sum += (*pixel as f64 - mean) * (*pixel as f64 - mean);
+            }
+        }
+        for sum in std_dev.iter_mut() {
+            *sum = (*sum / count).sqrt();
+        }
+
+        Normalization::PerPixel { mean, std_dev }
+    }
+
+    /// Standardize using the statistics of every pixel in the images.
+    pub fn global(images: &Images) -> Normalization {
+        let count = images.pixels().len().max(1) as f64;
+        let pixels = images.pixels().iter().map(|pixel| *pixel as f64);
+        let mean = pixels.clone().sum::<f64>() / count;
+        let variance = pixels
+            .map(|pixel| (pixel - mean) * (pixel - mean))
+            .sum::<f64>()
+            / count;
+        Normalization::Global {
+            mean,
+            std_dev: variance.sqrt(),
+        }
+    }
+
+    /// Parse the name used on the command line, and fit it to the images.
+    pub fn fit(name: &str, images: &Images) -> Option<Normalization> {
+        match name {
+            "none" => Some(Normalization::default()),
+            "min-max" => Some(Normalization::min_max(images)),
+            "per-pixel" => Some(Normalization::per_pixel(images)),
+            "global" => Some(Normalization::global(images)),
+            _ => None,
+        }
+    }
+
+    /// Map the pixels of an image to input activations.
+    pub fn apply(&self, image_data: &[u8]) -> Vec<f64> {
+        match self {
+            Normalization::MinMax { min, max } => {
+                let range = if max > min { max - min } else { 1.0 };
+                image_data
+                    .iter()
+                    .map(|pixel| (*pixel as f64 - min) / range)
+                    .collect()
+            }
+            Normalization::PerPixel { mean, std_dev } => {
+                assert_eq!(
+                    image_data.len(),
+                    mean.len(),
+                    "The image does not have the same pixel count as the normalization."
+                );
+                image_data
+                    .iter()
+                    .zip(mean.iter().zip(std_dev))
+                    .map(|(pixel, (mean, std_dev))| standardize(*pixel, *mean, *std_dev))
+                    .collect()
+            }
+            Normalization::Global { mean, std_dev } => image_data
+                .iter()
+                .map(|pixel| standardize(*pixel, *mean, *std_dev))
+                .collect(),
+        }
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        match self {
+            Normalization::MinMax { min, max } => {
+                writer.write_u8(0)?;
+                Tensor::column(vec![*min, *max]).write_to(writer)
+            }
+            Normalization::PerPixel { mean, std_dev } => {
+                writer.write_u8(1)?;
+                Tensor::column(mean.clone()).write_to(writer)?;
+                Tensor::column(std_dev.clone()).write_to(writer)
+            }
+            Normalization::Global { mean, std_dev } => {
+                writer.write_u8(2)?;
+                Tensor::column(vec![*mean, *std_dev]).write_to(writer)
+            }
+        }
+    }
+
+    /// Read in a normalization that was written by `write_to`.
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Normalization> {
+        let pair = |reader: &mut _| -> io::Result<(f64, f64)> {
+            let tensor = Tensor::read_from(reader)?;
+            if tensor.shape() != (2, 1) {
+                return Err(invalid_data());
+            }
+            Ok((tensor.get(0, 0), tensor.get(1, 0)))
+        };
+        match reader.read_u8()? {
+            0 => {
+                let (min, max) = pair(reader)?;
+                Ok(Normalization::MinMax { min, max })
+            }
+            1 => {
+                let mean = Tensor::read_from(reader)?;
+                let std_dev = Tensor::read_from(reader)?;
+                if mean.shape() != std_dev.shape() || mean.cols != 1 {
+                    return Err(invalid_data());
+                }
+                Ok(Normalization::PerPixel {
+                    mean: mean.data,
+                    std_dev: std_dev.data,
+                })
+            }
+            2 => {
+                let (mean, std_dev) = pair(reader)?;
+                Ok(Normalization::Global { mean, std_dev })
+            }
+            _ => Err(invalid_data()),
+        }
+    }
+}
+
+fn standardize(pixel: u8, mean: f64, std_dev: f64) -> f64 {
+    let std_dev = if std_dev < MIN_STD_DEV { 1.0 } else { std_dev };
+    (pixel as f64 - mean) / std_dev
+}
+
+fn invalid_data() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "The saved normalization is not valid.",
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn images() -> Images {
+        Images::from_list(
+            (1, 2),
+            vec![vec![10, 0], vec![30, 0], vec![50, 0]],
+            vec![0, 1, 2],
+        )
+    }
+
+    #[test]
+    fn min_max() {
+        assert_eq!(Normalization::default().apply(&[0, 255]), vec![0.0, 1.0]);
+        let normalization = Normalization::min_max(&images());
+        assert_eq!(
+            normalization,
+            Normalization::MinMax {
+                min: 0.0,
+                max: 50.0
+            }
+        );
+        assert_eq!(normalization.apply(&[25, 50]), vec![0.5, 1.0]);
+    }
+
+    #[test]
+    fn per_pixel() {
+        let normalization = Normalization::per_pixel(&images());
+        let std_dev = (800.0f64 / 3.0).sqrt();
+        assert_eq!(
+            normalization,
+            Normalization::PerPixel {
+                mean: vec![30.0, 0.0],
+                std_dev: vec![std_dev, 0.0],
+            }
+        );
+        let activations = normalization.apply(&[50, 0]);
+        assert!((activations[0] - 20.0 / std_dev).abs() < 1e-12);
+        assert_eq!(activations[1], 0.0, "Constant pixels are only centered");
+    }
+
+    #[test]
+    fn global() {
+        let normalization = Normalization::global(&images());
+        match normalization {
+            Normalization::Global { mean, std_dev } => {
+                assert!((mean - 15.0).abs() < 1e-12);
+                let activations = normalization.apply(&[15, 15]);
+                assert_eq!(activations, vec![0.0, 0.0]);
+                assert!(std_dev > 0.0);
+            }
+            _ => panic!("Expected a global normalization"),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for normalization in [
+            Normalization::default(),
+            Normalization::per_pixel(&images()),
+            Normalization::global(&images()),
+        ] {
+            let mut bytes = Vec::new();
+            normalization.write_to(&mut bytes).unwrap();
+            let read = Normalization::read_from(&mut bytes.as_slice()).unwrap();
+            assert_eq!(read, normalization);
+        }
+        assert!(Normalization::read_from(&mut [9u8].as_slice()).is_err());
+    }
+}
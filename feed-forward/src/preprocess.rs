@@ -0,0 +1,265 @@
+//! Prepare images from outside of the datasets, like photos or scans of handwriting, so
+//! that they look like the MNIST digits. MNIST digits are light on a dark background.
+//! Each one was scaled to fit in a 20x20 box, keeping its aspect ratio, and then placed
+//! in a 28x28 image so that the center of mass of its pixels is in the middle.
+use crate::augment::bilinear;
+use crate::image_data::ImageData;
+
+/// The width and height of an MNIST image.
+pub const MNIST_SIZE: usize = 28;
+/// The width and height of the box that the MNIST digits are scaled to fit inside.
+pub const MNIST_BOX_SIZE: usize = 20;
+/// Pixels that are dimmer than this, after the background is removed, are ignored when
+/// finding the edges of the digit.
+const NOISE_LEVEL: u8 = 32;
+/// The number of samples taken along each axis for every output pixel when scaling.
+const SUPERSAMPLING: usize = 4;
+
+/// Whether to invert the image so that the digit is light on a dark background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invert {
+    /// Invert the image if its border is mostly light, like a digit written on paper.
+    Auto,
+    Always,
+    Never,
+}
+
+impl Invert {
+    pub fn from_name(name: &str) -> Option<Invert> {
+        match name {
+            "auto" => Some(Invert::Auto),
+            "always" => Some(Invert::Always),
+            "never" => Some(Invert::Never),
+            _ => None,
+        }
+    }
+}
+
+/// Turn a grayscale image with the dimensions (rows, columns) into a 28x28 image in the
+/// style of MNIST. An image with nothing in it is returned as a black image.
+pub fn mnist_style(image: &[u8], dimensions: (usize, usize), invert: Invert) -> ImageData {
+    let should_invert = match invert {
+        Invert::Auto => {
+            let border = border(image, dimensions);
+            let sum: f64 = border.iter().map(|pixel| *pixel as f64).sum();
+            sum / border.len().max(1) as f64 > 127.0
+        }
+        Invert::Always => true,
+        Invert::Never => false,
+    };
+    let image: ImageData = if should_invert {
+        image.iter().map(|pixel| 255 - pixel).collect()
+    } else {
+        image.to_vec()
+    };
+    let image = remove_background(&image, dimensions);
+
+    let mut output = vec![0; MNIST_SIZE * MNIST_SIZE];
+    let (top, left, bottom, right) = match bounding_box(&image, dimensions, NOISE_LEVEL) {
+        Some(bounds) => bounds,
+        None => return output,
+    };
+    let cropped = crop(&image, dimensions, (top, left, bottom, right));
+    let (dimensions, fitted) = fit(&cropped, (bottom - top, right - left), MNIST_BOX_SIZE);
+    let (rows, cols) = dimensions;
+
+    // Move the center of mass to the middle, without pushing any of the digit out of
+    // the image.
+    let (x, y) = center_of_mass(&fitted, dimensions).unwrap_or((0.0, 0.0));
+    let middle = (MNIST_SIZE as f64 - 1.0) / 2.0;
+    let offset = |center: f64, size: usize| {
+        ((middle - center).round().max(0.0) as usize).min(MNIST_SIZE - size)
+    };
+    let (row_offset, col_offset) = (offset(y, rows), offset(x, cols));
+    for (row, line) in fitted.chunks(cols).enumerate() {
+        let start = (row + row_offset) * MNIST_SIZE + col_offset;
+        output[start..start + cols].copy_from_slice(line);
+    }
+    output
+}
+
+/// The pixels around the edge of the image.
+fn border(image: &[u8], dimensions: (usize, usize)) -> ImageData {
+    let (rows, cols) = dimensions;
+    (0..rows)
+        .flat_map(|row| (0..cols).map(move |col| (row, col)))
+        .filter(|(row, col)| *row == 0 || *col == 0 || *row == rows - 1 || *col == cols - 1)
+        .map(|(row, col)| image[row * cols + col])
+        .collect()
+}
+
+/// Paper and photos are rarely perfectly black once they are inverted. Subtract the
+/// median of the border, and stretch what is left so the brightest pixel is white.
+fn remove_background(image: &[u8], dimensions: (usize, usize)) -> ImageData {
+    let mut border = border(image, dimensions);
+    border.sort_unstable();
+    let background = border.get(border.len() / 2).cloned().unwrap_or(0) as u32;
+    let brightest = image.iter().cloned().max().unwrap_or(0) as u32;
+    if brightest <= background {
+        return vec![0; image.len()];
+    }
+    image
+        .iter()
+        .map(|pixel| {
+            let value = (*pixel as u32).saturating_sub(background);
+            (value * 255 / (brightest - background)) as u8
+        })
+        .collect()
+}
+
+/// The smallest (top, left, bottom, right) box that holds every pixel brighter than the
+/// threshold, where the bottom and right are exclusive.
+pub fn bounding_box(
+    image: &[u8],
+    dimensions: (usize, usize),
+    threshold: u8,
+) -> Option<(usize, usize, usize, usize)> {
+    let (rows, cols) = dimensions;
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+    for row in 0..rows {
+        for col in 0..cols {
+            if image[row * cols + col] > threshold {
+                let (top, left, bottom, right) = bounds.unwrap_or((row, col, row + 1, col + 1));
+                bounds = Some((
+                    top.min(row),
+                    left.min(col),
+                    bottom.max(row + 1),
+                    right.max(col + 1),
+                ));
+            }
+        }
+    }
+    bounds
+}
+
+fn crop(
+    image: &[u8],
+    dimensions: (usize, usize),
+    (top, left, bottom, right): (usize, usize, usize, usize),
+) -> ImageData {
+    let cols = dimensions.1;
+    (top..bottom)
+        .flat_map(|row| &image[row * cols + left..row * cols + right])
+        .cloned()
+        .collect()
+}
+
+/// Scale the image so that its longest side is `size`, keeping the aspect ratio. Each
+/// output pixel is the average of several samples, so that shrinking a large image
+/// smooths it rather than skipping over thin strokes.
+pub fn fit(image: &[u8], dimensions: (usize, usize), size: usize) -> ((usize, usize), ImageData) {
+    let (rows, cols) = dimensions;
+    let longest = rows.max(cols).max(1) as f64;
+    let scaled = |length: usize| ((length as f64 * size as f64 / longest).round() as usize).max(1);
+    let (new_rows, new_cols) = (scaled(rows), scaled(cols));
+    let (scale_y, scale_x) = (rows as f64 / new_rows as f64, cols as f64 / new_cols as f64);
+
+    let mut output = Vec::with_capacity(new_rows * new_cols);
+    for y in 0..new_rows {
+        for x in 0..new_cols {
+            let mut sum = 0.0;
+            for sample_y in 0..SUPERSAMPLING {
+                for sample_x in 0..SUPERSAMPLING {
+                    let offset = |sample: usize| (sample as f64 + 0.5) / SUPERSAMPLING as f64;
+                    // Pixel centers are at whole numbers in the source image.
+                    let source_x = (x as f64 + offset(sample_x)) * scale_x - 0.5;
+                    let source_y = (y as f64 + offset(sample_y)) * scale_y - 0.5;
+                    let source_x = source_x.clamp(0.0, cols as f64 - 1.0);
+                    let source_y = source_y.clamp(0.0, rows as f64 - 1.0);
+                    sum += bilinear(image, dimensions, source_x, source_y);
+                }
+            }
+            let mean = sum / (SUPERSAMPLING * SUPERSAMPLING) as f64;
+            output.push(mean.round().clamp(0.0, 255.0) as u8);
+        }
+    }
+    ((new_rows, new_cols), output)
+}
+
+/// The (x, y) position of the center of mass of the pixels, with pixel centers at
+/// whole numbers. An image with nothing in it has no center of mass.
+pub fn center_of_mass(image: &[u8], dimensions: (usize, usize)) -> Option<(f64, f64)> {
+    let cols = dimensions.1.max(1);
+    let (mut total, mut x, mut y) = (0.0, 0.0, 0.0);
+    for (index, pixel) in image.iter().enumerate() {
+        let mass = *pixel as f64;
+        total += mass;
+        x += mass * (index % cols) as f64;
+        y += mass * (index / cols) as f64;
+    }
+    if total == 0.0 {
+        None
+    } else {
+        Some((x / total, y / total))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A dark 2x4 bar on a light 10x12 background, like a short stroke on paper.
+    fn paper() -> ((usize, usize), ImageData) {
+        let (rows, cols) = (10, 12);
+        let mut image = vec![240; rows * cols];
+        for row in 2..4 {
+            for col in 1..5 {
+                image[row * cols + col] = 10;
+            }
+        }
+        ((rows, cols), image)
+    }
+
+    #[test]
+    fn bounding_boxes() {
+        let (dimensions, image) = paper();
+        let inverted: ImageData = image.iter().map(|pixel| 255 - pixel).collect();
+        assert_eq!(bounding_box(&inverted, dimensions, 32), Some((2, 1, 4, 5)));
+        assert_eq!(bounding_box(&[0; 4], (2, 2), 32), None);
+    }
+
+    #[test]
+    fn fits_the_longest_side() {
+        let ((rows, cols), image) = fit(&[255; 8], (2, 4), 20);
+        assert_eq!((rows, cols), (10, 20));
+        assert!(image.iter().all(|pixel| *pixel == 255));
+
+        let ((rows, cols), image) = fit(&[0, 255, 255, 0], (4, 1), 2);
+        assert_eq!((rows, cols), (2, 1));
+        assert_eq!(image, vec![128, 128], "Shrinking averages the pixels");
+    }
+
+    #[test]
+    fn centers_of_mass() {
+        assert_eq!(center_of_mass(&[0, 0, 0, 10], (2, 2)), Some((1.0, 1.0)));
+        assert_eq!(center_of_mass(&[10, 0, 0, 10], (2, 2)), Some((0.5, 0.5)));
+        assert_eq!(center_of_mass(&[0; 4], (2, 2)), None);
+    }
+
+    #[test]
+    fn mnist_images() {
+        let (dimensions, image) = paper();
+        let output = mnist_style(&image, dimensions, Invert::Auto);
+        assert_eq!(output.len(), MNIST_SIZE * MNIST_SIZE);
+        let size = (MNIST_SIZE, MNIST_SIZE);
+        assert_eq!(
+            bounding_box(&output, size, NOISE_LEVEL),
+            Some((9, 4, 19, 24)),
+            "The bar is 20 wide and centered"
+        );
+        let (x, y) = center_of_mass(&output, size).unwrap();
+        assert!((x - 13.5).abs() <= 0.5 && (y - 13.5).abs() <= 0.5);
+
+        assert_eq!(
+            mnist_style(&image, dimensions, Invert::Never),
+            vec![0; MNIST_SIZE * MNIST_SIZE],
+            "Without inverting, nothing is brighter than the page"
+        );
+        assert_eq!(
+            mnist_style(&[200; 4], (2, 2), Invert::Auto),
+            vec![0; MNIST_SIZE * MNIST_SIZE]
+        );
+        assert_eq!(Invert::from_name("always"), Some(Invert::Always));
+        assert_eq!(Invert::from_name("sometimes"), None);
+    }
+}
@@ -0,0 +1,116 @@
+/// A small seeded random number generator. The `rand` crate's generators don't
+/// expose their internal state, but training needs to be able to save the state in
+/// a checkpoint and pick up exactly where it left off. This is an implementation of
+/// SplitMix64, which has a single u64 of state.
+///
+/// http://xoshiro.di.unimi.it/splitmix64.c
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random { state: seed }
+    }
+
+    /// The internal state, which can be used to restore the generator with
+    /// `Random::new`.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A value in the range [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A value in the range [low, high).
+    pub fn range(&mut self, low: f64, high: f64) -> f64 {
+        low + (high - low) * self.next_f64()
+    }
+
+    /// A normally distributed value with a mean of 0 and a standard deviation of 1,
+    /// using the Box-Muller transform.
+    ///
+    /// https://en.wikipedia.org/wiki/Box%E2%80%93Muller_transform
+    pub fn normal(&mut self) -> f64 {
+        // Avoid taking the log of 0.
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+
+    /// An index in the range [0, count).
+    pub fn below(&mut self, count: usize) -> usize {
+        ((self.next_u64() as u128 * count as u128) >> 64) as usize
+    }
+
+    /// Shuffle the slice in place using the Fisher-Yates algorithm.
+    pub fn shuffle<T>(&mut self, slice: &mut [T]) {
+        for i in (1..slice.len()).rev() {
+            let j = self.below(i + 1);
+            slice.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deterministic() {
+        let mut a = Random::new(42);
+        let mut b = Random::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+
+        let mut c = Random::new(a.state());
+        assert_eq!(a.next_u64(), c.next_u64(), "The state can be restored");
+    }
+
+    #[test]
+    fn ranges() {
+        let mut random = Random::new(7);
+        for _ in 0..1000 {
+            let value = random.next_f64();
+            assert!((0.0..1.0).contains(&value));
+            assert!(random.below(10) < 10);
+        }
+    }
+
+    #[test]
+    fn normal() {
+        let mut random = Random::new(11);
+        let samples: Vec<f64> = (0..10000).map(|_| random.normal()).collect();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let variance =
+            samples.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / samples.len() as f64;
+        assert!(mean.abs() < 0.05, "The mean was {}", mean);
+        assert!(
+            (variance - 1.0).abs() < 0.05,
+            "The variance was {}",
+            variance
+        );
+    }
+
+    #[test]
+    fn shuffle() {
+        let mut random = Random::new(3);
+        let mut values: Vec<usize> = (0..50).collect();
+        random.shuffle(&mut values);
+        assert_ne!(values, (0..50).collect::<Vec<usize>>());
+        values.sort();
+        assert_eq!(values, (0..50).collect::<Vec<usize>>());
+    }
+}
@@ -0,0 +1,291 @@
+//! Draw images as text for the terminal. Every mode returns a `String`, so the output
+//! can be printed, compared in tests, or laid out next to other images.
+//!
+//! The shaded mode uses the 256-color palette. term-painter applies its colors by
+//! writing straight to the terminal rather than into a string, so the ANSI escape codes
+//! for the palette are written here instead.
+use crate::image_data::grayscale;
+use std::iter::zip;
+use std::str::FromStr;
+
+/// The characters of the grayscale ramp, from black to white.
+const RAMP: &[char] = &[' ', '.', ':', '-', '=', '+', '*', '#', '%', '@'];
+/// The first of the 24 grays at the end of the 256-color palette.
+const GRAY_START: u8 = 232;
+const GRAY_COUNT: u8 = 24;
+/// The 6x6x6 color cube in the 256-color palette starts after the 16 standard colors.
+const CUBE_START: u8 = 16;
+const RESET: &str = "\x1b[0m";
+
+/// An index into the 256-color palette, where the first 16 are the standard and bright
+/// colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaletteColor(pub u8);
+
+impl PaletteColor {
+    pub const RED: PaletteColor = PaletteColor(1);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    /// An "X" for pixels brighter than the threshold, and a "." for the rest.
+    Threshold(u8),
+    /// A character for every pixel, from a ramp of increasingly dense characters.
+    Ramp,
+    /// Two rows of pixels per line of text, using the Unicode half block characters
+    /// for pixels brighter than the threshold. This keeps the image's aspect ratio.
+    HalfBlocks(u8),
+    /// Like `HalfBlocks`, but the top and bottom of every character are shaded using
+    /// the 256-color palette. Color images are shaded in color.
+    Shaded,
+}
+
+impl FromStr for RenderMode {
+    type Err = String;
+
+    /// Parse the name used on the command line.
+    fn from_str(name: &str) -> Result<RenderMode, String> {
+        match name {
+            "threshold" => Ok(RenderMode::Threshold(50)),
+            "ramp" => Ok(RenderMode::Ramp),
+            "half-blocks" => Ok(RenderMode::HalfBlocks(50)),
+            "shaded" => Ok(RenderMode::Shaded),
+            _ => Err(format!("Unknown render mode {:?}", name)),
+        }
+    }
+}
+
+/// Render an image with the dimensions (rows, columns), where each pixel has
+/// `channels` bytes. Every line ends with a newline.
+pub fn render_image(
+    image: &[u8],
+    dimensions: (usize, usize),
+    channels: usize,
+    mode: RenderMode,
+) -> String {
+    let (rows, cols) = dimensions;
+    assert_eq!(
+        image.len(),
+        rows * cols * channels,
+        "The image does not match the dimensions."
+    );
+    let gray = grayscale(image, channels);
+    let mut string = String::new();
+    match mode {
+        RenderMode::Threshold(threshold) => {
+            for row in gray.chunks(cols.max(1)).take(rows) {
+                string.extend(
+                    row.iter()
+                        .map(|pixel| if *pixel > threshold { 'X' } else { '.' }),
+                );
+                string.push('\n');
+            }
+        }
+        RenderMode::Ramp => {
+            for row in gray.chunks(cols.max(1)).take(rows) {
+                string.extend(
+                    row.iter()
+                        .map(|pixel| RAMP[*pixel as usize * (RAMP.len() - 1) / 255]),
+                );
+                string.push('\n');
+            }
+        }
+        RenderMode::HalfBlocks(threshold) => {
+            for top in (0..rows).step_by(2) {
+                for col in 0..cols {
+                    let is_bright = |row: usize| row < rows && gray[row * cols + col] > threshold;
+                    string.push(match (is_bright(top), is_bright(top + 1)) {
+                        (true, true) => '█',
+                        (true, false) => '▀',
+                        (false, true) => '▄',
+                        (false, false) => ' ',
+                    });
+                }
+                string.push('\n');
+            }
+        }
+        RenderMode::Shaded => {
+            let pixel = |row: usize, col: usize| {
+                let start = (row * cols + col) * channels;
+                &image[start..start + channels]
+            };
+            for top in (0..rows).step_by(2) {
+                for col in 0..cols {
+                    let foreground = palette_color(pixel(top, col));
+                    string.push_str(&foreground_code(foreground));
+                    if top + 1 < rows {
+                        let background = palette_color(pixel(top + 1, col));
+                        string.push_str(&background_code(background));
+                        string.push('▀');
+                    } else {
+                        // Leave the background alone below the last row.
+                        string.push_str(RESET);
+                        string.push_str(&foreground_code(foreground));
+                        string.push('▀');
+                    }
+                }
+                string.push_str(RESET);
+                string.push('\n');
+            }
+        }
+    }
+    string
+}
+
+/// Lay out rendered images side by side, `columns` to a row, with a caption under each
+/// one. The captions may be painted, as they are padded by their visible width.
+pub fn render_grid(cells: &[(String, String)], columns: usize) -> String {
+    let columns = columns.max(1);
+    let gap = "  ";
+    let mut string = String::new();
+    for (row_index, row) in cells.chunks(columns).enumerate() {
+        if row_index > 0 {
+            string.push('\n');
+        }
+        let lines: Vec<Vec<&str>> = row
+            .iter()
+            .map(|(image, _)| image.lines().collect())
+            .collect();
+        let widths: Vec<usize> = zip(row, &lines)
+            .map(|((_, caption), lines)| {
+                lines
+                    .iter()
+                    .map(|line| visible_width(line))
+                    .chain(Some(visible_width(caption)))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let line_count = lines.iter().map(|lines| lines.len()).max().unwrap_or(0);
+
+        let mut push_line = |texts: Vec<&str>| {
+            let line: Vec<String> = zip(texts, &widths)
+                .map(|(text, width)| pad(text, *width))
+                .collect();
+            string.push_str(line.join(gap).trim_end());
+            string.push('\n');
+        };
+        for line_index in 0..line_count {
+            push_line(
+                lines
+                    .iter()
+                    .map(|lines| lines.get(line_index).cloned().unwrap_or(""))
+                    .collect(),
+            );
+        }
+        push_line(row.iter().map(|(_, caption)| caption.as_str()).collect());
+    }
+    string
+}
+
+/// Pad the text with spaces to a visible width.
+fn pad(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(visible_width(text));
+    format!("{}{}", text, " ".repeat(padding))
+}
+
+/// The closest color in the 256-color palette to a grayscale or RGB pixel.
+pub fn palette_color(pixel: &[u8]) -> PaletteColor {
+    let scale = |value: u8, steps: u8| ((value as u16 * (steps as u16 - 1) + 127) / 255) as u8;
+    match *pixel {
+        [red, green, blue] if red != green || green != blue => {
+            PaletteColor(CUBE_START + 36 * scale(red, 6) + 6 * scale(green, 6) + scale(blue, 6))
+        }
+        _ => PaletteColor(GRAY_START + scale(pixel[0], GRAY_COUNT)),
+    }
+}
+
+fn foreground_code(color: PaletteColor) -> String {
+    format!("\x1b[38;5;{}m", color.0)
+}
+
+fn background_code(color: PaletteColor) -> String {
+    format!("\x1b[48;5;{}m", color.0)
+}
+
+/// Wrap the text in the escape codes for a foreground color.
+pub fn paint(text: &str, color: PaletteColor) -> String {
+    format!("{}{}{}", foreground_code(color), text, RESET)
+}
+
+/// The number of characters that the text takes up in the terminal, ignoring any
+/// escape codes.
+pub fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut in_escape = false;
+    for character in text.chars() {
+        match character {
+            '\x1b' => in_escape = true,
+            'm' if in_escape => in_escape = false,
+            _ if in_escape => {}
+            _ => width += 1,
+        }
+    }
+    width
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A 3x2 image, which is taller than it is wide.
+    const TALL: [u8; 6] = [0, 255, 100, 0, 255, 30];
+
+    #[test]
+    fn threshold() {
+        let string = render_image(&TALL, (3, 2), 1, RenderMode::Threshold(50));
+        assert_eq!(string, ".X\nX.\nX.\n");
+        let wide = render_image(&TALL, (2, 3), 1, RenderMode::Threshold(50));
+        assert_eq!(wide, ".XX\n.X.\n");
+    }
+
+    #[test]
+    fn ramp() {
+        let string = render_image(&TALL, (3, 2), 1, RenderMode::Ramp);
+        assert_eq!(string, " @\n- \n@.\n");
+    }
+
+    #[test]
+    fn half_blocks() {
+        // The last row is on its own, so it only uses the top half.
+        let string = render_image(&TALL, (3, 2), 1, RenderMode::HalfBlocks(50));
+        assert_eq!(string, "▄▀\n▀ \n");
+    }
+
+    #[test]
+    fn shaded() {
+        let string = render_image(&[0, 255], (2, 1), 1, RenderMode::Shaded);
+        assert_eq!(string, "\x1b[38;5;232m\x1b[48;5;255m▀\x1b[0m\n");
+        assert_eq!(visible_width(&string), 2, "The newline is counted");
+
+        let red = [255, 0, 0];
+        let string = render_image(&red, (1, 1), 3, RenderMode::Shaded);
+        assert!(string.starts_with("\x1b[38;5;196m"), "{:?}", string);
+        assert_eq!(palette_color(&[128, 128, 128]), PaletteColor(244));
+    }
+
+    #[test]
+    fn grid() {
+        let cells = vec![
+            (String::from("XX\nXX\n"), String::from("7")),
+            (String::from("X\n"), paint("3 wide", PaletteColor::RED)),
+            (String::from("."), String::from("1")),
+        ];
+        let string = render_grid(&cells, 2);
+        assert_eq!(
+            string,
+            format!(
+                "XX  X\nXX\n7   {}\n\n.\n1\n",
+                paint("3 wide", PaletteColor::RED)
+            )
+        );
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(paint("7", PaletteColor::RED), "\x1b[38;5;1m7\x1b[0m");
+        assert_eq!(visible_width(&paint("seven", PaletteColor::RED)), 5);
+        assert_eq!("shaded".parse(), Ok(RenderMode::Shaded));
+        assert!("sepia".parse::<RenderMode>().is_err());
+    }
+}
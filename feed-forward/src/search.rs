@@ -0,0 +1,506 @@
+//! Hyperparameter search. A grid search trains a network for every combination of the
+//! values in a `SearchSpace`, and a random search trains a fixed number of networks
+//! with randomly picked values. Every trial is scored on the same held out validation
+//! split, and the trials are run in parallel on a limited number of threads.
+use crate::image_data::Images;
+use crate::metrics::json_number;
+use crate::network::{Evaluation, Network};
+use crate::normalize::Normalization;
+use crate::random::Random;
+use crate::train::{Optimizer, Trainer, TrainingConfig};
+use std::io;
+use std::sync::mpsc;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::Instant;
+
+/// The values to try for each hyperparameter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchSpace {
+    pub learning_rates: Vec<f64>,
+    /// The (hidden layer count, hidden node count) of the networks.
+    pub layer_sizes: Vec<(usize, usize)>,
+    pub batch_sizes: Vec<usize>,
+    pub optimizers: Vec<Optimizer>,
+    /// The strengths of the L2 regularization. See `TrainingConfig::weight_decay`.
+    pub weight_decays: Vec<f64>,
+}
+
+impl Default for SearchSpace {
+    fn default() -> SearchSpace {
+        SearchSpace {
+            learning_rates: vec![0.1, 0.5, 1.0],
+            layer_sizes: vec![(1, 16), (2, 16)],
+            batch_sizes: vec![16, 32],
+            optimizers: vec![Optimizer::Sgd, Optimizer::Momentum(0.9)],
+            weight_decays: vec![0.0, 0.0001],
+        }
+    }
+}
+
+/// One point in the search space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hyperparameters {
+    pub learning_rate: f64,
+    pub hidden_layer_count: usize,
+    pub hidden_node_count: usize,
+    pub batch_size: usize,
+    pub optimizer: Optimizer,
+    pub weight_decay: f64,
+}
+
+impl SearchSpace {
+    /// Every combination of the values, in a stable order.
+    pub fn grid(&self) -> Vec<Hyperparameters> {
+        let mut grid = Vec::new();
+        for &learning_rate in &self.learning_rates {
+            for &(hidden_layer_count, hidden_node_count) in &self.layer_sizes {
+                for &batch_size in &self.batch_sizes {
+                    for &optimizer in &self.optimizers {
+                        for &weight_decay in &self.weight_decays {
+                            grid.push(Hyperparameters {
+                                learning_rate,
+                                hidden_layer_count,
+                                hidden_node_count,
+                                batch_size,
+                                optimizer,
+                                weight_decay,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    /// Pick `count` random points. The learning rate is drawn log-uniformly from
+    /// between the smallest and largest of the learning rates, as it matters on a
+    /// log scale. The other values are picked from their lists.
+    pub fn random(&self, count: usize, seed: u64) -> Vec<Hyperparameters> {
+        let mut random = Random::new(seed);
+        let min = self
+            .learning_rates
+            .iter()
+            .cloned()
+            .fold(f64::INFINITY, f64::min);
+        let max = self.learning_rates.iter().cloned().fold(0.0, f64::max);
+        (0..count)
+            .map(|_| {
+                let learning_rate = if min > 0.0 && min < max {
+                    random.range(min.ln(), max.ln()).exp()
+                } else {
+                    pick(&self.learning_rates, &mut random)
+                };
+                let (hidden_layer_count, hidden_node_count) = pick(&self.layer_sizes, &mut random);
+                Hyperparameters {
+                    learning_rate,
+                    hidden_layer_count,
+                    hidden_node_count,
+                    batch_size: pick(&self.batch_sizes, &mut random),
+                    optimizer: pick(&self.optimizers, &mut random),
+                    weight_decay: pick(&self.weight_decays, &mut random),
+                }
+            })
+            .collect()
+    }
+}
+
+fn pick<T: Copy>(values: &[T], random: &mut Random) -> T {
+    assert!(
+        !values.is_empty(),
+        "Every hyperparameter needs at least one value."
+    );
+    values[random.below(values.len())]
+}
+
+/// The result of training and scoring one set of hyperparameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trial {
+    /// The position of the hyperparameters in the list that was searched.
+    pub index: usize,
+    pub hyperparameters: Hyperparameters,
+    /// The loss and accuracy on the validation split.
+    pub evaluation: Evaluation,
+    /// Seconds spent training and scoring.
+    pub seconds: f64,
+}
+
+impl Trial {
+    pub fn to_json(&self) -> String {
+        let hyperparameters = &self.hyperparameters;
+        format!(
+            "{{\"trial\":{},\"learning_rate\":{},\"hidden_layers\":{},\"hidden_nodes\":{},\"batch_size\":{},\"optimizer\":\"{}\",\"weight_decay\":{},\"validation_loss\":{},\"validation_accuracy\":{},\"seconds\":{}}}",
+            self.index,
+            json_number(Some(hyperparameters.learning_rate)),
+            hyperparameters.hidden_layer_count,
+            hyperparameters.hidden_node_count,
+            hyperparameters.batch_size,
+            hyperparameters.optimizer.name(),
+            json_number(Some(hyperparameters.weight_decay)),
+            json_number(Some(self.evaluation.loss)),
+            json_number(Some(self.evaluation.accuracy)),
+            json_number(Some(self.seconds))
+        )
+    }
+}
+
+/// How the settings that are not being searched are configured.
+#[derive(Debug, Clone)]
+pub struct Search {
+    /// The epochs, seed and augmentation for every trial. The hyperparameters replace
+    /// the learning rate, batch size, optimizer and weight decay. The trials run at the
+    /// same time, so none of them write checkpoints or metrics, or show the dashboard.
+    pub training: TrainingConfig,
+    pub output_node_count: usize,
+    /// The name of the normalization, which is fitted to the training split.
+    pub normalize: String,
+    /// The fraction of the images that are held out to score the trials.
+    pub validation_fraction: f64,
+    /// The seed for splitting off the validation images.
+    pub seed: u64,
+    /// The most trials to train at once.
+    pub threads: usize,
+}
+
+impl Default for Search {
+    fn default() -> Search {
+        Search {
+            training: TrainingConfig::default(),
+            output_node_count: 10,
+            normalize: String::from("none"),
+            validation_fraction: 0.2,
+            seed: 0,
+            threads: 1,
+        }
+    }
+}
+
+impl Search {
+    /// Split the images into the (training, validation) images, keeping the same
+    /// proportion of each label in both.
+    pub fn split(&self, images: &Images) -> (Images, Images) {
+        let mut splits = images
+            .stratified_split(
+                &[1.0 - self.validation_fraction, self.validation_fraction],
+                self.seed,
+            )
+            .into_iter();
+        let training = splits.next().expect("There is a training split.");
+        let validation = splits.next().expect("There is a validation split.");
+        (training, validation)
+    }
+
+    /// Train a fresh network with the hyperparameters, and score it on the validation
+    /// images.
+    pub fn train_trial(
+        &self,
+        hyperparameters: &Hyperparameters,
+        training: &Images,
+        validation: &Images,
+    ) -> io::Result<Evaluation> {
+        let normalization = Normalization::fit(&self.normalize, training)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Unknown normalization."))?;
+        let mut network = Network::new(
+            training.clone(),
+            hyperparameters.hidden_layer_count,
+            hyperparameters.hidden_node_count,
+            self.output_node_count,
+        );
+        network.normalization = normalization;
+
+        let mut trainer = Trainer::new(TrainingConfig {
+            learning_rate: hyperparameters.learning_rate,
+            batch_size: hyperparameters.batch_size,
+            optimizer: hyperparameters.optimizer,
+            weight_decay: hyperparameters.weight_decay,
+            checkpoint_path: None,
+            metrics_path: None,
+            dashboard: false,
+            ..self.training.clone()
+        });
+        trainer.train(&mut network)?;
+        Ok(network.evaluate(validation))
+    }
+
+    /// Run a trial for each of the hyperparameters, up to `threads` at a time.
+    /// `on_trial` is called with each trial as soon as it finishes, so the results can
+    /// be written out as they come in. The trials are returned in their original
+    /// order. If any trial fails, the first error is returned once the rest finish.
+    pub fn run(
+        &self,
+        trials: &[Hyperparameters],
+        images: &Images,
+        mut on_trial: impl FnMut(&Trial) -> io::Result<()>,
+    ) -> io::Result<Vec<Trial>> {
+        let (training, validation) = self.split(images);
+        let next = Mutex::new(trials.iter().enumerate());
+        let (sender, receiver) = mpsc::channel();
+
+        let mut results: Vec<Option<Trial>> = vec![None; trials.len()];
+        let mut first_error = None;
+        thread::scope(|scope| {
+            for _ in 0..self.threads.clamp(1, trials.len().max(1)) {
+                let sender = sender.clone();
+                let (next, training, validation) = (&next, &training, &validation);
+                scope.spawn(move || loop {
+                    // The lock is only held to take the next trial, which can't panic.
+                    let item = next.lock().unwrap_or_else(PoisonError::into_inner).next();
+                    let (index, hyperparameters) = match item {
+                        Some(item) => item,
+                        None => break,
+                    };
+                    let started = Instant::now();
+                    let result =
+                        self.train_trial(hyperparameters, training, validation)
+                            .map(|evaluation| Trial {
+                                index,
+                                hyperparameters: *hyperparameters,
+                                evaluation,
+                                seconds: started.elapsed().as_secs_f64(),
+                            });
+                    if sender.send(result).is_err() {
+                        break;
+                    }
+                });
+            }
+            // Only the threads hold senders now, so the loop ends once they are done.
+            drop(sender);
+            for result in receiver {
+                match result.and_then(|trial| on_trial(&trial).map(|_| trial)) {
+                    Ok(trial) => {
+                        let index = trial.index;
+                        results[index] = Some(trial);
+                    }
+                    Err(err) => {
+                        first_error.get_or_insert(err);
+                    }
+                }
+            }
+        });
+
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(results.into_iter().flatten().collect()),
+        }
+    }
+}
+
+/// The columns that the table of trials can be sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    /// The highest validation accuracy first.
+    Accuracy,
+    /// The lowest validation loss first.
+    Loss,
+    /// The order that the trials were listed in.
+    Trial,
+    LearningRate,
+    BatchSize,
+    /// The quickest trials first.
+    Time,
+}
+
+impl SortBy {
+    pub fn from_name(name: &str) -> Option<SortBy> {
+        match name {
+            "accuracy" => Some(SortBy::Accuracy),
+            "loss" => Some(SortBy::Loss),
+            "trial" => Some(SortBy::Trial),
+            "learning-rate" => Some(SortBy::LearningRate),
+            "batch-size" => Some(SortBy::BatchSize),
+            "time" => Some(SortBy::Time),
+            _ => None,
+        }
+    }
+}
+
+/// Sort the trials by a column. Ties keep their original order.
+pub fn sort_trials(trials: &mut [Trial], sort_by: SortBy) {
+    match sort_by {
+        SortBy::Accuracy => trials.sort_by(|a, b| {
+            b.evaluation
+                .accuracy
+                .total_cmp(&a.evaluation.accuracy)
+                .then(a.evaluation.loss.total_cmp(&b.evaluation.loss))
+        }),
+        SortBy::Loss => trials.sort_by(|a, b| a.evaluation.loss.total_cmp(&b.evaluation.loss)),
+        SortBy::Trial => trials.sort_by_key(|trial| trial.index),
+        SortBy::LearningRate => trials.sort_by(|a, b| {
+            a.hyperparameters
+                .learning_rate
+                .total_cmp(&b.hyperparameters.learning_rate)
+        }),
+        SortBy::BatchSize => trials.sort_by_key(|trial| trial.hyperparameters.batch_size),
+        SortBy::Time => trials.sort_by(|a, b| a.seconds.total_cmp(&b.seconds)),
+    }
+}
+
+/// Lay out the trials as a table, one per line, in the order they are given.
+pub fn format_table(trials: &[Trial]) -> String {
+    let mut table = format!(
+        "{:>5}  {:>13}  {:>6}  {:>5}  {:>14}  {:>12}  {:>9}  {:>8}  {:>7}\n",
+        "trial",
+        "learning rate",
+        "layers",
+        "batch",
+        "optimizer",
+        "weight decay",
+        "loss",
+        "accuracy",
+        "seconds"
+    );
+    for trial in trials {
+        let hyperparameters = &trial.hyperparameters;
+        table.push_str(&format!(
+            "{:>5}  {:>13.6}  {:>6}  {:>5}  {:>14}  {:>12}  {:>9.5}  {:>7.2}%  {:>7.1}\n",
+            trial.index,
+            hyperparameters.learning_rate,
+            format!(
+                "{}x{}",
+                hyperparameters.hidden_layer_count, hyperparameters.hidden_node_count
+            ),
+            hyperparameters.batch_size,
+            hyperparameters.optimizer.name(),
+            hyperparameters.weight_decay,
+            trial.evaluation.loss,
+            trial.evaluation.accuracy * 100.0,
+            trial.seconds
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cross_validation::test::images;
+
+    fn space() -> SearchSpace {
+        SearchSpace {
+            learning_rates: vec![0.01, 1.0],
+            layer_sizes: vec![(1, 4)],
+            batch_sizes: vec![1, 4],
+            optimizers: vec![Optimizer::Sgd],
+            weight_decays: vec![0.0],
+        }
+    }
+
+    fn search() -> Search {
+        Search {
+            training: TrainingConfig {
+                epochs: 20,
+                ..TrainingConfig::default()
+            },
+            output_node_count: 2,
+            validation_fraction: 0.5,
+            threads: 3,
+            ..Search::default()
+        }
+    }
+
+    fn trial(index: usize, learning_rate: f64, loss: f64, accuracy: f64) -> Trial {
+        Trial {
+            index,
+            hyperparameters: Hyperparameters {
+                learning_rate,
+                ..space().grid()[0]
+            },
+            evaluation: Evaluation { loss, accuracy },
+            seconds: 1.0,
+        }
+    }
+
+    #[test]
+    fn grids() {
+        let grid = SearchSpace::default().grid();
+        assert_eq!(grid.len(), 3 * 2 * 2 * 2 * 2);
+        assert_eq!(grid[0].learning_rate, 0.1);
+        assert_eq!(grid[0].weight_decay, 0.0);
+        assert_eq!(grid[1].weight_decay, 0.0001);
+        assert_eq!(grid.last().unwrap().optimizer, Optimizer::Momentum(0.9));
+    }
+
+    #[test]
+    fn random_search() {
+        let space = space();
+        let trials = space.random(50, 7);
+        assert_eq!(trials, space.random(50, 7), "The trials are seeded");
+        assert_ne!(trials, space.random(50, 8));
+        for trial in &trials {
+            assert!(trial.learning_rate >= 0.01 && trial.learning_rate < 1.0);
+            assert!(space.batch_sizes.contains(&trial.batch_size));
+        }
+        let small = trials
+            .iter()
+            .filter(|trial| trial.learning_rate < 0.1)
+            .count();
+        assert!(
+            small > 10 && small < 40,
+            "The learning rates are spread on a log scale, got {} below 0.1",
+            small
+        );
+    }
+
+    #[test]
+    fn runs_trials_in_parallel() {
+        let grid = space().grid();
+        let mut finished = Vec::new();
+        let trials = search()
+            .run(&grid, &images(), |trial| {
+                finished.push(trial.index);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(trials.len(), 4);
+        let indexes: Vec<usize> = trials.iter().map(|trial| trial.index).collect();
+        assert_eq!(indexes, vec![0, 1, 2, 3], "The trials come back in order");
+        finished.sort_unstable();
+        assert_eq!(finished, indexes);
+        for (trial, hyperparameters) in trials.iter().zip(&grid) {
+            assert_eq!(trial.hyperparameters, *hyperparameters);
+        }
+        let best = trials
+            .iter()
+            .map(|trial| trial.evaluation.accuracy)
+            .fold(0.0, f64::max);
+        assert_eq!(best, 1.0, "The classes are easy to learn, got {:?}", trials);
+
+        let failing = Search {
+            normalize: String::from("unknown"),
+            ..search()
+        };
+        assert!(failing.run(&grid, &images(), |_| Ok(())).is_err());
+    }
+
+    #[test]
+    fn sorts_tables() {
+        let mut trials = vec![
+            trial(0, 0.5, 0.3, 0.8),
+            trial(1, 0.1, 0.2, 0.9),
+            trial(2, 1.0, 0.1, 0.9),
+        ];
+        let order =
+            |trials: &[Trial]| -> Vec<usize> { trials.iter().map(|trial| trial.index).collect() };
+        sort_trials(&mut trials, SortBy::Accuracy);
+        assert_eq!(order(&trials), vec![2, 1, 0], "Ties are broken by the loss");
+        sort_trials(&mut trials, SortBy::LearningRate);
+        assert_eq!(order(&trials), vec![1, 0, 2]);
+        sort_trials(&mut trials, SortBy::Trial);
+        assert_eq!(order(&trials), vec![0, 1, 2]);
+        assert_eq!(SortBy::from_name("loss"), Some(SortBy::Loss));
+        assert_eq!(SortBy::from_name("speed"), None);
+
+        let table = format_table(&trials);
+        assert_eq!(table.lines().count(), 4);
+        assert!(
+            table.lines().nth(1).unwrap().contains("80.00%"),
+            "{}",
+            table
+        );
+        assert_eq!(
+            trials[1].to_json(),
+            "{\"trial\":1,\"learning_rate\":0.1,\"hidden_layers\":1,\"hidden_nodes\":4,\"batch_size\":1,\"optimizer\":\"sgd\",\"weight_decay\":0,\"validation_loss\":0.2,\"validation_accuracy\":0.9,\"seconds\":1}"
+        );
+    }
+}
@@ -0,0 +1,639 @@
+//! Load tabular data from CSV files, so that the same networks can be trained on
+//! problems that aren't images. Each row becomes a 1xN "image" whose pixels are the
+//! encoded features, and the target column becomes the label. Numeric features are
+//! scaled linearly so that the smallest value is 0 and the largest is 255, and
+//! categorical features are one-hot encoded as 0 or 255. This means that the default
+//! `Normalization` maps every feature to the range 0-1.
+use crate::image_data::{Error, Images};
+use std::fs;
+use std::path::Path;
+
+/// Values that are treated as missing, in addition to empty fields.
+const MISSING_VALUES: [&str; 3] = ["NA", "N/A", "?"];
+
+/// A column of the CSV file, either by its name in the header or its zero-based index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    Name(String),
+    Index(usize),
+}
+
+impl Column {
+    /// Parse a column from the command line, where a number is an index.
+    pub fn parse(text: &str) -> Column {
+        match text.parse() {
+            Ok(index) => Column::Index(index),
+            Err(_) => Column::Name(text.to_string()),
+        }
+    }
+}
+
+/// What to do with a row that has missing values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MissingValues {
+    /// Fail to load the file.
+    Error,
+    /// Skip the row.
+    DropRow,
+    /// Use the mean of a numeric column, or the most common value of a categorical
+    /// column.
+    Mean,
+    /// Use this value instead, as if it was in the file.
+    Fill(String),
+}
+
+impl MissingValues {
+    /// Parse the name used on the command line: error, drop, mean, or fill:VALUE.
+    pub fn from_name(name: &str) -> Option<MissingValues> {
+        match name {
+            "error" => Some(MissingValues::Error),
+            "drop" => Some(MissingValues::DropRow),
+            "mean" => Some(MissingValues::Mean),
+            _ => name
+                .strip_prefix("fill:")
+                .map(|value| MissingValues::Fill(value.to_string())),
+        }
+    }
+}
+
+/// How to read a CSV file.
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    /// Whether the first row holds the names of the columns.
+    pub has_header: bool,
+    pub delimiter: char,
+    /// The columns to use as features, or every column but the target if this is None.
+    pub features: Option<Vec<Column>>,
+    /// The column holding the class of each row.
+    pub target: Column,
+    /// Features to one-hot encode. Columns with values that aren't numbers are always
+    /// categorical.
+    pub categorical: Vec<Column>,
+    /// What to do with missing features. Rows with a missing target are always dropped,
+    /// unless this is `MissingValues::Error`.
+    pub missing: MissingValues,
+}
+
+impl Default for CsvOptions {
+    fn default() -> CsvOptions {
+        CsvOptions {
+            has_header: true,
+            delimiter: ',',
+            features: None,
+            target: Column::Index(0),
+            categorical: Vec::new(),
+            missing: MissingValues::Error,
+        }
+    }
+}
+
+/// How a feature column is turned into pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeatureEncoding {
+    /// A single pixel, scaled so that `min` is 0 and `max` is 255.
+    Numeric { min: f64, max: f64 },
+    /// A pixel for every value, where the pixel for the row's value is 255.
+    Categorical { values: Vec<String> },
+}
+
+/// The encoding that was fitted to a CSV file. It can be used to read other files the
+/// same way, such as a test set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableEncoding {
+    /// The index of each feature column and how it is encoded.
+    pub features: Vec<(usize, FeatureEncoding)>,
+    pub target: usize,
+    /// The values of the target column, where a row's label is the index of its value.
+    pub class_names: Vec<String>,
+    /// A name for every pixel, e.g. "age" or "color=red" for a one-hot pixel.
+    pub feature_names: Vec<String>,
+    missing: MissingValues,
+    /// The values used for missing features when filling in with the mean.
+    fill_values: Vec<Option<String>>,
+    has_header: bool,
+    delimiter: char,
+    /// The number of fields in each row, and the names of the columns if there was a
+    /// header, which other files must match.
+    field_count: usize,
+    header: Option<Vec<String>>,
+}
+
+/// A CSV file loaded as images.
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub images: Images,
+    pub encoding: TableEncoding,
+}
+
+impl CsvOptions {
+    /// Read a CSV file, fitting the encoding to its values.
+    pub fn read(&self, path: impl AsRef<Path>) -> Result<Table, Error> {
+        self.parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse the text of a CSV file, fitting the encoding to its values.
+    pub fn parse(&self, text: &str) -> Result<Table, Error> {
+        let (header, records) = split_header(parse_records(text, self.delimiter)?, self.has_header);
+        let field_count = field_count(&header, &records)?;
+        let find = |column: &Column| find_column(column, &header, field_count);
+
+        let target = find(&self.target)?;
+        let feature_columns: Vec<usize> = match self.features {
+            Some(ref columns) => columns.iter().map(find).collect::<Result<_, _>>()?,
+            None => (0..field_count).filter(|index| *index != target).collect(),
+        };
+        if feature_columns.contains(&target) {
+            return Err(Error::Message(
+                "The target column can not also be a feature.",
+            ));
+        }
+        let categorical: Vec<usize> = self
+            .categorical
+            .iter()
+            .map(find)
+            .collect::<Result<_, _>>()?;
+
+        let records = handle_missing(records, target, &feature_columns, &self.missing)?;
+        let mut features = Vec::with_capacity(feature_columns.len());
+        let mut fill_values = Vec::with_capacity(feature_columns.len());
+        let mut feature_names = Vec::new();
+        for &column in &feature_columns {
+            let values: Vec<&str> = records
+                .iter()
+                .map(|record| record[column].as_str())
+                .filter(|value| !is_missing(value))
+                .collect();
+            let name = header
+                .as_ref()
+                .map_or_else(|| column.to_string(), |header| header[column].clone());
+            let numbers: Option<Vec<f64>> = values.iter().map(|value| value.parse().ok()).collect();
+            let encoding = match numbers {
+                Some(numbers) if !categorical.contains(&column) => {
+                    feature_names.push(name);
+                    fill_values.push(Some(
+                        (numbers.iter().sum::<f64>() / numbers.len().max(1) as f64).to_string(),
+                    ));
+                    FeatureEncoding::Numeric {
+                        min: numbers.iter().cloned().fold(f64::INFINITY, f64::min),
+                        max: numbers.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                    }
+                }
+                _ => {
+                    let values = distinct_values(&values);
+                    for value in &values {
+                        feature_names.push(format!("{}={}", name, value));
+                    }
+                    fill_values.push(most_common(&records, column));
+                    FeatureEncoding::Categorical { values }
+                }
+            };
+            features.push((column, encoding));
+        }
+
+        let targets: Vec<&str> = records
+            .iter()
+            .map(|record| record[target].as_str())
+            .collect();
+        let class_names = distinct_values(&targets);
+        if class_names.len() > u8::MAX as usize + 1 {
+            return Err(Error::Message(
+                "The target column has more classes than fit in a label.",
+            ));
+        }
+
+        let encoding = TableEncoding {
+            features,
+            target,
+            class_names,
+            feature_names,
+            missing: self.missing.clone(),
+            fill_values,
+            has_header: self.has_header,
+            delimiter: self.delimiter,
+            field_count,
+            header,
+        };
+        let images = encoding.encode(&records)?;
+        Ok(Table { images, encoding })
+    }
+}
+
+impl TableEncoding {
+    /// The number of pixels in each encoded row.
+    pub fn feature_count(&self) -> usize {
+        self.feature_names.len()
+    }
+
+    /// Read another CSV file with the same columns, encoding it the same way.
+    pub fn read(&self, path: impl AsRef<Path>) -> Result<Images, Error> {
+        self.parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse the text of another CSV file with the same columns.
+    pub fn parse(&self, text: &str) -> Result<Images, Error> {
+        let (header, records) = split_header(parse_records(text, self.delimiter)?, self.has_header);
+        let field_count = field_count(&header, &records)?;
+        // A file without any rows has nothing to check.
+        let is_empty = header.is_none() && records.is_empty();
+        if field_count != self.field_count && !is_empty {
+            return Err(Error::Message(
+                "The CSV file does not have the same number of columns as the fitted file.",
+            ));
+        }
+        if let (Some(header), Some(fitted)) = (&header, &self.header) {
+            if header != fitted {
+                return Err(Error::Message(
+                    "The CSV header does not match the header of the fitted file.",
+                ));
+            }
+        }
+        let feature_columns: Vec<usize> = self.features.iter().map(|(column, _)| *column).collect();
+        let records = handle_missing(records, self.target, &feature_columns, &self.missing)?;
+        self.encode(&records)
+    }
+
+    fn encode(&self, records: &[Vec<String>]) -> Result<Images, Error> {
+        let mut images = Images::new((1, self.feature_count()), Vec::new(), Vec::new());
+        let mut pixels = Vec::with_capacity(self.feature_count());
+        for record in records {
+            pixels.clear();
+            for ((column, encoding), fill_value) in self.features.iter().zip(&self.fill_values) {
+                let mut value = record[*column].as_str();
+                if is_missing(value) {
+                    // Only `MissingValues::Mean` leaves missing values in the records.
+                    value = fill_value.as_deref().unwrap_or(value);
+                }
+                match encoding {
+                    FeatureEncoding::Numeric { min, max } => {
+                        let number: f64 = value.parse().map_err(|_| {
+                            Error::Message("A numeric CSV column has a value that isn't a number.")
+                        })?;
+                        let range = if max > min { max - min } else { 1.0 };
+                        // Values outside of the fitted range are clamped.
+                        pixels
+                            .push(((number - min) / range * 255.0).round().clamp(0.0, 255.0) as u8);
+                    }
+                    FeatureEncoding::Categorical { values } => {
+                        // Values that weren't seen when fitting are all zeros.
+                        pixels.extend(
+                            values
+                                .iter()
+                                .map(|known| if known == value { 255 } else { 0 }),
+                        );
+                    }
+                }
+            }
+            let label = self
+                .class_names
+                .iter()
+                .position(|name| *name == record[self.target])
+                .ok_or(Error::Message(
+                    "A CSV row has a target that isn't a known class.",
+                ))?;
+            images.push(&pixels, label as u8);
+        }
+        Ok(images)
+    }
+}
+
+fn is_missing(value: &str) -> bool {
+    value.is_empty() || MISSING_VALUES.contains(&value)
+}
+
+/// Apply the missing value policy. Missing features are left in the records for
+/// `MissingValues::Mean`, as the means aren't known until the columns are fitted.
+fn handle_missing(
+    records: Vec<Vec<String>>,
+    target: usize,
+    feature_columns: &[usize],
+    missing: &MissingValues,
+) -> Result<Vec<Vec<String>>, Error> {
+    let mut kept = Vec::with_capacity(records.len());
+    for mut record in records {
+        let missing_feature = feature_columns
+            .iter()
+            .any(|column| is_missing(&record[*column]));
+        let missing_target = is_missing(&record[target]);
+        match missing {
+            MissingValues::Error if missing_feature || missing_target => {
+                return Err(Error::Message("A CSV row has a missing value."))
+            }
+            MissingValues::DropRow if missing_feature => continue,
+            _ if missing_target => continue,
+            MissingValues::Fill(value) => {
+                for column in feature_columns {
+                    if is_missing(&record[*column]) {
+                        record[*column] = value.clone();
+                    }
+                }
+            }
+            _ => {}
+        }
+        kept.push(record);
+    }
+    Ok(kept)
+}
+
+/// The distinct values, sorted numerically if they are all numbers, and
+/// alphabetically otherwise.
+fn distinct_values(values: &[&str]) -> Vec<String> {
+    let mut distinct: Vec<String> = Vec::new();
+    for value in values {
+        if !distinct.iter().any(|known| known == value) {
+            distinct.push(value.to_string());
+        }
+    }
+    let numbers: Option<Vec<f64>> = distinct.iter().map(|value| value.parse().ok()).collect();
+    match numbers {
+        Some(numbers) => {
+            let mut pairs: Vec<(f64, String)> = numbers.into_iter().zip(distinct).collect();
+            pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+            pairs.into_iter().map(|(_, value)| value).collect()
+        }
+        None => {
+            distinct.sort();
+            distinct
+        }
+    }
+}
+
+fn most_common(records: &[Vec<String>], column: usize) -> Option<String> {
+    let values: Vec<&str> = records
+        .iter()
+        .map(|record| record[column].as_str())
+        .filter(|value| !is_missing(value))
+        .collect();
+    distinct_values(&values)
+        .into_iter()
+        .max_by_key(|value| values.iter().filter(|other| **other == value).count())
+}
+
+fn split_header(
+    mut records: Vec<Vec<String>>,
+    has_header: bool,
+) -> (Option<Vec<String>>, Vec<Vec<String>>) {
+    if has_header && !records.is_empty() {
+        let header = records.remove(0);
+        (Some(header), records)
+    } else {
+        (None, records)
+    }
+}
+
+/// Check that every row has the same number of fields, and return that number.
+fn field_count(header: &Option<Vec<String>>, records: &[Vec<String>]) -> Result<usize, Error> {
+    let count = header
+        .as_ref()
+        .or_else(|| records.first())
+        .map_or(0, |record| record.len());
+    if records.iter().any(|record| record.len() != count) {
+        return Err(Error::Message(
+            "A CSV row does not have the same number of fields as the first row.",
+        ));
+    }
+    Ok(count)
+}
+
+fn find_column(
+    column: &Column,
+    header: &Option<Vec<String>>,
+    field_count: usize,
+) -> Result<usize, Error> {
+    match column {
+        Column::Index(index) if *index < field_count => Ok(*index),
+        Column::Index(_) => Err(Error::Message("A CSV column index is out of range.")),
+        Column::Name(name) => header
+            .as_ref()
+            .ok_or(Error::Message(
+                "CSV columns can only be named when there is a header.",
+            ))?
+            .iter()
+            .position(|field| field == name)
+            .ok_or(Error::Message("A CSV column name is not in the header.")),
+    }
+}
+
+/// Split CSV text into records of fields. Fields can be quoted to include the
+/// delimiter, newlines, or a quote, which is written twice. Whitespace around unquoted
+/// fields and blank lines are ignored.
+///
+/// https://tools.ietf.org/html/rfc4180
+fn parse_records(text: &str, delimiter: char) -> Result<Vec<Vec<String>>, Error> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+
+    fn end_field(record: &mut Vec<String>, field: &mut String, quoted: &mut bool) {
+        let value = if *quoted {
+            field.clone()
+        } else {
+            field.trim().to_string()
+        };
+        record.push(value);
+        field.clear();
+        *quoted = false;
+    }
+
+    while let Some(character) = chars.next() {
+        if in_quotes {
+            match character {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                _ => field.push(character),
+            }
+            continue;
+        }
+        match character {
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+                in_quotes = true;
+            }
+            '\n' => {
+                end_field(&mut record, &mut field, &mut quoted);
+                if !(record.len() == 1 && record[0].is_empty()) {
+                    records.push(record.clone());
+                }
+                record.clear();
+            }
+            '\r' => {}
+            _ if character == delimiter => end_field(&mut record, &mut field, &mut quoted),
+            _ => field.push(character),
+        }
+    }
+    if in_quotes {
+        return Err(Error::Message("A quoted CSV field is not closed."));
+    }
+    if !record.is_empty() || !field.trim().is_empty() || quoted {
+        end_field(&mut record, &mut field, &mut quoted);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const IRIS: &str = "sepal_length,petal_width,color,species
+5.0,0.2,blue,setosa
+7.0,1.4,red,versicolor
+6.0,,red,versicolor
+5.5,1.0,,setosa
+";
+
+    fn options() -> CsvOptions {
+        CsvOptions {
+            target: Column::Name("species".to_string()),
+            missing: MissingValues::Mean,
+            ..CsvOptions::default()
+        }
+    }
+
+    #[test]
+    fn records() {
+        let records = parse_records("a, \"b,c\"\n\n\"say \"\"hi\"\"\",d\r\ne", ',').unwrap();
+        assert_eq!(
+            records,
+            vec![
+                vec!["a".to_string(), "b,c".to_string()],
+                vec!["say \"hi\"".to_string(), "d".to_string()],
+                vec!["e".to_string()],
+            ]
+        );
+        assert_eq!(parse_records("a;b", ';').unwrap(), vec![vec!["a", "b"]]);
+        assert!(parse_records("\"a,b", ',').is_err());
+    }
+
+    #[test]
+    fn encodes_features() {
+        let table = options().parse(IRIS).unwrap();
+        let encoding = &table.encoding;
+        assert_eq!(encoding.class_names, vec!["setosa", "versicolor"]);
+        assert_eq!(
+            encoding.feature_names,
+            vec!["sepal_length", "petal_width", "color=blue", "color=red"]
+        );
+        assert_eq!(table.images.dimensions, (1, 4));
+        assert_eq!(table.images.len(), 4);
+        assert_eq!(table.images.labels, vec![0, 1, 1, 0]);
+        assert_eq!(table.images.image(0), &[0, 0, 255, 0]);
+        assert_eq!(table.images.image(1), &[255, 255, 0, 255]);
+        // The mean petal width is 0.87, and the most common color is red.
+        assert_eq!(table.images.image(2)[1], 142);
+        assert_eq!(&table.images.image(3)[2..], &[0, 255]);
+    }
+
+    #[test]
+    fn missing_values() {
+        let error = CsvOptions {
+            missing: MissingValues::Error,
+            ..options()
+        };
+        assert!(error.parse(IRIS).is_err());
+        assert_eq!(
+            MissingValues::from_name("error"),
+            Some(MissingValues::Error)
+        );
+        assert_eq!(
+            MissingValues::from_name("fill:0"),
+            Some(MissingValues::Fill("0".to_string()))
+        );
+        assert_eq!(MissingValues::from_name("zero"), None);
+
+        let dropped = CsvOptions {
+            missing: MissingValues::DropRow,
+            ..options()
+        };
+        assert_eq!(dropped.parse(IRIS).unwrap().images.labels, vec![0, 1]);
+
+        let filled = CsvOptions {
+            missing: MissingValues::Fill("0".to_string()),
+            categorical: vec![Column::Name("color".to_string())],
+            ..options()
+        };
+        let table = filled.parse(IRIS).unwrap();
+        assert_eq!(table.images.image(2)[1], 0);
+        assert_eq!(
+            table.encoding.feature_names[2..],
+            ["color=0", "color=blue", "color=red"]
+        );
+
+        let missing_target = options().parse("x,species\n1,a\n2,\n3,b").unwrap();
+        assert_eq!(missing_target.images.labels, vec![0, 1]);
+    }
+
+    #[test]
+    fn selects_columns() {
+        let table = CsvOptions {
+            has_header: false,
+            features: Some(vec![Column::Index(2)]),
+            target: Column::Index(0),
+            ..CsvOptions::default()
+        }
+        .parse("10,x,3\n2,y,1\n10,z,2\n")
+        .unwrap();
+        assert_eq!(
+            table.encoding.class_names,
+            vec!["2", "10"],
+            "Sorted as numbers"
+        );
+        assert_eq!(table.images.labels, vec![1, 0, 1]);
+        assert_eq!(table.images.pixels(), &[255, 0, 128]);
+
+        let name_without_header = CsvOptions {
+            has_header: false,
+            target: Column::Name("species".to_string()),
+            ..CsvOptions::default()
+        };
+        assert!(name_without_header.parse("1,2").is_err());
+        assert!(options().parse("a,species\n1,x\n2").is_err(), "A short row");
+    }
+
+    #[test]
+    fn reuses_encoding() {
+        let table = options().parse(IRIS).unwrap();
+        let test = table
+            .encoding
+            .parse("sepal_length,petal_width,color,species\n8.0,0.2,green,setosa\n")
+            .unwrap();
+        assert_eq!(
+            test.image(0),
+            &[255, 0, 0, 0],
+            "Clamped, with an unknown color"
+        );
+        assert!(table
+            .encoding
+            .parse("sepal_length,petal_width,color,species\n5.0,0.2,red,virginica\n")
+            .is_err());
+
+        for other in [
+            "sepal_length,petal_width,color\n5.0,0.2,red\n",
+            "sepal_length,petal_width,colour,species\n5.0,0.2,red,setosa\n",
+        ]
+        .iter()
+        {
+            match table.encoding.parse(other) {
+                Err(Error::Message(_)) => {}
+                result => panic!("Expected an error for {:?}, got {:?}", other, result),
+            }
+        }
+        let without_header = CsvOptions {
+            has_header: false,
+            ..CsvOptions::default()
+        }
+        .parse("a,1,2\nb,3,4\n")
+        .unwrap();
+        assert!(
+            without_header.encoding.parse("a,1\n").is_err(),
+            "A missing column"
+        );
+        assert_eq!(without_header.encoding.parse("").unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,891 @@
+//! Mini-batch gradient descent for a `Network`. Long training runs can periodically
+//! write out a checkpoint, and then be resumed from it later with the same results
+//! as a run that was never interrupted.
+use crate::augment::Augmentation;
+use crate::autodiff::Tensor;
+use crate::dashboard::Dashboard;
+use crate::image_data::Images;
+use crate::metrics::{Metrics, MetricsFormat, MetricsLog};
+use crate::network::{LayerGradients, Network};
+use crate::random::Random;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::iter::zip;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// The magic number at the start of a checkpoint, "MLCP".
+const CHECKPOINT_MAGIC: u32 = 0x4d4c_4350;
+const CHECKPOINT_VERSION: u32 = 4;
+
+/// How the gradients are turned into a step for the weights and biases.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Optimizer {
+    /// Plain stochastic gradient descent: w = w - η∇C
+    Sgd,
+    /// Gradient descent with momentum, where the value is the decay of the velocity:
+    /// v = μv + ∇C, w = w - ηv
+    Momentum(f64),
+}
+
+impl Optimizer {
+    /// Parse "sgd", or "momentum:F" where F is the decay of the velocity.
+    pub fn from_name(name: &str) -> Option<Optimizer> {
+        match name {
+            "sgd" => Some(Optimizer::Sgd),
+            _ => name
+                .strip_prefix("momentum:")
+                .and_then(|decay| decay.parse().ok())
+                .map(Optimizer::Momentum),
+        }
+    }
+
+    /// The name that `from_name` parses.
+    pub fn name(self) -> String {
+        match self {
+            Optimizer::Sgd => String::from("sgd"),
+            Optimizer::Momentum(decay) => format!("momentum:{}", decay),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrainingConfig {
+    pub epochs: usize,
+    pub batch_size: usize,
+    /// The learning rate, eta.
+    pub learning_rate: f64,
+    pub optimizer: Optimizer,
+    /// The strength of the L2 regularization, lambda. Each step also shrinks the
+    /// weights towards zero by adding λw to their gradient. The biases are left alone.
+    pub weight_decay: f64,
+    /// The seed for shuffling the training images.
+    pub seed: u64,
+    /// Where to write checkpoints. When this is set, a checkpoint is written at the
+    /// end of every epoch.
+    pub checkpoint_path: Option<PathBuf>,
+    /// Also write a checkpoint every N batches.
+    pub checkpoint_every: Option<usize>,
+    /// Where to append the metrics. A record is written at the end of every epoch.
+    pub metrics_path: Option<PathBuf>,
+    pub metrics_format: MetricsFormat,
+    /// Also write a metrics record every N batches.
+    pub metrics_every: Option<usize>,
+    /// Show a live view of the training progress in the terminal.
+    pub dashboard: bool,
+    /// Randomly distort each training image every time it is used.
+    pub augmentation: Option<Augmentation>,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> TrainingConfig {
+        TrainingConfig {
+            epochs: 10,
+            batch_size: 32,
+            learning_rate: 0.5,
+            optimizer: Optimizer::Sgd,
+            weight_decay: 0.0,
+            seed: 0,
+            checkpoint_path: None,
+            checkpoint_every: None,
+            metrics_path: None,
+            metrics_format: MetricsFormat::Csv,
+            metrics_every: None,
+            dashboard: false,
+            augmentation: None,
+        }
+    }
+}
+
+/// Holds all of the state for a training run, so that it can be written out to a
+/// checkpoint and resumed.
+#[derive(Debug)]
+pub struct Trainer {
+    pub config: TrainingConfig,
+    random: Random,
+    /// The optimizer state, with one entry per layer after the input layer. This is
+    /// only used by `Optimizer::Momentum`.
+    velocities: Vec<LayerGradients>,
+    epoch: usize,
+    /// The next batch to train in the current epoch.
+    batch: usize,
+    /// The shuffled order of the images for the current epoch.
+    order: Vec<usize>,
+    /// The sum of the costs in the current epoch so far.
+    epoch_loss: f64,
+    /// The total number of batches that have been trained.
+    step: usize,
+    gradient_norm: f64,
+    /// The seconds of training from before this trainer was resumed.
+    elapsed: f64,
+    started: Option<Instant>,
+    metrics_log: Option<MetricsLog>,
+    dashboard: Option<Dashboard>,
+    /// The metrics at the end of every epoch.
+    pub history: Vec<Metrics>,
+    /// When provided, the validation loss and accuracy are computed at the end of
+    /// every epoch.
+    pub validation: Option<Images>,
+}
+
+impl Trainer {
+    pub fn new(config: TrainingConfig) -> Trainer {
+        let random = Random::new(config.seed);
+        Trainer {
+            config,
+            random,
+            velocities: Vec::new(),
+            epoch: 0,
+            batch: 0,
+            order: Vec::new(),
+            epoch_loss: 0.0,
+            step: 0,
+            gradient_norm: 0.0,
+            elapsed: 0.0,
+            started: None,
+            metrics_log: None,
+            dashboard: None,
+            history: Vec::new(),
+            validation: None,
+        }
+    }
+
+    /// The epoch that is currently being trained, starting at 0.
+    pub fn epoch(&self) -> usize {
+        self.epoch
+    }
+
+    /// The next batch to be trained in the current epoch.
+    pub fn batch(&self) -> usize {
+        self.batch
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.epoch >= self.config.epochs
+    }
+
+    /// Seconds spent training, including the time before a resume.
+    pub fn wall_time(&self) -> f64 {
+        self.elapsed
+            + self
+                .started
+                .map_or(0.0, |started| started.elapsed().as_secs_f64())
+    }
+
+    /// The metrics for the training so far, without any validation results.
+    pub fn current_metrics(&self) -> Metrics {
+        let images_seen = self.batch * self.config.batch_size;
+        Metrics {
+            epoch: self.epoch,
+            step: self.step,
+            train_loss: self.epoch_loss / images_seen.max(1) as f64,
+            validation_loss: None,
+            validation_accuracy: None,
+            learning_rate: self.config.learning_rate,
+            wall_time: self.wall_time(),
+            gradient_norm: self.gradient_norm,
+        }
+    }
+
+    /// Train a single mini-batch on the network's images. Returns false once every
+    /// epoch has been trained.
+    pub fn step(&mut self, network: &mut Network) -> io::Result<bool> {
+        if self.is_finished() {
+            return Ok(false);
+        }
+        let image_count = network.images.len();
+        if image_count == 0 {
+            return Err(invalid_input("There are no images to train on."));
+        }
+        if self.config.batch_size == 0 {
+            return Err(invalid_input("The batch size must be at least 1."));
+        }
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+
+        if self.batch == 0 {
+            self.order = (0..image_count).collect();
+            self.random.shuffle(&mut self.order);
+            self.epoch_loss = 0.0;
+        }
+
+        let start = self.batch * self.config.batch_size;
+        let end = (start + self.config.batch_size).min(image_count);
+        let indexes = &self.order[start..end];
+
+        // Average the gradients over the mini-batch.
+        let mut batch_gradients: Option<Vec<LayerGradients>> = None;
+        for &image_index in indexes {
+            let (cost, gradients) = match self.config.augmentation {
+                Some(ref augmentation) => {
+                    let image = augmentation.apply_channels(
+                        network.images.image(image_index),
+                        network.images.dimensions,
+                        network.images.channels,
+                        &mut self.random,
+                    );
+                    network.image_gradients(&image, network.images.labels[image_index])
+                }
+                None => network.gradients(image_index),
+            };
+            self.epoch_loss += cost;
+            match batch_gradients {
+                Some(ref mut sums) => {
+                    for (sum, gradient) in zip(sums.iter_mut(), &gradients) {
+                        add_in_place(&mut sum.weights, &gradient.weights);
+                        add_in_place(&mut sum.biases, &gradient.biases);
+                    }
+                }
+                None => batch_gradients = Some(gradients),
+            }
+        }
+        let mut gradients = batch_gradients.expect("The batch was empty.");
+        let scale = 1.0 / indexes.len() as f64;
+        for gradient in gradients.iter_mut() {
+            scale_in_place(&mut gradient.weights, scale);
+            scale_in_place(&mut gradient.biases, scale);
+        }
+        if self.config.weight_decay != 0.0 {
+            // The input layer has no weights, so it has no gradients.
+            for (gradient, layer) in zip(gradients.iter_mut(), network.layers.iter().skip(1)) {
+                let mut decay = layer.weights_tensor();
+                scale_in_place(&mut decay, self.config.weight_decay);
+                add_in_place(&mut gradient.weights, &decay);
+            }
+        }
+        self.gradient_norm = gradients
+            .iter()
+            .flat_map(|gradient| gradient.weights.data.iter().chain(&gradient.biases.data))
+            .map(|value| value * value)
+            .sum::<f64>()
+            .sqrt();
+
+        let steps = self.optimizer_steps(gradients);
+        network.update(&steps);
+
+        self.batch += 1;
+        self.step += 1;
+        if end == image_count {
+            let mut metrics = self.current_metrics();
+            metrics.train_loss = self.epoch_loss / image_count as f64;
+            if let Some(ref validation) = self.validation {
+                let evaluation = network.evaluate(validation);
+                metrics.validation_loss = Some(evaluation.loss);
+                metrics.validation_accuracy = Some(evaluation.accuracy);
+            }
+            self.history.push(metrics);
+            self.epoch += 1;
+            self.batch = 0;
+        }
+        Ok(true)
+    }
+
+    /// Turn the averaged gradients into the steps that are subtracted from the weights.
+    fn optimizer_steps(&mut self, mut gradients: Vec<LayerGradients>) -> Vec<LayerGradients> {
+        let learning_rate = self.config.learning_rate;
+        match self.config.optimizer {
+            Optimizer::Sgd => {}
+            Optimizer::Momentum(decay) => {
+                if self.velocities.is_empty() {
+                    self.velocities = gradients;
+                } else {
+                    for (velocity, gradient) in zip(self.velocities.iter_mut(), &gradients) {
+                        scale_in_place(&mut velocity.weights, decay);
+                        add_in_place(&mut velocity.weights, &gradient.weights);
+                        scale_in_place(&mut velocity.biases, decay);
+                        add_in_place(&mut velocity.biases, &gradient.biases);
+                    }
+                }
+                gradients = self.velocities.clone();
+            }
+        }
+        for gradient in gradients.iter_mut() {
+            scale_in_place(&mut gradient.weights, learning_rate);
+            scale_in_place(&mut gradient.biases, learning_rate);
+        }
+        gradients
+    }
+
+    /// Train until every epoch is done, writing out checkpoints and metrics along the
+    /// way if they are configured.
+    pub fn train(&mut self, network: &mut Network) -> io::Result<()> {
+        while self.train_epoch(network)?.is_some() {}
+        Ok(())
+    }
+
+    /// Train the rest of the current epoch, and return its metrics. This returns
+    /// `None` if training is already finished.
+    pub fn train_epoch(&mut self, network: &mut Network) -> io::Result<Option<&Metrics>> {
+        let epoch = self.epoch;
+        while self.epoch == epoch && self.step(network)? {
+            let end_of_epoch = self.batch == 0;
+            let is_due = |every: Option<usize>| match every {
+                Some(every) => every > 0 && self.batch.is_multiple_of(every),
+                None => false,
+            };
+
+            if let Some(path) = self.config.checkpoint_path.clone() {
+                if end_of_epoch || is_due(self.config.checkpoint_every) {
+                    self.save_checkpoint(network, &path)?;
+                }
+            }
+
+            if self.config.metrics_path.is_some()
+                && (end_of_epoch || is_due(self.config.metrics_every))
+            {
+                let metrics = if end_of_epoch {
+                    self.history.last().unwrap().clone()
+                } else {
+                    self.current_metrics()
+                };
+                self.write_metrics(&metrics)?;
+            }
+
+            if self.config.dashboard {
+                let mut dashboard = self.dashboard.take().unwrap_or_default();
+                dashboard.update(self, network.images.len());
+                self.dashboard = Some(dashboard);
+            }
+        }
+        Ok(self.history.last().filter(|metrics| metrics.epoch == epoch))
+    }
+
+    fn write_metrics(&mut self, metrics: &Metrics) -> io::Result<()> {
+        if self.metrics_log.is_none() {
+            if let Some(ref path) = self.config.metrics_path {
+                self.metrics_log = Some(MetricsLog::open(path, self.config.metrics_format)?);
+            }
+        }
+        match self.metrics_log {
+            Some(ref mut log) => log.write(metrics),
+            None => Ok(()),
+        }
+    }
+
+    /// Write the network's weights along with all of the training state. The file is
+    /// written next to the path first and then moved into place, so that an
+    /// interrupted write doesn't clobber the previous checkpoint.
+    pub fn save_checkpoint(&self, network: &Network, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut partial_path = path.as_os_str().to_owned();
+        partial_path.push(".partial");
+        let partial_path = PathBuf::from(partial_path);
+
+        {
+            let mut file = BufWriter::new(File::create(&partial_path)?);
+            file.write_u32::<BigEndian>(CHECKPOINT_MAGIC)?;
+            file.write_u32::<BigEndian>(CHECKPOINT_VERSION)?;
+            network.write_weights(&mut file)?;
+
+            // The settings that change the results, so that a resume can check them.
+            file.write_u64::<BigEndian>(self.config.batch_size as u64)?;
+            file.write_f64::<BigEndian>(self.config.learning_rate)?;
+            write_optimizer(&mut file, self.config.optimizer)?;
+            file.write_f64::<BigEndian>(self.config.weight_decay)?;
+            // The augmentation draws from the same random numbers as the shuffling.
+            match self.config.augmentation {
+                Some(ref augmentation) => {
+                    file.write_u8(1)?;
+                    augmentation.write_to(&mut file)?;
+                }
+                None => file.write_u8(0)?,
+            }
+
+            file.write_u64::<BigEndian>(self.random.state())?;
+            file.write_u64::<BigEndian>(self.epoch as u64)?;
+            file.write_u64::<BigEndian>(self.batch as u64)?;
+            file.write_f64::<BigEndian>(self.epoch_loss)?;
+            file.write_u64::<BigEndian>(self.step as u64)?;
+            file.write_f64::<BigEndian>(self.gradient_norm)?;
+            file.write_f64::<BigEndian>(self.wall_time())?;
+
+            file.write_u64::<BigEndian>(self.order.len() as u64)?;
+            for index in &self.order {
+                file.write_u64::<BigEndian>(*index as u64)?;
+            }
+
+            file.write_u64::<BigEndian>(self.velocities.len() as u64)?;
+            for velocity in &self.velocities {
+                velocity.weights.write_to(&mut file)?;
+                velocity.biases.write_to(&mut file)?;
+            }
+
+            file.write_u64::<BigEndian>(self.history.len() as u64)?;
+            for metrics in &self.history {
+                write_metrics(&mut file, metrics)?;
+            }
+            file.flush()?;
+        }
+
+        fs::rename(&partial_path, path)
+    }
+
+    /// Restore the training state and the network's weights from a checkpoint. The
+    /// network must have the same shape as the one that was checkpointed, and should
+    /// be given the same training images. The batch size, learning rate, optimizer,
+    /// weight decay and augmentation must match the ones that the checkpoint was made
+    /// with.
+    pub fn resume(
+        config: TrainingConfig,
+        path: impl AsRef<Path>,
+        network: &mut Network,
+    ) -> io::Result<Trainer> {
+        let mut file = BufReader::new(File::open(path)?);
+        if file.read_u32::<BigEndian>()? != CHECKPOINT_MAGIC {
+            return Err(invalid_data("The file is not a training checkpoint."));
+        }
+        if file.read_u32::<BigEndian>()? != CHECKPOINT_VERSION {
+            return Err(invalid_data(
+                "The checkpoint is from an unsupported version.",
+            ));
+        }
+        network.read_weights(&mut file)?;
+
+        let batch_size = file.read_u64::<BigEndian>()? as usize;
+        let learning_rate = file.read_f64::<BigEndian>()?;
+        let optimizer = read_optimizer(&mut file)?;
+        let weight_decay = file.read_f64::<BigEndian>()?;
+        let augmentation = match file.read_u8()? {
+            0 => None,
+            1 => Some(Augmentation::read_from(&mut file)?),
+            _ => return Err(invalid_data("The checkpoint's augmentation is not valid.")),
+        };
+        if batch_size != config.batch_size {
+            return Err(invalid_data(format!(
+                "The checkpoint was made with a batch size of {}, not {}.",
+                batch_size, config.batch_size
+            )));
+        }
+        if learning_rate != config.learning_rate {
+            return Err(invalid_data(format!(
+                "The checkpoint was made with a learning rate of {}, not {}.",
+                learning_rate, config.learning_rate
+            )));
+        }
+        if optimizer != config.optimizer {
+            return Err(invalid_data(format!(
+                "The checkpoint was made with the {} optimizer, not {}.",
+                optimizer.name(),
+                config.optimizer.name()
+            )));
+        }
+        if weight_decay != config.weight_decay {
+            return Err(invalid_data(format!(
+                "The checkpoint was made with a weight decay of {}, not {}.",
+                weight_decay, config.weight_decay
+            )));
+        }
+        if augmentation != config.augmentation {
+            return Err(invalid_data(
+                "The checkpoint was made with a different augmentation.",
+            ));
+        }
+
+        let mut trainer = Trainer::new(config);
+        trainer.random = Random::new(file.read_u64::<BigEndian>()?);
+        trainer.epoch = file.read_u64::<BigEndian>()? as usize;
+        trainer.batch = file.read_u64::<BigEndian>()? as usize;
+        trainer.epoch_loss = file.read_f64::<BigEndian>()?;
+        trainer.step = file.read_u64::<BigEndian>()? as usize;
+        trainer.gradient_norm = file.read_f64::<BigEndian>()?;
+        trainer.elapsed = file.read_f64::<BigEndian>()?;
+
+        let order_len = file.read_u64::<BigEndian>()?;
+        for _ in 0..order_len {
+            trainer.order.push(file.read_u64::<BigEndian>()? as usize);
+        }
+        let image_count = network.images.len();
+        if trainer.batch > 0 && trainer.order.len() != image_count {
+            return Err(invalid_data(
+                "The checkpoint was made with a different number of images.",
+            ));
+        }
+        if trainer.order.iter().any(|index| *index >= image_count) {
+            return Err(invalid_data(
+                "The checkpoint's image order is out of range.",
+            ));
+        }
+        if trainer.batch * batch_size >= image_count.max(1) {
+            return Err(invalid_data(
+                "The checkpoint's batch is past the end of the epoch.",
+            ));
+        }
+
+        let velocity_len = file.read_u64::<BigEndian>()?;
+        for _ in 0..velocity_len {
+            trainer.velocities.push(LayerGradients {
+                weights: Tensor::read_from(&mut file)?,
+                biases: Tensor::read_from(&mut file)?,
+            });
+        }
+        // There is either no velocity yet, or one for every layer after the input.
+        if !trainer.velocities.is_empty() {
+            let layers = &network.layers[1..];
+            let matches = trainer.velocities.len() == layers.len()
+                && zip(&trainer.velocities, layers).all(|(velocity, layer)| {
+                    velocity.weights.shape() == layer.weights_tensor().shape()
+                        && velocity.biases.shape() == layer.biases_tensor().shape()
+                });
+            if !matches {
+                return Err(invalid_data(
+                    "The checkpoint's velocities do not match the network.",
+                ));
+            }
+        }
+
+        let history_len = file.read_u64::<BigEndian>()?;
+        for _ in 0..history_len {
+            trainer.history.push(read_metrics(&mut file)?);
+        }
+
+        // Make sure there is no trailing data that was meant for something else.
+        if file.read(&mut [0])? != 0 {
+            return Err(invalid_data("The checkpoint has unexpected trailing data."));
+        }
+
+        Ok(trainer)
+    }
+}
+
+fn write_metrics(writer: &mut impl Write, metrics: &Metrics) -> io::Result<()> {
+    // NaN stands in for the validation results that were not computed.
+    writer.write_u64::<BigEndian>(metrics.epoch as u64)?;
+    writer.write_u64::<BigEndian>(metrics.step as u64)?;
+    writer.write_f64::<BigEndian>(metrics.train_loss)?;
+    writer.write_f64::<BigEndian>(metrics.validation_loss.unwrap_or(f64::NAN))?;
+    writer.write_f64::<BigEndian>(metrics.validation_accuracy.unwrap_or(f64::NAN))?;
+    writer.write_f64::<BigEndian>(metrics.learning_rate)?;
+    writer.write_f64::<BigEndian>(metrics.wall_time)?;
+    writer.write_f64::<BigEndian>(metrics.gradient_norm)
+}
+
+fn read_metrics(reader: &mut impl Read) -> io::Result<Metrics> {
+    let optional = |value: f64| if value.is_nan() { None } else { Some(value) };
+    Ok(Metrics {
+        epoch: reader.read_u64::<BigEndian>()? as usize,
+        step: reader.read_u64::<BigEndian>()? as usize,
+        train_loss: reader.read_f64::<BigEndian>()?,
+        validation_loss: optional(reader.read_f64::<BigEndian>()?),
+        validation_accuracy: optional(reader.read_f64::<BigEndian>()?),
+        learning_rate: reader.read_f64::<BigEndian>()?,
+        wall_time: reader.read_f64::<BigEndian>()?,
+        gradient_norm: reader.read_f64::<BigEndian>()?,
+    })
+}
+
+/// Write the optimizer as a tag, followed by its momentum decay.
+fn write_optimizer(writer: &mut impl Write, optimizer: Optimizer) -> io::Result<()> {
+    let (tag, decay) = match optimizer {
+        Optimizer::Sgd => (0, 0.0),
+        Optimizer::Momentum(decay) => (1, decay),
+    };
+    writer.write_u8(tag)?;
+    writer.write_f64::<BigEndian>(decay)
+}
+
+fn read_optimizer(reader: &mut impl Read) -> io::Result<Optimizer> {
+    let tag = reader.read_u8()?;
+    let decay = reader.read_f64::<BigEndian>()?;
+    match tag {
+        0 => Ok(Optimizer::Sgd),
+        1 => Ok(Optimizer::Momentum(decay)),
+        _ => Err(invalid_data("The checkpoint has an unknown optimizer.")),
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn invalid_input(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn add_in_place(tensor: &mut Tensor, other: &Tensor) {
+    for (a, b) in zip(tensor.data.iter_mut(), &other.data) {
+        *a += b;
+    }
+}
+
+fn scale_in_place(tensor: &mut Tensor, scale: f64) {
+    for value in tensor.data.iter_mut() {
+        *value *= scale;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::augment::Transform;
+    use crate::image_data::Images;
+    use std::env;
+
+    fn network() -> Network {
+        Network::new(
+            Images::from_list(
+                (2, 2),
+                vec![
+                    vec![255, 0, 0, 0],
+                    vec![0, 255, 0, 0],
+                    vec![0, 0, 255, 0],
+                    vec![0, 0, 0, 255],
+                    vec![255, 255, 0, 0],
+                    vec![0, 0, 255, 255],
+                    vec![255, 0, 255, 0],
+                ],
+                vec![0, 1, 0, 1, 0, 1, 0],
+            ),
+            1, // hidden layer count
+            3, // hidden node count
+            2, // output node count
+        )
+    }
+
+    fn config() -> TrainingConfig {
+        TrainingConfig {
+            epochs: 4,
+            batch_size: 3,
+            learning_rate: 1.0,
+            optimizer: Optimizer::Momentum(0.9),
+            seed: 1234,
+            ..TrainingConfig::default()
+        }
+    }
+
+    fn weights(network: &Network) -> Vec<Tensor> {
+        network
+            .layers
+            .iter()
+            .skip(1)
+            .flat_map(|layer| vec![layer.weights_tensor(), layer.biases_tensor()])
+            .collect()
+    }
+
+    #[test]
+    fn training_reduces_loss() {
+        let mut network = network();
+        let mut trainer = Trainer::new(TrainingConfig {
+            epochs: 50,
+            ..config()
+        });
+        trainer.train(&mut network).unwrap();
+
+        assert_eq!(trainer.history.len(), 50);
+        let first = trainer.history.first().unwrap().train_loss;
+        let last = trainer.history.last().unwrap().train_loss;
+        assert!(last < first, "The loss went from {} to {}", first, last);
+    }
+
+    #[test]
+    fn optimizer_names() {
+        for optimizer in [Optimizer::Sgd, Optimizer::Momentum(0.9)].iter() {
+            assert_eq!(Optimizer::from_name(&optimizer.name()), Some(*optimizer));
+        }
+        assert_eq!(Optimizer::from_name("momentum:x"), None);
+        assert_eq!(Optimizer::from_name("adam"), None);
+    }
+
+    #[test]
+    fn weight_decay_shrinks_weights() {
+        let squared_weights = |network: &Network| -> f64 {
+            network
+                .layers
+                .iter()
+                .skip(1)
+                .flat_map(|layer| layer.weights_tensor().data)
+                .map(|weight| weight * weight)
+                .sum()
+        };
+        let initial = network();
+        let mut plain = initial.clone();
+        Trainer::new(config()).train(&mut plain).unwrap();
+        let mut decayed = initial.clone();
+        Trainer::new(TrainingConfig {
+            weight_decay: 0.5,
+            ..config()
+        })
+        .train(&mut decayed)
+        .unwrap();
+        assert!(squared_weights(&decayed) < squared_weights(&plain));
+    }
+
+    #[test]
+    fn resume_matches_uninterrupted_run() {
+        let initial = network();
+
+        let mut uninterrupted = initial.clone();
+        let mut trainer = Trainer::new(config());
+        trainer.train(&mut uninterrupted).unwrap();
+
+        // Stop partway through the second epoch, and resume into a different network.
+        let path = env::temp_dir().join(format!("ml-rs-checkpoint-{}", std::process::id()));
+        let mut interrupted = initial.clone();
+        let mut trainer = Trainer::new(config());
+        for _ in 0..4 {
+            trainer.step(&mut interrupted).unwrap();
+        }
+        assert_eq!((trainer.epoch(), trainer.batch()), (1, 1));
+        trainer.save_checkpoint(&interrupted, &path).unwrap();
+
+        let mut resumed = network();
+        let mut trainer = Trainer::resume(config(), &path, &mut resumed).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!((trainer.epoch(), trainer.batch()), (1, 1));
+        trainer.train(&mut resumed).unwrap();
+
+        assert_eq!(weights(&resumed), weights(&uninterrupted));
+        assert_eq!(trainer.history.len(), 4);
+        assert_eq!(trainer.history.last().unwrap().step, 12);
+    }
+
+    #[test]
+    fn augmented_resume() {
+        let config = || TrainingConfig {
+            augmentation: Some(Augmentation::mnist()),
+            ..config()
+        };
+        let initial = network();
+
+        let mut uninterrupted = initial.clone();
+        Trainer::new(config()).train(&mut uninterrupted).unwrap();
+
+        let path = env::temp_dir().join(format!("ml-rs-augmented-{}", std::process::id()));
+        let mut interrupted = initial.clone();
+        let mut trainer = Trainer::new(config());
+        for _ in 0..5 {
+            trainer.step(&mut interrupted).unwrap();
+        }
+        trainer.save_checkpoint(&interrupted, &path).unwrap();
+        let gentler = TrainingConfig {
+            augmentation: Some(Augmentation::new().then(Transform::Translate { max: 1.0 })),
+            ..config()
+        };
+        assert!(Trainer::resume(gentler, &path, &mut network()).is_err());
+        let mut resumed = network();
+        let mut trainer = Trainer::resume(config(), &path, &mut resumed).unwrap();
+        fs::remove_file(&path).unwrap();
+        trainer.train(&mut resumed).unwrap();
+
+        assert_eq!(
+            weights(&resumed),
+            weights(&uninterrupted),
+            "The augmentation is reproducible"
+        );
+
+        let mut plain = initial.clone();
+        Trainer::new(TrainingConfig {
+            augmentation: None,
+            ..config()
+        })
+        .train(&mut plain)
+        .unwrap();
+        assert_ne!(
+            weights(&plain),
+            weights(&uninterrupted),
+            "The images were distorted"
+        );
+    }
+
+    #[test]
+    fn metrics_log() {
+        let path =
+            env::temp_dir().join(format!("ml-rs-train-metrics-{}.jsonl", std::process::id()));
+        let mut network = network();
+        let mut trainer = Trainer::new(TrainingConfig {
+            metrics_path: Some(path.clone()),
+            metrics_format: MetricsFormat::JsonLines,
+            metrics_every: Some(2),
+            ..config()
+        });
+        trainer.validation = Some(network.images.clone());
+        trainer.train(&mut network).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // There are 3 batches per epoch, so a record is written after the 2nd batch
+        // and at the end of the epoch.
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert!(lines[0].contains("\"validation_loss\":null"));
+        assert!(lines[1].starts_with("{\"epoch\":0,\"step\":3,"));
+        assert!(
+            !lines[1].contains("null"),
+            "The epoch has validation results"
+        );
+
+        let metrics = trainer.history.last().unwrap();
+        assert!(metrics.validation_accuracy.is_some());
+        assert!(metrics.gradient_norm > 0.0);
+    }
+
+    #[test]
+    fn resume_checks_the_settings() {
+        let path = env::temp_dir().join(format!("ml-rs-settings-{}", std::process::id()));
+        let mut trainer = Trainer::new(config());
+        let mut network = network();
+        trainer.step(&mut network).unwrap();
+        trainer.save_checkpoint(&network, &path).unwrap();
+
+        let changes = [
+            TrainingConfig {
+                batch_size: 4,
+                ..config()
+            },
+            TrainingConfig {
+                learning_rate: 0.5,
+                ..config()
+            },
+            TrainingConfig {
+                optimizer: Optimizer::Sgd,
+                ..config()
+            },
+            TrainingConfig {
+                weight_decay: 0.1,
+                ..config()
+            },
+            TrainingConfig {
+                augmentation: Some(Augmentation::mnist()),
+                ..config()
+            },
+        ];
+        for changed in changes.iter() {
+            let result = Trainer::resume(changed.clone(), &path, &mut network.clone());
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+        let mut fewer_images = Network::new(
+            Images::from_list((2, 2), vec![vec![0; 4]; 2], vec![0, 1]),
+            1,
+            3,
+            2,
+        );
+        let result = Trainer::resume(config(), &path, &mut fewer_images);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let mut other_shape = Network::new(network.images.clone(), 1, 4, 2);
+        let result = Trainer::resume(config(), &path, &mut other_shape);
+        assert!(result.is_err());
+
+        assert!(Trainer::resume(config(), &path, &mut network).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_empty_batches() {
+        let mut trainer = Trainer::new(TrainingConfig {
+            batch_size: 0,
+            ..config()
+        });
+        let result = trainer.train(&mut network());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn resume_rejects_other_files() {
+        let path = env::temp_dir().join(format!("ml-rs-not-checkpoint-{}", std::process::id()));
+        fs::write(&path, b"not a checkpoint").unwrap();
+        let result = Trainer::resume(config(), &path, &mut network());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
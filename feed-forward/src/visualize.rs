@@ -0,0 +1,192 @@
+//! Visualize what the network has learned. Every node in the first hidden layer has
+//! one weight per pixel, so its weights can be laid out as an image with the same
+//! dimensions as the input. Bright pixels are positive weights that excite the node,
+//! and dark pixels are negative weights that inhibit it. For color images the weights
+//! of each channel are shown in that color.
+use crate::image_data::{grayscale, write_pgm, write_ppm, ImageData};
+use crate::network::Network;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// The characters used for the terminal, from the most negative to the most positive
+/// weight.
+const RAMP: &[u8] = b" .:-=+*#%@";
+
+/// Get the weights for every node in the first hidden layer as grayscale images.
+/// Each image is scaled so that zero is mid-gray, and the weight with the largest
+/// magnitude is either black or white.
+pub fn weight_images(network: &Network) -> Vec<ImageData> {
+    let layer = network
+        .layers
+        .get(1)
+        .expect("The network has no layers after the input layer.");
+    let weights = layer.weights_tensor();
+    weights
+        .data
+        .chunks(weights.cols.max(1))
+        .take(weights.rows)
+        .map(|node_weights| {
+            let max = node_weights
+                .iter()
+                .fold(0.0f64, |max, weight| max.max(weight.abs()));
+            node_weights
+                .iter()
+                .map(|weight| {
+                    let normalized = if max > 0.0 { weight / max } else { 0.0 };
+                    (127.5 + normalized * 127.5).round() as u8
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Render the weight images as a grid in the terminal, with `columns` images per
+/// row. Each image is captioned with its node index. Color weights are averaged into
+/// grayscale.
+pub fn render_weight_grid(network: &Network, columns: usize) -> String {
+    let (rows, cols) = network.images.dimensions;
+    let images: Vec<ImageData> = weight_images(network)
+        .iter()
+        .map(|image| grayscale(image, network.images.channels))
+        .collect();
+    let columns = columns.max(1);
+    let captions: Vec<String> = (0..images.len())
+        .map(|node_index| format!("node {}", node_index))
+        .collect();
+    // Make sure the captions fit when the images are narrow.
+    let width = captions
+        .iter()
+        .map(|caption| caption.len())
+        .fold(cols, usize::max);
+    let mut string = String::new();
+
+    for (chunk_index, chunk) in images.chunks(columns).enumerate() {
+        for caption in &captions[chunk_index * columns..][..chunk.len()] {
+            string.push_str(&format!("{:<width$} ", caption, width = width));
+        }
+        string.push('\n');
+
+        for row in 0..rows {
+            for image in chunk {
+                for col in 0..cols {
+                    let pixel = image[row * cols + col] as usize;
+                    let index = pixel * (RAMP.len() - 1) / 255;
+                    string.push(RAMP[index] as char);
+                }
+                string.push_str(&" ".repeat(width - cols + 1));
+            }
+            string.push('\n');
+        }
+        string.push('\n');
+    }
+
+    string
+}
+
+/// Write each of the weight images to a PGM file in the directory, or a PPM file for
+/// color images. The directory is created if needed. Returns the paths of the files
+/// that were written.
+pub fn write_weight_images(
+    network: &Network,
+    directory: impl AsRef<Path>,
+) -> io::Result<Vec<PathBuf>> {
+    let directory = directory.as_ref();
+    fs::create_dir_all(directory)?;
+    let mut paths = Vec::new();
+    for (node_index, image) in weight_images(network).iter().enumerate() {
+        let color = network.images.channels == 3;
+        let extension = if color { "ppm" } else { "pgm" };
+        let path = directory.join(format!("node-{:03}.{}", node_index, extension));
+        let mut file = BufWriter::new(File::create(&path)?);
+        if color {
+            write_ppm(&mut file, network.images.dimensions, image)?;
+        } else {
+            write_pgm(&mut file, network.images.dimensions, image)?;
+        }
+        file.flush()?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::image_data::Images;
+    use std::env;
+
+    fn network() -> Network {
+        Network::new(
+            Images::from_list((2, 3), Vec::new(), Vec::new()),
+            1, // hidden layer count
+            5, // hidden node count
+            2, // output node count
+        )
+    }
+
+    #[test]
+    fn scales_weights() {
+        let network = network();
+        let images = weight_images(&network);
+        assert_eq!(images.len(), 5, "There is an image per hidden node");
+
+        let weights = network.layers[1].weights_tensor();
+        for (node, image) in images.iter().enumerate() {
+            assert_eq!(image.len(), 6);
+            let row = &weights.data[node * 6..(node + 1) * 6];
+            let max = row.iter().fold(0.0f64, |max, w| max.max(w.abs()));
+            for (pixel, weight) in image.iter().zip(row) {
+                if weight.abs() == max {
+                    assert!(*pixel == 0 || *pixel == 255, "The largest weight saturates");
+                }
+                assert_eq!(*pixel >= 128, *weight >= 0.0, "Zero is mid-gray");
+            }
+        }
+    }
+
+    #[test]
+    fn grid() {
+        let grid = render_weight_grid(&network(), 3);
+        let lines: Vec<&str> = grid.lines().collect();
+        // Two rows of images, each with a caption, two pixel rows and a blank line.
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[0], "node 0 node 1 node 2 ");
+        assert_eq!(
+            lines[1].len(),
+            3 * 7,
+            "The cells are as wide as the captions"
+        );
+        assert_eq!(lines[4], "node 3 node 4 ");
+    }
+
+    #[test]
+    fn pgm_files() {
+        let directory = env::temp_dir().join(format!("ml-rs-weights-{}", std::process::id()));
+        let paths = write_weight_images(&network(), &directory).unwrap();
+        assert_eq!(paths.len(), 5);
+        let bytes = fs::read(&paths[0]).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert!(bytes.starts_with(b"P5\n3 2\n255\n"));
+        assert_eq!(bytes.len(), 11 + 6);
+    }
+
+    #[test]
+    fn color_files() {
+        let network = Network::new(
+            Images::with_channels((2, 3), 3, Vec::new(), Vec::new()),
+            1, // hidden layer count
+            5, // hidden node count
+            2, // output node count
+        );
+        assert_eq!(render_weight_grid(&network, 5).lines().count(), 4);
+
+        let directory = env::temp_dir().join(format!("ml-rs-color-weights-{}", std::process::id()));
+        let paths = write_weight_images(&network, &directory).unwrap();
+        let bytes = fs::read(&paths[0]).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(paths[0].extension().unwrap(), "ppm");
+        assert!(bytes.starts_with(b"P6\n3 2\n255\n"));
+        assert_eq!(bytes.len(), 11 + 18);
+    }
+}